@@ -20,36 +20,648 @@
 
 ////////////////////////////////////////////////////////////////////////////////
 // Place all imports below:
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
 use std::marker;
+use std::str::FromStr;
 
 ////////////////////////////////////////////////////////////////////////////////
 /// SL1. Addmendums, labeled AX.Y, made to the original SL rule set.
 ///
 /// A1.1
-/// A generic struct representing any target (building, unit, armour, etc).
-/// Every target has a location (in terms of longitude, latitude), and an
-/// elevation. Every target also has an armor value - a value that has to be
+/// A generic trait representing any target (building, bunker, armour, etc).
+/// Every target has a location on the map, tracked by the BattleManager.
+/// Every target also has an armor value - a value that has to be
 /// super seeded for any destructive force to have a chance to inflict damage
 /// on the target. Finally, every target has a health value - how much damage it
 /// can withstand before it is destroyed.
-struct Target {
-    location: Hex,
-    elevation: u8,
+pub trait Target {
+    fn armor(&self) -> u8;
+    fn health(&self) -> u8;
+    /// Inflicts an attack of the given strength on the target. Only the
+    /// strength in excess of the armor is taken as damage.
+    fn take_damage(&mut self, strength: u8) -> Damage;
+}
+
+/// The effect an attack had on a target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Damage {
+    // The attack did not get through the armor.
+    Resisted,
+    // The target took damage, and has the given health left.
+    Damaged(u8),
+    Destroyed,
+}
+
+/// Applies damage to the health of a target with the given armor.
+fn inflict(armor: u8, health: &mut u8, strength: u8) -> Damage {
+    let damage = strength.saturating_sub(armor);
+    if damage == 0 {
+        return Damage::Resisted;
+    }
+    *health = health.saturating_sub(damage);
+    match *health {
+        0 => Damage::Destroyed,
+        left => Damage::Damaged(left),
+    }
+}
+
+/// A1.2 Structures are the targets that are part of the map itself. A
+/// destroyed structure changes the terrain of its hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StructureKind {
+    Building,
+    Bunker,
+    // A bridge carries the terrain of its hex across water.
+    Bridge,
+}
+
+#[derive(Debug, Clone)]
+struct Structure {
+    kind: StructureKind,
+    location: HexId,
     armor: u8,
     health: u8,
 }
 
+impl Structure {
+    fn new(
+        kind: StructureKind,
+        location: HexId,
+        armor: u8,
+        health: u8,
+    ) -> Structure {
+        Structure { kind, location, armor, health }
+    }
+
+    /// Returns the terrain left behind in the hex of the destroyed structure.
+    /// Buildings and bunkers collapse into rubble, while a collapsed bridge
+    /// leaves nothing but the water it spanned.
+    fn ruins(&self, terrain: &[Terrain]) -> Vec<Terrain> {
+        if self.kind == StructureKind::Bridge {
+            return vec![Terrain::Water];
+        }
+        let mut ruins: Vec<Terrain> = terrain
+            .iter()
+            .filter(|t| {
+                !matches!(
+                    t,
+                    Terrain::EnterBuilding
+                        | Terrain::WithinBuilding
+                        | Terrain::Bunker
+                )
+            })
+            .copied()
+            .collect();
+        ruins.push(Terrain::Rubble);
+        ruins
+    }
+}
+
+impl Target for Structure {
+    fn armor(&self) -> u8 {
+        self.armor
+    }
+
+    fn health(&self) -> u8 {
+        self.health
+    }
+
+    fn take_damage(&mut self, strength: u8) -> Damage {
+        inflict(self.armor, &mut self.health, strength)
+    }
+}
+
+/// Identifies a target on the battlefield: a structure by its hex, and a
+/// vehicle by its `ars`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TargetId {
+    Structure(HexId),
+    Vehicle(Ipv4Addr),
+}
+
+impl BattleManager {
+    /// Places a structure on the map.
+    fn raise_structure(
+        &mut self,
+        structure: Structure,
+    ) -> Result<(), RuleBreak> {
+        self.map.hex(&structure.location).ok_or(RuleBreak::E0002)?;
+        self.structures.insert(structure.location, structure);
+        Ok(())
+    }
+
+    fn target_mut(&mut self, target: &TargetId) -> Option<&mut dyn Target> {
+        match target {
+            TargetId::Structure(hex) => {
+                self.structures.get_mut(hex).map(|s| s as &mut dyn Target)
+            }
+            TargetId::Vehicle(ars) => {
+                if !self.locations.contains_key(ars) {
+                    return None;
+                }
+                self.vehicles.get_mut(ars).map(|v| v as &mut dyn Target)
+            }
+        }
+    }
+
+    /// A1.1 Attacks a target with the given strength. A destroyed structure
+    /// collapses, and every unit in its hex must pass a morale check to
+    /// survive the collapse. A destroyed vehicle is eliminated.
+    fn damage(
+        &mut self,
+        target: TargetId,
+        strength: u8,
+    ) -> Result<Damage, RuleBreak> {
+        let damage = self
+            .target_mut(&target)
+            .ok_or(RuleBreak::A1100)?
+            .take_damage(strength);
+        match (damage, target) {
+            (Damage::Damaged(health), _) => {
+                self.events.push(Event::Damaged { target, health });
+            }
+            (Damage::Destroyed, TargetId::Structure(hex)) => {
+                let structure = self.structures.remove(&hex);
+                if let (Some(structure), Some(hex)) =
+                    (structure, self.map.hex_mut(&hex))
+                {
+                    hex.terrain = structure.ruins(&hex.terrain);
+                }
+                self.events.push(Event::Destroyed(target));
+                for ars in self.units_in(&hex) {
+                    self.morale_check(ars, 0);
+                }
+            }
+            (Damage::Destroyed, TargetId::Vehicle(ars)) => {
+                self.events.push(Event::Destroyed(target));
+                self.destroy_vehicle(ars);
+            }
+            _ => {}
+        }
+        Ok(damage)
+    }
+}
+
 /// A struct reponsible for tracking relevant events on the battlefield.
-struct BattleManager {}
+///
+/// Instead of each game element keeping track of its own state, the
+/// BattleManager tracks the state of every unit on the battlefield in maps
+/// keyed by the unit's `ars`. Ordered maps are used so that every iteration
+/// over the battlefield, and thereby every dice roll, happens in the same
+/// order each time a battle is played out.
+#[derive(Debug, Clone)]
+struct BattleManager {
+    map: Map,
+    dice: Dice,
+    phase: Phase,
+    // The side that is Player::Attacker during the current player turn.
+    attacker: Side,
+    squads: BTreeMap<Ipv4Addr, Squad<Unphased>>,
+    leaders: BTreeMap<Ipv4Addr, Leader>,
+    vehicles: BTreeMap<Ipv4Addr, Vehicle>,
+    // A1.2 Structures standing on the map.
+    structures: BTreeMap<HexId, Structure>,
+    // A5.8 Minefields laid on the map, both hidden and revealed.
+    minefields: BTreeMap<HexId, Minefield>,
+    // A7.6 Off-board artillery modules available to each side.
+    artillery: BTreeMap<u32, ArtilleryModule>,
+    // A5.9 Units that exited the map, mapped to the edge they left by.
+    exited: BTreeMap<Ipv4Addr, Edge>,
+    // A21.6 The side in control of each hex, and the hexes of every building
+    // that spans more than one hex.
+    control: BTreeMap<HexId, Side>,
+    buildings: BTreeMap<u32, Vec<HexId>>,
+    // A21.7 Reinforcements held off board, mapped to the hexes they may
+    // enter the map through once released.
+    offboard: BTreeMap<Ipv4Addr, Vec<HexId>>,
+    sides: BTreeMap<Ipv4Addr, Side>,
+    locations: BTreeMap<Ipv4Addr, HexId>,
+    conditions: BTreeMap<Ipv4Addr, Condition>,
+    markers: BTreeMap<Ipv4Addr, Vec<Marker>>,
+    weapons: BTreeMap<u32, SupportWeapon>,
+    // Support weapons mapped to the unit that carries them.
+    carriers: BTreeMap<u32, Ipv4Addr>,
+    // Support weapons left behind in a hex, without anyone carrying them.
+    abandoned: BTreeMap<u32, HexId>,
+    // SL2.7 The side each support weapon belongs to.
+    owners: BTreeMap<u32, Side>,
+    // SL18.1 Weapons mounted in vehicles.
+    mounted: BTreeSet<u32>,
+    malfunctioned: BTreeSet<u32>,
+    // A4.71 Demolition charges placed in a hex, waiting to be detonated.
+    charges: BTreeMap<u32, (Ipv4Addr, HexId)>,
+    // SL5.75 The families of support weapons each unit has fired during the
+    // current phase.
+    weapon_types_fired: BTreeMap<Ipv4Addr, BTreeSet<WeaponFamily>>,
+    // MF spent by each unit during the current Phase::Movement.
+    mf_spent: BTreeMap<Ipv4Addr, u8>,
+    // SL5.74 The most portage points each unit carried while moving during
+    // the current player turn.
+    moved_portage: BTreeMap<Ipv4Addr, u8>,
+    // SL5.44 Squads mapped to the leader they move in the company of.
+    escorts: BTreeMap<Ipv4Addr, Ipv4Addr>,
+    // A18.3 The hexside that the hull, and the turret, of each vehicle faces.
+    facings: BTreeMap<Ipv4Addr, Facing>,
+    turrets: BTreeMap<Ipv4Addr, Facing>,
+    // A18.4 Passengers mapped to the vehicle they ride in.
+    passengers: BTreeMap<Ipv4Addr, Ipv4Addr>,
+    stacking_limit: StackingLimit,
+    events: Vec<Event>,
+    // The snapshots that orders are taken back with, or None if undo is
+    // turned off.
+    history: Option<History>,
+}
+
+impl BattleManager {
+    fn new(map: Map, attacker: Side, seed: u64) -> BattleManager {
+        BattleManager {
+            map,
+            dice: Dice::new(seed),
+            phase: Phase::Rally,
+            attacker,
+            squads: BTreeMap::new(),
+            leaders: BTreeMap::new(),
+            vehicles: BTreeMap::new(),
+            structures: BTreeMap::new(),
+            minefields: BTreeMap::new(),
+            artillery: BTreeMap::new(),
+            exited: BTreeMap::new(),
+            control: BTreeMap::new(),
+            buildings: BTreeMap::new(),
+            offboard: BTreeMap::new(),
+            sides: BTreeMap::new(),
+            locations: BTreeMap::new(),
+            conditions: BTreeMap::new(),
+            markers: BTreeMap::new(),
+            weapons: BTreeMap::new(),
+            carriers: BTreeMap::new(),
+            abandoned: BTreeMap::new(),
+            owners: BTreeMap::new(),
+            mounted: BTreeSet::new(),
+            malfunctioned: BTreeSet::new(),
+            charges: BTreeMap::new(),
+            weapon_types_fired: BTreeMap::new(),
+            mf_spent: BTreeMap::new(),
+            moved_portage: BTreeMap::new(),
+            escorts: BTreeMap::new(),
+            facings: BTreeMap::new(),
+            turrets: BTreeMap::new(),
+            passengers: BTreeMap::new(),
+            stacking_limit: StackingLimit::default(),
+            events: Vec::new(),
+            history: None,
+        }
+    }
+
+    /// Places a squad belonging to `side` in the given hex.
+    fn deploy_squad(
+        &mut self,
+        side: Side,
+        squad: Squad<Unphased>,
+        hex: HexId,
+    ) -> Result<(), RuleBreak> {
+        self.map.hex(&hex).ok_or(RuleBreak::E0002)?;
+        let ars = squad.ars;
+        self.squads.insert(ars, squad);
+        self.place(ars, side, hex);
+        Ok(())
+    }
+
+    /// Places a leader belonging to `side` in the given hex.
+    fn deploy_leader(
+        &mut self,
+        side: Side,
+        leader: Leader,
+        hex: HexId,
+    ) -> Result<(), RuleBreak> {
+        self.map.hex(&hex).ok_or(RuleBreak::E0002)?;
+        let ars = leader.ars;
+        self.leaders.insert(ars, leader);
+        self.place(ars, side, hex);
+        Ok(())
+    }
+
+    fn place(&mut self, ars: Ipv4Addr, side: Side, hex: HexId) {
+        self.sides.insert(ars, side);
+        self.locations.insert(ars, hex);
+        self.conditions.insert(ars, Condition::Composed);
+        self.markers.insert(ars, Vec::new());
+    }
+
+    /// Hands a support weapon to the unit that is going to operate it.
+    fn issue_support_weapon(
+        &mut self,
+        id: u32,
+        weapon: SupportWeapon,
+        carrier: Ipv4Addr,
+    ) -> Result<(), RuleBreak> {
+        if !self.offboard.contains_key(&carrier) {
+            self.unit(&carrier).ok_or(RuleBreak::E0001)?;
+        }
+        if self.weapons.contains_key(&id) {
+            return Err(RuleBreak::E0004);
+        }
+        self.weapons.insert(id, weapon);
+        self.owners.insert(id, self.sides[&carrier]);
+        self.carriers.insert(id, carrier);
+        Ok(())
+    }
+
+    /// Leaves a support weapon, belonging to `owner`, abandoned in a hex.
+    fn leave_support_weapon(
+        &mut self,
+        id: u32,
+        weapon: SupportWeapon,
+        owner: Side,
+        hex: HexId,
+    ) -> Result<(), RuleBreak> {
+        self.map.hex(&hex).ok_or(RuleBreak::E0002)?;
+        if self.weapons.contains_key(&id) {
+            return Err(RuleBreak::E0004);
+        }
+        self.weapons.insert(id, weapon);
+        self.owners.insert(id, owner);
+        self.abandoned.insert(id, hex);
+        Ok(())
+    }
+
+    /// Returns what kind of unit the `ars` identifies, if it is on the
+    /// battlefield.
+    fn unit(&self, ars: &Ipv4Addr) -> Option<Unit> {
+        if !self.locations.contains_key(ars) {
+            None
+        } else if self.squads.contains_key(ars) {
+            Some(Unit::Squad)
+        } else if self.leaders.contains_key(ars) {
+            Some(Unit::Leader)
+        } else if self.vehicles.contains_key(ars) {
+            Some(Unit::Armour)
+        } else {
+            None
+        }
+    }
+
+    /// All units, on either side, located in the given hex.
+    fn units_in(&self, hex: &HexId) -> Vec<Ipv4Addr> {
+        self.locations
+            .iter()
+            .filter(|(_, location)| *location == hex)
+            .map(|(ars, _)| *ars)
+            .collect()
+    }
+
+    fn morale(&self, ars: &Ipv4Addr) -> u8 {
+        match self.unit(ars) {
+            Some(Unit::Squad) => self.squads[ars].morale,
+            Some(Unit::Leader) => self.leaders[ars].morale,
+            Some(Unit::Armour) => self.vehicles[ars].morale,
+            _ => 0,
+        }
+    }
+
+    fn broken(&self, ars: &Ipv4Addr) -> bool {
+        self.conditions.get(ars) == Some(&Condition::Broken)
+    }
+
+    fn has_marker(&self, ars: &Ipv4Addr, marker: Marker) -> bool {
+        self.markers.get(ars).is_some_and(|m| m.contains(&marker))
+    }
+
+    fn add_marker(&mut self, ars: Ipv4Addr, marker: Marker) {
+        let markers = self.markers.entry(ars).or_default();
+        if !markers.contains(&marker) {
+            markers.push(marker);
+        }
+    }
+
+    fn remove_marker(&mut self, marker: Marker) {
+        for markers in self.markers.values_mut() {
+            markers.retain(|m| *m != marker);
+        }
+    }
+
+    /// SL2.6 Returns the leader whose command a unit is under, that is the
+    /// non-broken leader with the best leadership stacked in the same hex.
+    /// A leader is never under his own command.
+    fn led_by(&self, ars: &Ipv4Addr) -> Option<Ipv4Addr> {
+        let hex = self.locations.get(ars)?;
+        let side = self.sides.get(ars)?;
+        self.units_in(hex)
+            .into_iter()
+            .filter(|l| l != ars && self.sides[l] == *side && !self.broken(l))
+            .filter_map(|l| self.leaders.get(&l))
+            .min_by_key(|leader| leader.leadership)
+            .map(|leader| leader.ars)
+    }
+
+    /// Returns the `Player` role the side has in the current player turn.
+    fn player(&self, side: Side) -> Player {
+        if side == self.attacker {
+            Player::Attacker
+        } else {
+            Player::Defender
+        }
+    }
+
+    /// Returns whether the side gives orders in the current phase. Both
+    /// sides rally, only the defender fires in Phase::DefensiveFire, and the
+    /// attacker acts in every other phase but close combat.
+    fn acting(&self, side: Side) -> bool {
+        match self.phase {
+            Phase::Rally => true,
+            Phase::DefensiveFire => self.player(side) == Player::Defender,
+            Phase::CloseCombat => false,
+            _ => self.player(side) == Player::Attacker,
+        }
+    }
+
+    /// SL2.4 A unit that fails a morale check breaks. A unit that is already
+    /// broken, and fails yet another morale check, is eliminated.
+    fn morale_check(&mut self, ars: Ipv4Addr, modifier: i8) -> FireOutcome {
+        let roll = self.dice.roll();
+        let leadership =
+            self.led_by(&ars).map_or(0, |l| self.leaders[&l].leadership);
+        let total = roll.total() as i8 + modifier + leadership;
+        if total <= self.morale(&ars) as i8 {
+            FireOutcome::Unaffected
+        } else if self.broken(&ars) {
+            self.eliminate(ars);
+            FireOutcome::Eliminated
+        } else {
+            self.conditions.insert(ars, Condition::Broken);
+            self.events.push(Event::Broken(ars));
+            FireOutcome::Broken
+        }
+    }
+
+    /// Removes a unit from the battlefield. Any support weapons it carried
+    /// are left behind in its hex, except for the weapons mounted in a
+    /// vehicle, which are lost along with it. The passengers of a vehicle
+    /// are left behind in the hex as well.
+    fn eliminate(&mut self, ars: Ipv4Addr) {
+        if let Some(hex) = self.locations.remove(&ars) {
+            let carried: Vec<u32> = self
+                .carriers
+                .iter()
+                .filter(|(_, carrier)| **carrier == ars)
+                .map(|(id, _)| *id)
+                .collect();
+            for id in carried {
+                self.carriers.remove(&id);
+                if !self.mounted.contains(&id) {
+                    self.abandoned.insert(id, hex);
+                }
+            }
+        }
+        self.passengers
+            .retain(|passenger, vehicle| *passenger != ars && *vehicle != ars);
+        self.events.push(Event::Eliminated(ars));
+    }
+}
 
-/// The ScenarioManager is reponsible for tracking scenrio relevant events on
-/// the battlefield. TODO: give examples of scenerio events.
-struct ScenarioManager {}
+/// Everything of relevance that happens on the battlefield is recorded by the
+/// BattleManager as an event.
+#[derive(Debug, Clone, PartialEq)]
+enum Event {
+    PhaseChanged(Phase),
+    Moved {
+        units: Vec<Ipv4Addr>,
+        path: Vec<HexId>,
+    },
+    Routed {
+        ars: Ipv4Addr,
+        path: Vec<HexId>,
+    },
+    Advanced {
+        units: Vec<Ipv4Addr>,
+        to: HexId,
+    },
+    PickedUp {
+        ars: Ipv4Addr,
+        weapon: u32,
+    },
+    Captured {
+        ars: Ipv4Addr,
+        weapon: u32,
+    },
+    Embarked {
+        ars: Ipv4Addr,
+        vehicle: Ipv4Addr,
+    },
+    Disembarked {
+        ars: Ipv4Addr,
+        vehicle: Ipv4Addr,
+    },
+    TurretRotated {
+        ars: Ipv4Addr,
+        facing: Facing,
+    },
+    Damaged {
+        target: TargetId,
+        health: u8,
+    },
+    Destroyed(TargetId),
+    ChargePlaced {
+        ars: Ipv4Addr,
+        weapon: u32,
+        hex: HexId,
+    },
+    Detonated {
+        weapon: u32,
+        hex: HexId,
+        roll: DiceRoll,
+        result: FireResult,
+    },
+    Expended(u32),
+    FireMission {
+        module: u32,
+        target: HexId,
+        contact: DiceRoll,
+        access: Option<DiceRoll>,
+    },
+    Blast {
+        hex: HexId,
+        roll: DiceRoll,
+        result: FireResult,
+    },
+    Shellholed(HexId),
+    SmokeGrenade {
+        ars: Ipv4Addr,
+        hex: HexId,
+        roll: DiceRoll,
+    },
+    SmokePlaced(HexId),
+    SmokeDispersed(HexId),
+    SmokeCleared(HexId),
+    MineAttack {
+        hex: HexId,
+        units: Vec<Ipv4Addr>,
+        roll: DiceRoll,
+    },
+    MinefieldRevealed(HexId),
+    Ablaze(HexId),
+    BurnedOut(HexId),
+    AntiTankFire {
+        ars: Ipv4Addr,
+        weapon: u32,
+        target: Ipv4Addr,
+        roll: DiceRoll,
+        result: ArmorResult,
+    },
+    Abandoned {
+        ars: Ipv4Addr,
+        weapon: u32,
+        hex: HexId,
+    },
+    Transferred {
+        weapon: u32,
+        from: Ipv4Addr,
+        to: Ipv4Addr,
+    },
+    Fired {
+        members: Vec<Ipv4Addr>,
+        weapons: Vec<u32>,
+        target: HexId,
+        roll: DiceRoll,
+    },
+    Broken(Ipv4Addr),
+    Eliminated(Ipv4Addr),
+    Malfunctioned(u32),
+    Rally {
+        ars: Ipv4Addr,
+        roll: DiceRoll,
+        rallied: bool,
+    },
+    Exited {
+        units: Vec<Ipv4Addr>,
+        edge: Edge,
+    },
+    ControlChanged {
+        hex: HexId,
+        side: Side,
+    },
+    BuildingControlled {
+        building: u32,
+        side: Side,
+    },
+    Reinforcements {
+        units: Vec<Ipv4Addr>,
+        roll: Option<DiceRoll>,
+        arrived: bool,
+    },
+    Entered {
+        units: Vec<Ipv4Addr>,
+        hex: HexId,
+    },
+    GameTurn(u8),
+    GameOver(GameOver),
+}
 
 /// Marker States
 // A1.2
 // The state of a non-broken combat unit at the start of a new Game Turn.
+#[derive(Debug, Clone)]
 struct Unphased;
 // SL5.1
 // Any combat unit that moved during Phase::PrepFire of the current GameTurn.
@@ -60,6 +672,51 @@ struct PrepFired;
 // TODO: what types of support weapons are there?
 struct FiredSupportWeapon;
 
+/// A1.3 Dice
+/// All chance on the battlefield is resolved by rolling two six-sided dice,
+/// one white and one coloured. Most rules only care about the total of the
+/// two dice, but some rules single out the coloured die.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DiceRoll {
+    white: u8,
+    coloured: u8,
+}
+
+impl DiceRoll {
+    fn total(&self) -> u8 {
+        self.white + self.coloured
+    }
+}
+
+/// The dice are a seeded pseudo random number generator (SplitMix64), which
+/// makes it possible to play out the exact same battle twice.
+#[derive(Debug, Clone)]
+struct Dice {
+    state: u64,
+    // The number of rolls made since the dice were seeded.
+    rolls: u64,
+}
+
+impl Dice {
+    fn new(seed: u64) -> Dice {
+        Dice { state: seed, rolls: 0 }
+    }
+
+    fn die(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z % 6) as u8 + 1
+    }
+
+    fn roll(&mut self) -> DiceRoll {
+        self.rolls += 1;
+        DiceRoll { white: self.die(), coloured: self.die() }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 /// SL2. Combat Units
 ///
@@ -68,7 +725,7 @@ struct FiredSupportWeapon;
 //  TODO: consider weather to treat Armour crews and AT Gun crews as units.
 /// Unit is a Generic Type representing any unit (leader, squad, armour crew,
 /// Anti Tank Gun crew) on the battlefield.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Unit {
     // TODO: _consider: Are Armour and ATGun treated differently in terms of
     // PrepFire and limitations to Movement?
@@ -104,7 +761,7 @@ impl Unit {
 /// 'breaking down psychologically and fleeing'. A broken unit will remain
 /// broken until it has received successful treatment by non-broken personel.
 use std::net::Ipv4Addr;
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Squad<State> {
     // The `ars` is an identifying value, based on the United States Army
     // Regimental System (USARS) - an organizational and classification system
@@ -140,26 +797,37 @@ struct Squad<State> {
                                         // condition: Condition,
 }
 
+impl<State> Squad<State> {
+    fn new(
+        ars: Ipv4Addr,
+        firepower: u8,
+        range: u8,
+        morale: u8,
+    ) -> Squad<State> {
+        Squad { ars, firepower, range, morale, _state: marker::PhantomData }
+    }
+}
+
 impl Squad<Unphased> {
     fn has_moved(&self) -> bool {
         todo!()
     }
-    fn stacked_with_leader(&self) -> bool {
-        todo!()
+
+    /// Returns true if a non-broken leader of the same side is stacked in the
+    /// same hex as the squad.
+    fn stacked_with_leader(&self, bm: &BattleManager) -> bool {
+        bm.led_by(&self.ars).is_some()
     }
 }
 
 // SL2.6 Leadership affects unit performance
 impl Squad<Unphased> {
-    // TODO: modifier when calculating `unit To Hit` or `unit save morale`.
-    fn get_leadership_modifier(&self) -> u8 {
-        todo!()
-        /*
-        match self.led_by()? {
+    // The modifier used when calculating `unit To Hit` or `unit save morale`.
+    fn get_leadership_modifier(&self, bm: &BattleManager) -> i8 {
+        match bm.led_by(&self.ars) {
             None => 0,
-            Some => self.led_by().leadership(),
+            Some(leader) => bm.leaders[&leader].leadership,
         }
-        */
     }
 }
 
@@ -169,16 +837,16 @@ impl Squad<Unphased> {
 /// The leadership number, usually negative, is added as a modifier to any
 /// morale or firepower test performed by a unit under the leader's command.
 ///
+#[derive(Debug, Clone)]
 struct Leader {
+    pub ars: Ipv4Addr,
     identity: String,
-    pub leadership: u8,
+    pub leadership: i8,
     // Morale: a rating of the leader's ability to withstand combat stress
     // before breaking down psychologically, and fleeing'.
     // A broken leader is non-operational until receiving treatment by
-    // non-broken personel.
-    //
-    // TODO: Using illegal states, make sure that leadership modifier to units
-    // is only applicable if the leader himself is non-broken.
+    // non-broken personel. The BattleManager makes sure that the leadership
+    // modifier is only applied to units stacked with a non-broken leader.
     morale: u8,
     // TODO: _WIP Ideas for fields to add:
     // Competence: a rating of a leader's tactical competence.
@@ -187,6 +855,17 @@ struct Leader {
     // condition: Condition,
 }
 
+impl Leader {
+    fn new(
+        ars: Ipv4Addr,
+        identity: &str,
+        leadership: i8,
+        morale: u8,
+    ) -> Leader {
+        Leader { ars, identity: identity.to_string(), leadership, morale }
+    }
+}
+
 // TODO: _code-structure: move to morale
 // TODO: perhaps improve naming of enum?
 // TODO: perhaps add Surrendered?
@@ -195,6 +874,7 @@ struct Leader {
 /// The unit's survivial instincts will trumph any army discipline until the
 /// unit is rallied into a non-broken state.
 ///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Condition {
     Broken,
     Composed,
@@ -212,6 +892,8 @@ enum Condition {
 /// To express this, support weapons have a number which determines if the
 /// weapon temporarily breaks down and malfunctions during operation.
 ///
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum WeaponType {
     Rifle,
     LMG,
@@ -228,19 +910,554 @@ enum WeaponType {
     Artillery,
 }
 
+/// SL5.75 Weapons are grouped into families of weapons that are operated in
+/// the same manner. A unit may only fire one weapon of each family in the
+/// same fire phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum WeaponFamily {
+    Rifle,
+    MachineGun,
+    Mortar,
+    Demolition,
+    Flamethrower,
+    Mine,
+    ATRifle,
+    Gun,
+    Artillery,
+}
+
+impl WeaponType {
+    fn family(&self) -> WeaponFamily {
+        match self {
+            WeaponType::Rifle => WeaponFamily::Rifle,
+            WeaponType::LMG | WeaponType::MMG | WeaponType::HMG => {
+                WeaponFamily::MachineGun
+            }
+            WeaponType::Mortar => WeaponFamily::Mortar,
+            WeaponType::Demolition => WeaponFamily::Demolition,
+            WeaponType::Flamethrower => WeaponFamily::Flamethrower,
+            WeaponType::APMine | WeaponType::ATMine => WeaponFamily::Mine,
+            WeaponType::ATRifle => WeaponFamily::ATRifle,
+            WeaponType::ATGun | WeaponType::TankGun => WeaponFamily::Gun,
+            WeaponType::Artillery => WeaponFamily::Artillery,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 struct SupportWeapon {
     weapon: WeaponType,
     firepower: u8,
     penetration: u8,
     range: u8,
     breakdown: u8,
+    // SL5.7 The cost, in portage points, of carrying the weapon.
+    portage: u8,
+    // A8.8 The weapon's ability to penetrate armor. Weapons without any armor
+    // penetration cannot fire at armored targets.
+    armor_penetration: u8,
+}
+
+impl SupportWeapon {
+    fn new(
+        weapon: WeaponType,
+        firepower: u8,
+        penetration: u8,
+        range: u8,
+        breakdown: u8,
+        portage: u8,
+    ) -> SupportWeapon {
+        SupportWeapon {
+            weapon,
+            firepower,
+            penetration,
+            range,
+            breakdown,
+            portage,
+            armor_penetration: 0,
+        }
+    }
+
+    fn with_armor_penetration(mut self, armor_penetration: u8) -> Self {
+        self.armor_penetration = armor_penetration;
+        self
+    }
+}
+
+// SL2.7 Captured support weapons
+// A support weapon always belongs to the side that it was issued to, but may
+// be picked up by units of either side once abandoned. A weapon carried by
+// a unit of the opposing side is a captured weapon. Captured weapons are
+// unfamiliar to their new crews, and break down more easily.
+const CAPTURED_BREAKDOWN_PENALTY: u8 = 2;
+
+impl BattleManager {
+    /// Returns true if the weapon is carried by a unit of the side that does
+    /// not own it.
+    fn captured(&self, weapon: &u32) -> bool {
+        match (self.carriers.get(weapon), self.owners.get(weapon)) {
+            (Some(carrier), Some(owner)) => self.sides[carrier] != *owner,
+            _ => false,
+        }
+    }
+
+    /// Returns the breakdown number of the weapon, lowered if the weapon is
+    /// operated by a captor.
+    fn breakdown(&self, weapon: &u32) -> u8 {
+        let breakdown = self.weapons[weapon].breakdown;
+        if self.captured(weapon) {
+            breakdown.saturating_sub(CAPTURED_BREAKDOWN_PENALTY)
+        } else {
+            breakdown
+        }
+    }
+
+    /// SL18.1 Checks that the weapon may be picked up by a unit of the given
+    /// side. Any abandoned support weapon may be captured, but the weapons
+    /// mounted in vehicles can never be captured and used.
+    fn check_capture(
+        &self,
+        side: Side,
+        weapon: &u32,
+    ) -> Result<(), RuleBreak> {
+        let sw = self.weapons.get(weapon).ok_or(RuleBreak::E0003)?;
+        let owner = self.owners.get(weapon);
+        if owner != Some(&side)
+            && (sw.weapon == WeaponType::TankGun
+                || self.mounted.contains(weapon))
+        {
+            return Err(RuleBreak::E1810);
+        }
+        Ok(())
+    }
 }
 
 /// SL18.1
 /// All vehicles must be operated by crews.
 /// Enemy vechicles cannot be captured and used.
-struct Vechicle {
+///
+/// A18.1 A vehicle is identified by an `ars`, just like squads and leaders,
+/// and its crew is part of the vehicle. A vehicle whose crew breaks can
+/// neither move nor fire until the crew is rallied.
+#[derive(Debug, Clone)]
+struct Vehicle {
+    pub ars: Ipv4Addr,
+    identity: String,
+    kind: VehicleType,
+    // Vehicles move in vehicle MF, with their own terrain costs, see
+    // `Terrain::vehicle_movement_cost`.
     mf: u8,
+    armor: Armor,
+    // A1.1 Damage that gets through the armor of a vehicle, from anything
+    // but anti-tank fire, destroys it.
+    health: u8,
+    // Morale of the crew operating the vehicle.
+    morale: u8,
+}
+
+impl Vehicle {
+    fn new(
+        ars: Ipv4Addr,
+        identity: &str,
+        kind: VehicleType,
+        mf: u8,
+        armor: Armor,
+        morale: u8,
+    ) -> Vehicle {
+        Vehicle {
+            ars,
+            identity: identity.to_string(),
+            kind,
+            mf,
+            armor,
+            health: 1,
+            morale,
+        }
+    }
+
+    fn armored(&self) -> bool {
+        self.armor != Armor::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VehicleType {
+    Tank,
+    AssaultGun,
+    Halftrack,
+    ArmouredCar,
+    Truck,
+}
+
+/// How a vehicle gets across the battlefield, which decides what terrain it
+/// can cross, and at what cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Locomotion {
+    Tracked,
+    HalfTracked,
+    Wheeled,
+}
+
+impl VehicleType {
+    fn locomotion(&self) -> Locomotion {
+        match self {
+            VehicleType::Tank | VehicleType::AssaultGun => Locomotion::Tracked,
+            VehicleType::Halftrack => Locomotion::HalfTracked,
+            VehicleType::ArmouredCar | VehicleType::Truck => {
+                Locomotion::Wheeled
+            }
+        }
+    }
+
+    /// Returns true if the main armament is mounted in a turret.
+    fn turret(&self) -> bool {
+        matches!(self, VehicleType::Tank | VehicleType::ArmouredCar)
+    }
+
+    /// A8.7 The to-hit DRM of the vehicle's size. Low vehicles are harder to
+    /// hit, and large vehicles easier.
+    fn size_modifier(&self) -> i8 {
+        match self {
+            VehicleType::AssaultGun | VehicleType::ArmouredCar => 1,
+            VehicleType::Truck => -1,
+            _ => 0,
+        }
+    }
+
+    /// A18.4 The number of squads that can ride in the vehicle. Leaders ride
+    /// along without taking up any room.
+    fn capacity(&self) -> u8 {
+        match self {
+            VehicleType::Halftrack => 1,
+            VehicleType::Truck => 2,
+            _ => 0,
+        }
+    }
+}
+
+/// The armor factors of the front, sides and rear of a vehicle. Unarmored
+/// vehicles have an armor factor of zero all around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Armor {
+    front: u8,
+    side: u8,
+    rear: u8,
+}
+
+/// A18.3 The hexside that a vehicle, or its turret, faces. Hexsides are
+/// numbered clockwise from 0 to 5, starting with the hexside facing north.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Facing(u8);
+
+impl Facing {
+    /// The direction of the facing in degrees, measured clockwise from east.
+    fn degrees(&self) -> f64 {
+        -90.0 + 60.0 * self.0 as f64
+    }
+
+    /// Returns the number of hexsides to turn from this facing to the other.
+    fn turns(&self, other: Facing) -> u8 {
+        let turns = (self.0 + 6 - other.0 % 6) % 6;
+        turns.min(6 - turns)
+    }
+}
+
+// A18.3 Turning a vehicle costs 1 MF for each hexside turned. A vehicle
+// always turns to face the hex it is about to enter.
+const VEHICLE_TURN_COST: u8 = 1;
+// A18.31 A vehicle may only fire at targets within its covered arc, that is
+// within this many degrees of the facing of its turret, or of its hull if
+// the vehicle has no turret.
+const COVERED_ARC: f64 = 60.0;
+// A18.4 Boarding a vehicle, or leaving it, costs the passenger this many MF.
+const EMBARK_COST: u8 = 2;
+
+// A1.1 Anything but anti-tank fire strikes a vehicle at its weakest armor.
+impl Target for Vehicle {
+    fn armor(&self) -> u8 {
+        self.armor.front.min(self.armor.side).min(self.armor.rear)
+    }
+
+    fn health(&self) -> u8 {
+        self.health
+    }
+
+    fn take_damage(&mut self, strength: u8) -> Damage {
+        inflict(Target::armor(self), &mut self.health, strength)
+    }
+}
+
+// A18.4 Vehicles carry their weapons, and the weapons loaded into them by
+// their passengers, without any portage limits.
+impl Carrier for Vehicle {
+    fn portage_allowance(&self) -> u8 {
+        u8::MAX
+    }
+
+    fn advance_portage(&self) -> u8 {
+        u8::MAX
+    }
+
+    fn fire_portage(&self) -> u8 {
+        u8::MAX
+    }
+}
+
+impl BattleManager {
+    /// Places a vehicle belonging to `side` in the given hex, with its hull,
+    /// and turret, facing the given hexside.
+    fn deploy_vehicle(
+        &mut self,
+        side: Side,
+        vehicle: Vehicle,
+        hex: HexId,
+        facing: Facing,
+    ) -> Result<(), RuleBreak> {
+        self.map.hex(&hex).ok_or(RuleBreak::E0002)?;
+        let ars = vehicle.ars;
+        let facing = Facing(facing.0 % 6);
+        self.facings.insert(ars, facing);
+        if vehicle.kind.turret() {
+            self.turrets.insert(ars, facing);
+        }
+        self.vehicles.insert(ars, vehicle);
+        self.place(ars, side, hex);
+        Ok(())
+    }
+
+    /// SL18.1 Mounts a weapon in a vehicle. Mounted weapons are operated by
+    /// the crew of the vehicle, and can never be abandoned or captured.
+    fn mount(
+        &mut self,
+        id: u32,
+        weapon: SupportWeapon,
+        vehicle: Ipv4Addr,
+    ) -> Result<(), RuleBreak> {
+        if !self.vehicles.contains_key(&vehicle) {
+            return Err(RuleBreak::E0001);
+        }
+        if self.weapons.contains_key(&id) {
+            return Err(RuleBreak::E0004);
+        }
+        self.issue_support_weapon(id, weapon, vehicle)?;
+        self.mounted.insert(id);
+        Ok(())
+    }
+
+    /// Returns the main armament of the vehicle, that is the first gun
+    /// mounted in it.
+    fn main_armament(&self, vehicle: &Ipv4Addr) -> Option<u32> {
+        self.carriers
+            .iter()
+            .filter(|(id, carrier)| {
+                *carrier == vehicle && self.mounted.contains(id)
+            })
+            .map(|(id, _)| *id)
+            .find(|id| self.weapons[id].weapon.family() == WeaponFamily::Gun)
+    }
+
+    /// Returns the units riding in the vehicle.
+    fn riders(&self, vehicle: &Ipv4Addr) -> Vec<Ipv4Addr> {
+        self.passengers
+            .iter()
+            .filter(|(_, v)| *v == vehicle)
+            .map(|(ars, _)| *ars)
+            .collect()
+    }
+
+    /// A18.5 Returns true if the unit is an armored vehicle, or rides in one.
+    /// The Infantry Fire Table has no effect on armored vehicles, nor on the
+    /// passengers they protect.
+    fn armored(&self, ars: &Ipv4Addr) -> bool {
+        let vehicle = self.passengers.get(ars).unwrap_or(ars);
+        self.vehicles.get(vehicle).is_some_and(Vehicle::armored)
+    }
+
+    /// A18.4 Checks that the unit is not riding in a vehicle. Passengers
+    /// only move along with their vehicle.
+    fn check_dismounted(&self, ars: &Ipv4Addr) -> Result<(), RuleBreak> {
+        if self.passengers.contains_key(ars) {
+            return Err(RuleBreak::A1841);
+        }
+        Ok(())
+    }
+
+    /// A18.31 Checks that the target hex is within the covered arc of the
+    /// vehicle.
+    fn check_covered_arc(
+        &self,
+        ars: &Ipv4Addr,
+        target: &HexId,
+    ) -> Result<(), RuleBreak> {
+        let origin = self.locations.get(ars).ok_or(RuleBreak::E0001)?;
+        let facing = self.turrets.get(ars).or(self.facings.get(ars));
+        let facing = facing.ok_or(RuleBreak::E0001)?;
+        let bearing = self.map.bearing(origin, *facing, target);
+        match bearing {
+            Some(bearing) if bearing <= COVERED_ARC + 1e-6 => Ok(()),
+            _ => Err(RuleBreak::A1831),
+        }
+    }
+
+    /// A18.31 Orders a vehicle to rotate its turret to face another hexside.
+    /// The turret may be rotated during any phase in which the vehicle may
+    /// fire, but not after it has fired.
+    fn rotate_turret(
+        &mut self,
+        ars: Ipv4Addr,
+        facing: Facing,
+    ) -> Result<(), RuleBreak> {
+        let side = *self.sides.get(&ars).ok_or(RuleBreak::E0001)?;
+        if !self.turrets.contains_key(&ars) {
+            return Err(RuleBreak::A1830);
+        }
+        self.check_may_fire(&ars, side)?;
+        if self.has_marker(&ars, Marker::Fired)
+            || self.weapon_types_fired.contains_key(&ars)
+        {
+            return Err(RuleBreak::A8200);
+        }
+        let facing = Facing(facing.0 % 6);
+        self.turrets.insert(ars, facing);
+        self.events.push(Event::TurretRotated { ars, facing });
+        Ok(())
+    }
+
+    /// A18.2 Orders a vehicle to move along the given path of adjacent hexes,
+    /// carrying its passengers along. The vehicle turns to face every hex
+    /// before entering it, and pays for both turning and terrain in vehicle
+    /// MF.
+    fn move_vehicle(
+        &mut self,
+        ars: Ipv4Addr,
+        path: &[HexId],
+    ) -> Result<u8, RuleBreak> {
+        let locomotion =
+            self.vehicles.get(&ars).ok_or(RuleBreak::E0001)?.kind.locomotion();
+        self.unit_can_move(&ars)?;
+        if self.has_marker(&ars, Marker::Immobilized) {
+            return Err(RuleBreak::A8800);
+        }
+        if path.is_empty() {
+            return Err(RuleBreak::E0005);
+        }
+        let start = self.locations[&ars];
+        let side = self.sides[&ars];
+        let mut facing = self.facings[&ars];
+        let mut cost: u8 = 0;
+        let mut from = start;
+        let mut road = 0;
+        for to in path {
+            let hex = self.map.hex(to).ok_or(RuleBreak::E0002)?;
+            let hexside =
+                self.map.facing(&from, to).ok_or(RuleBreak::A5100)?;
+            if hex.terrain.contains(&Terrain::Blaze) {
+                return Err(RuleBreak::A8930);
+            }
+            // SL5.6 Vehicles may not move into a hex containing an enemy
+            // unit either.
+            if self.units_in(to).iter().any(|ars| self.sides[ars] != side) {
+                return Err(RuleBreak::E5600);
+            }
+            let turns = facing.turns(hexside) * VEHICLE_TURN_COST;
+            cost = cost.saturating_add(turns);
+            facing = hexside;
+            // SL5.52 Roads are as fast for vehicles as for infantry.
+            if self.map.along_road(&from, to) {
+                road = (road + 1) % 2;
+                cost = cost.saturating_add(road);
+            } else {
+                road = 0;
+                let step = self
+                    .map
+                    .vehicle_movement_cost(&from, to, locomotion)
+                    .ok_or(RuleBreak::A1821)?;
+                cost = cost.saturating_add(step);
+            }
+            from = *to;
+        }
+        let spent = self.mf_spent.get(&ars).copied().unwrap_or(0);
+        if spent.saturating_add(cost) > self.mf_allowance(&ars) {
+            return Err(RuleBreak::E5200);
+        }
+        let mut units = vec![ars];
+        units.extend(self.riders(&ars));
+        let destination = *path.last().unwrap_or(&start);
+        self.check_stacking(side, &units, &destination)?;
+        *self.mf_spent.entry(ars).or_default() += cost;
+        self.facings.insert(ars, facing);
+        for unit in &units {
+            self.locations.insert(*unit, destination);
+            self.add_marker(*unit, Marker::Moved);
+        }
+        self.events
+            .push(Event::Moved { units: units.clone(), path: path.to_vec() });
+        self.trigger_mines(&units, path);
+        self.claim_path(&units, path);
+        Ok(cost)
+    }
+
+    /// Eliminates a vehicle. The passengers bail out of the burning wreck,
+    /// and must pass a morale check to do so in good order.
+    fn destroy_vehicle(&mut self, ars: Ipv4Addr) {
+        let riders = self.riders(&ars);
+        self.eliminate(ars);
+        for rider in riders {
+            self.morale_check(rider, 0);
+        }
+    }
+
+    /// A18.4 Orders a squad or leader to board a vehicle of the same side,
+    /// in the same hex. The weapons carried by the unit are loaded along
+    /// with it.
+    fn embark(
+        &mut self,
+        ars: Ipv4Addr,
+        vehicle: Ipv4Addr,
+    ) -> Result<(), RuleBreak> {
+        let kind = self.vehicles.get(&vehicle).ok_or(RuleBreak::E0001)?.kind;
+        self.unit_can_move(&ars)?;
+        self.check_dismounted(&ars)?;
+        if self.vehicles.contains_key(&ars)
+            || self.locations.get(&ars) != self.locations.get(&vehicle)
+            || self.sides.get(&ars) != self.sides.get(&vehicle)
+        {
+            return Err(RuleBreak::A1840);
+        }
+        let squads = self
+            .riders(&vehicle)
+            .iter()
+            .filter(|rider| self.squads.contains_key(rider))
+            .count();
+        if self.squads.contains_key(&ars) && squads >= kind.capacity() as usize
+        {
+            return Err(RuleBreak::A1840);
+        }
+        let spent = self.mf_spent.get(&ars).copied().unwrap_or(0);
+        if spent + EMBARK_COST > self.mf_allowance(&ars) {
+            return Err(RuleBreak::E5200);
+        }
+        *self.mf_spent.entry(ars).or_default() += EMBARK_COST;
+        self.passengers.insert(ars, vehicle);
+        self.events.push(Event::Embarked { ars, vehicle });
+        self.trigger_mines(&[ars], &[self.locations[&ars]]);
+        Ok(())
+    }
+
+    /// A18.4 Orders a passenger to leave its vehicle, into the vehicle's hex.
+    fn disembark(&mut self, ars: Ipv4Addr) -> Result<(), RuleBreak> {
+        let vehicle = *self.passengers.get(&ars).ok_or(RuleBreak::A1840)?;
+        self.unit_can_move(&ars)?;
+        let spent = self.mf_spent.get(&ars).copied().unwrap_or(0);
+        if spent + EMBARK_COST > self.mf_allowance(&ars) {
+            return Err(RuleBreak::E5200);
+        }
+        *self.mf_spent.entry(ars).or_default() += EMBARK_COST;
+        self.passengers.remove(&ars);
+        self.events.push(Event::Disembarked { ars, vehicle });
+        self.trigger_mines(&[ars], &[self.locations[&ars]]);
+        Ok(())
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -274,13 +1491,16 @@ static HEXDISTANCE: AtomicU8 = AtomicU8::new(40);
 /// of both terrain types.
 ///
 //  TODO: Implement terrain category in database.
+#[derive(Debug, Clone)]
 struct Hex {
-    id: u32,
+    id: HexId,
     elevation: u8,
     // TODO: not super happy of type-setting terrain as a Vec<Terrain>, but how
     // else can we handle the fact that a single hex could contain
     // a house on a hill surrounded by a hedge?
     terrain: Vec<Terrain>,
+    // A7.7 Smoke drifting over the hex, if any.
+    smoke: Option<Smoke>,
 }
 
 impl Hex {
@@ -289,36 +1509,264 @@ impl Hex {
     }
 }
 
-/// Returns the distance from hex A, to hex B in the number of inclusive hexes.
-fn get_distance(origin: Hex, target: Hex) -> u8 {
-    todo!()
-}
+/// The number of hex columns, lettered A to GG, on a single board.
+const BOARD_COLUMNS: u8 = 33;
+/// The number of hexes, numbered 1 to 10, in each column of a single board.
+const BOARD_ROWS: u8 = 10;
 
-/// Returns a boolean indicating if the unit located in origin hex can see
-/// the enemy located in the target hex.
-/// The requires a database hit due to the fact that all line of sight from
-/// to any other hex on the map has been precalculated in to a lookup table.
-/// Each hex has a center which is used to calculate Line of Sight (LOS) to other
-/// hexes, for targeting purposes. A unit (except for mortar units) can only fire
-/// on targets that they can see (that is in their LOS).
-fn get_los(origin: Hex, target: Hex) -> bool {
-    todo!()
-}
+/// The cube coordinate offsets of the six hexes adjacent to a hex, clockwise
+/// from the hex to the north.
+const HEXSIDES: [(i32, i32, i32); 6] =
+    [(0, 1, -1), (1, 0, -1), (1, -1, 0), (0, -1, 1), (-1, 0, 1), (-1, 1, 0)];
 
-/// SL3.1 Terrain will affect how fast units can move through a hex on the map.
-/// The Terrain Effects Chart (or TEC) is a database containing a description of
-/// all terrain types.
-//  TODO: add TEC database and connectivity.
-///
-/// The Terrain Effect Function (TEF) calculates how a particular type of terrain
-/// affects movement. A hex can contain multiple terrain types.
-/// The cost of moving through such a hex is cumulative.
-fn get_terrain_effect_on_move(hex: Hex) -> u8 {
-    // types of terrain in the hex
-    // SL3.8 accumulate terrain types to calculate effect
-    todo!();
+/// A map is made up of one or more boards, placed from north to south with
+/// their long edges joined together. Hexes are laid out in columns, where
+/// every odd column is shifted half a hex to the south.
+#[derive(Debug, Clone)]
+struct Map {
+    boards: Vec<u8>,
+    hexes: HashMap<HexId, Hex>,
 }
-/// SL3.3 Each hex on the map has a center point. This point is used to
+
+impl Map {
+    /// Creates a map out of the given boards. Every hex starts out as
+    /// Terrain::OpenGround at ground level.
+    fn new(boards: &[u8]) -> Map {
+        let mut hexes = HashMap::new();
+        for board in boards {
+            for column in 0..BOARD_COLUMNS {
+                for row in 1..=BOARD_ROWS {
+                    let id = HexId { board: *board, column, row };
+                    let terrain = vec![Terrain::OpenGround];
+                    let smoke = None;
+                    let hex = Hex { id, elevation: 0, terrain, smoke };
+                    hexes.insert(id, hex);
+                }
+            }
+        }
+        Map { boards: boards.to_vec(), hexes }
+    }
+
+    fn hex(&self, id: &HexId) -> Option<&Hex> {
+        self.hexes.get(id)
+    }
+
+    fn hex_mut(&mut self, id: &HexId) -> Option<&mut Hex> {
+        self.hexes.get_mut(id)
+    }
+
+    /// Returns the column and row of the hex on the combined map.
+    fn coordinates(&self, id: &HexId) -> Option<(i32, i32)> {
+        let board = self.boards.iter().position(|b| *b == id.board)?;
+        let row = board as i32 * BOARD_ROWS as i32 + id.row as i32 - 1;
+        Some((id.column as i32, row))
+    }
+
+    /// Returns the hex found at the given column and row on the combined map.
+    fn at(&self, column: i32, row: i32) -> Option<HexId> {
+        if column < 0 || column >= BOARD_COLUMNS as i32 || row < 0 {
+            return None;
+        }
+        let board = *self.boards.get((row / BOARD_ROWS as i32) as usize)?;
+        let row = (row % BOARD_ROWS as i32) as u8 + 1;
+        Some(HexId { board, column: column as u8, row })
+    }
+
+    /// Converts the column and row of a hex into cube coordinates, in which
+    /// distances and straight lines between hexes are easy to calculate.
+    fn cube(&self, id: &HexId) -> Option<(i32, i32, i32)> {
+        let (column, row) = self.coordinates(id)?;
+        let x = column;
+        let z = row - (column - (column & 1)) / 2;
+        Some((x, -x - z, z))
+    }
+
+    fn at_cube(&self, (x, _, z): (i32, i32, i32)) -> Option<HexId> {
+        self.at(x, z + (x - (x & 1)) / 2)
+    }
+
+    /// Returns the hexes adjacent to the given hex.
+    fn neighbours(&self, id: &HexId) -> Vec<HexId> {
+        let Some((x, y, z)) = self.cube(id) else {
+            return Vec::new();
+        };
+        HEXSIDES
+            .iter()
+            .filter_map(|(dx, dy, dz)| self.at_cube((x + dx, y + dy, z + dz)))
+            .collect()
+    }
+
+    /// A18.3 Returns the hexside of the origin hex that faces the adjacent
+    /// target hex.
+    fn facing(&self, origin: &HexId, target: &HexId) -> Option<Facing> {
+        let (ax, ay, az) = self.cube(origin)?;
+        let (bx, by, bz) = self.cube(target)?;
+        HEXSIDES
+            .iter()
+            .position(|side| *side == (bx - ax, by - ay, bz - az))
+            .map(|side| Facing(side as u8))
+    }
+
+    /// Returns the angle, in degrees from 0 to 180, between the facing and
+    /// the line from the center of the origin hex to the center of the
+    /// target hex. A target straight ahead is at 0 degrees, and a target
+    /// straight behind is at 180 degrees.
+    fn bearing(
+        &self,
+        origin: &HexId,
+        facing: Facing,
+        target: &HexId,
+    ) -> Option<f64> {
+        let (ax, _, az) = self.cube(origin)?;
+        let (bx, _, bz) = self.cube(target)?;
+        if (ax, az) == (bx, bz) {
+            return Some(0.0);
+        }
+        let (dx, dz) = ((bx - ax) as f64, (bz - az) as f64);
+        let angle = (3f64.sqrt() * (dz + dx / 2.0)).atan2(1.5 * dx);
+        let difference =
+            (angle.to_degrees() - facing.degrees()).rem_euclid(360.0);
+        Some(difference.min(360.0 - difference))
+    }
+
+    /// Returns the distance from hex A, to hex B in the number of hexes, that
+    /// is, adjacent hexes are 1 hex apart.
+    fn distance(&self, origin: &HexId, target: &HexId) -> Option<u8> {
+        let (ax, ay, az) = self.cube(origin)?;
+        let (bx, by, bz) = self.cube(target)?;
+        let distance =
+            (ax - bx).abs().max((ay - by).abs()).max((az - bz).abs());
+        Some(distance as u8)
+    }
+
+    /// Returns the hexes that a straight line, drawn from the center of the
+    /// origin hex to the center of the target hex, passes through. Neither the
+    /// origin nor the target hex are included.
+    fn line(&self, origin: &HexId, target: &HexId) -> Vec<HexId> {
+        let (Some(a), Some(b), Some(n)) = (
+            self.cube(origin),
+            self.cube(target),
+            self.distance(origin, target),
+        ) else {
+            return Vec::new();
+        };
+        // Nudge the line slightly so that it never runs exactly along a
+        // hexside, which would make the hexes it passes through ambiguous.
+        let a = (a.0 as f64 + 1e-6, a.1 as f64 + 2e-6, a.2 as f64 - 3e-6);
+        let b = (b.0 as f64 + 1e-6, b.1 as f64 + 2e-6, b.2 as f64 - 3e-6);
+        (1..n)
+            .filter_map(|i| {
+                let t = i as f64 / n as f64;
+                let x = a.0 + (b.0 - a.0) * t;
+                let y = a.1 + (b.1 - a.1) * t;
+                let z = a.2 + (b.2 - a.2) * t;
+                self.at_cube(cube_round(x, y, z))
+            })
+            .collect()
+    }
+
+    /// SL5.5 Returns the MF cost of moving from a hex into an adjacent hex.
+    /// SL5.53 Moving from one level to a higher one doubles the MF cost.
+    fn movement_cost(&self, from: &HexId, to: &HexId) -> u8 {
+        let (Some(from), Some(to)) = (self.hex(from), self.hex(to)) else {
+            return 0;
+        };
+        let cost = get_terrain_effect_on_move(to);
+        if to.elevation > from.elevation {
+            cost * 2
+        } else {
+            cost
+        }
+    }
+
+    /// A18.2 Returns the vehicle MF cost of moving from a hex into an
+    /// adjacent hex, or None if the terrain cannot be crossed by the vehicle.
+    /// SL5.53 Moving from one level to a higher one doubles the MF cost.
+    fn vehicle_movement_cost(
+        &self,
+        from: &HexId,
+        to: &HexId,
+        locomotion: Locomotion,
+    ) -> Option<u8> {
+        let (from, to) = (self.hex(from)?, self.hex(to)?);
+        let cost = to
+            .terrain
+            .iter()
+            .map(|t| t.vehicle_movement_cost(locomotion))
+            .sum::<Option<u8>>()?;
+        if to.elevation > from.elevation {
+            Some(cost * 2)
+        } else {
+            Some(cost)
+        }
+    }
+
+    /// Returns true if both hexes are road hexes on the same level.
+    fn along_road(&self, from: &HexId, to: &HexId) -> bool {
+        let road = |hex: &Hex| hex.terrain.contains(&Terrain::OnRoad);
+        match (self.hex(from), self.hex(to)) {
+            (Some(from), Some(to)) => {
+                road(from) && road(to) && from.elevation == to.elevation
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns a boolean indicating if the unit located in origin hex can see
+    /// the enemy located in the target hex.
+    /// Each hex has a center which is used to calculate Line of Sight (LOS) to
+    /// other hexes, for targeting purposes. A unit (except for mortar units)
+    /// can only fire on targets that they can see (that is in their LOS).
+    ///
+    /// SL7.3 LOS extends into woods and buildings but not through them, and
+    /// a hex that is higher than both the origin and the target hex blocks
+    /// the LOS between them. A7.71 The same goes for hexes filled with
+    /// smoke that has not yet dispersed.
+    fn los(&self, origin: &HexId, target: &HexId) -> bool {
+        let (Some(from), Some(to)) = (self.hex(origin), self.hex(target))
+        else {
+            return false;
+        };
+        let height = from.elevation.max(to.elevation);
+        self.line(origin, target).iter().filter_map(|id| self.hex(id)).all(
+            |hex| {
+                hex.elevation <= height
+                    && !hex.terrain.iter().any(Terrain::blocks_los)
+                    && !hex.smoke.is_some_and(|smoke| !smoke.dispersed)
+            },
+        )
+    }
+}
+
+/// Rounds fractional cube coordinates to the cube coordinates of the hex that
+/// contains them.
+fn cube_round(x: f64, y: f64, z: f64) -> (i32, i32, i32) {
+    let (mut rx, mut ry, mut rz) = (x.round(), y.round(), z.round());
+    let (dx, dy, dz) = ((rx - x).abs(), (ry - y).abs(), (rz - z).abs());
+    if dx > dy && dx > dz {
+        rx = -ry - rz;
+    } else if dy > dz {
+        ry = -rx - rz;
+    } else {
+        rz = -rx - ry;
+    }
+    (rx as i32, ry as i32, rz as i32)
+}
+
+/// SL3.1 Terrain will affect how fast units can move through a hex on the map.
+/// The Terrain Effects Chart (or TEC) is a database containing a description of
+/// all terrain types.
+//  TODO: add TEC database and connectivity.
+///
+/// The Terrain Effect Function (TEF) calculates how a particular type of terrain
+/// affects movement. A hex can contain multiple terrain types.
+/// The cost of moving through such a hex is cumulative.
+fn get_terrain_effect_on_move(hex: &Hex) -> u8 {
+    // types of terrain in the hex
+    // SL3.8 accumulate terrain types to calculate effect
+    hex.terrain.iter().map(|t| t.movement_cost() as u8).sum()
+}
+/// SL3.3 Each hex on the map has a center point. This point is used to
 /// calculating line of sight (LOS) between two different hexes on a map.
 /// These points are important because combat units are assumed to be firing
 /// from these center points towards center points in other hexes.
@@ -344,6 +1792,57 @@ fn get_terrain_effect_on_move(hex: Hex) -> u8 {
 //  hex, however the id of that hex is something that needs to be considered.
 /// The classic Hex format is:
 /// 1AB3 where <MAPID(1A)-ROWLETTER(B)-ROWHEXNUMBER(3)>
+///
+/// Columns A to Z are followed by the doubled letters AA to GG, meaning that
+/// `2E3` is the third hex of column E on board 2, and `4GG10` is the last hex
+/// of the last column on board 4.
+// The SL3.3 to SL3.7 paragraphs above are kept with the hex documentation.
+#[allow(clippy::empty_line_after_doc_comments)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct HexId {
+    board: u8,
+    // The column letter, counted from zero (A = 0, Z = 25, AA = 26).
+    column: u8,
+    row: u8,
+}
+
+impl fmt::Display for HexId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let letter = (b'A' + self.column % 26) as char;
+        let letters = if self.column < 26 {
+            letter.to_string()
+        } else {
+            format!("{letter}{letter}")
+        };
+        write!(f, "{}{}{}", self.board, letters, self.row)
+    }
+}
+
+impl FromStr for HexId {
+    type Err = RuleBreak;
+
+    fn from_str(s: &str) -> Result<HexId, RuleBreak> {
+        let letters_at = s.find(|c: char| c.is_ascii_uppercase());
+        let letters_at = letters_at.ok_or(RuleBreak::E0002)?;
+        let row_at = s[letters_at..]
+            .find(|c: char| c.is_ascii_digit())
+            .ok_or(RuleBreak::E0002)?
+            + letters_at;
+        let board = s[..letters_at].parse().map_err(|_| RuleBreak::E0002)?;
+        let letters = &s.as_bytes()[letters_at..row_at];
+        let row = s[row_at..].parse().map_err(|_| RuleBreak::E0002)?;
+        let column = match letters {
+            [a] if a.is_ascii_uppercase() => a - b'A',
+            [a, b] if a == b && a.is_ascii_uppercase() => a - b'A' + 26,
+            _ => return Err(RuleBreak::E0002),
+        };
+        if column >= BOARD_COLUMNS || row == 0 || row > BOARD_ROWS {
+            return Err(RuleBreak::E0002);
+        }
+        Ok(HexId { board, column, row })
+    }
+}
+
 fn get_grid_coordinate() -> Hex {
     // Normally used when clicking on a map and reading the Hex of the hex.
     todo!()
@@ -368,19 +1867,38 @@ static GAMETURN: AtomicU8 = AtomicU8::new(2);
 /// In each game turn, one player will be the defender, while the othe will be
 /// the attacker, they then switch and the defender becomes the attacker.
 /// It should be noted that it's the attacker who goes through the phases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Player {
     Attacker,
     Defender,
 }
 
+/// The two opposing sides on the battlefield. Whereas the `Player` role of a
+/// side switches with every player turn, the side a unit fights for never
+/// changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Side {
+    Allies,
+    Axis,
+}
+
+impl Side {
+    fn opponent(&self) -> Side {
+        match self {
+            Side::Allies => Side::Axis,
+            Side::Axis => Side::Allies,
+        }
+    }
+}
+
 /// The player who moves first in a game turn is refered to as the
 /// Player::Attacker, while the other player is refered to as Player::Defender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Phase {
     // SL4.1
     // Both players can attempt to repair malfunctioning support weapons and
     // attempt to rally broken units.
     // TODO: add fn repair_malfunctioning_support_weapon()
-    // TODO: add fn rally_broken_unit().
     Rally,
     // SL4.2
     // The Player::Attacker may order any of his units to fire on any
@@ -401,7 +1919,6 @@ enum Phase {
     // Player::Attacker may now order any of his units who moved during
     // Phase::Movement to fire at enemy units. The penalty for firing after
     // movement is that firepower is halfed, rounded down.
-    // TODO: assert that firepower is halved (rounded down) for units that moved.
     // Units that did neither Phase::PrepFire nor Phase::Movement can fire at their
     // full firepower.
     // At the end of the AdvancingFire phase all Marker::PreFire are removed.
@@ -431,6 +1948,107 @@ enum Phase {
     CloseCombat,
 }
 
+impl Phase {
+    /// Returns the phase that follows this one. Phase::CloseCombat is followed
+    /// by the Phase::Rally of the next player turn.
+    fn next(&self) -> Phase {
+        match self {
+            Phase::Rally => Phase::PrepFire,
+            Phase::PrepFire => Phase::Movement,
+            Phase::Movement => Phase::DefensiveFire,
+            Phase::DefensiveFire => Phase::AdvancingFire,
+            Phase::AdvancingFire => Phase::Rout,
+            Phase::Rout => Phase::Advance,
+            Phase::Advance => Phase::CloseCombat,
+            Phase::CloseCombat => Phase::Rally,
+        }
+    }
+}
+
+impl BattleManager {
+    /// Ends the current phase and steps the battle into the next one.
+    fn end_phase(&mut self) {
+        let before = self.snapshot(Step::EndPhase);
+        self.update_control();
+        // A unit may only fire once in each fire phase, and only one weapon
+        // of each family.
+        self.remove_marker(Marker::Fired);
+        self.weapon_types_fired.clear();
+        match self.phase {
+            Phase::Rally => self.remove_marker(Marker::Rallied),
+            Phase::Movement => {
+                self.mf_spent.clear();
+                self.escorts.clear();
+                self.remove_marker(Marker::SmokeGrenade);
+            }
+            Phase::Rout => self.mf_spent.clear(),
+            Phase::Advance => {
+                self.remove_marker(Marker::Advanced);
+                self.detonate_charges();
+            }
+            // SL4.5 At the end of the AdvancingFire phase all
+            // Marker::PrepFire are removed.
+            Phase::AdvancingFire => self.remove_marker(Marker::PrepFire),
+            // SL4.9 After the end of Phase::CloseCombat the Player::Attacker
+            // and Player::Defender switches.
+            Phase::CloseCombat => {
+                self.remove_marker(Marker::Moved);
+                self.remove_marker(Marker::EnPassant);
+                self.moved_portage.clear();
+                self.attacker = self.attacker.opponent();
+                // A8.8 Shocked crews recover at the start of their own
+                // player turn.
+                for (ars, markers) in self.markers.iter_mut() {
+                    if self.sides.get(ars) == Some(&self.attacker) {
+                        markers.retain(|m| *m != Marker::Shocked);
+                    }
+                }
+                self.dissipate_smoke();
+                self.burn();
+            }
+            _ => {}
+        }
+        self.phase = self.phase.next();
+        self.events.push(Event::PhaseChanged(self.phase));
+        self.remember(before);
+    }
+}
+
+// SL4.1 A broken unit may attempt to rally once in each Phase::Rally, as long
+// as it is stacked with an unbroken leader of its side. Broken leaders, and
+// the crews of broken vehicles, may attempt to rally on their own. The unit
+// rallies if the dice roll, modified by the leadership of the leader, is equal
+// to, or less than, its morale.
+impl BattleManager {
+    /// SL4.1 Orders a broken unit to attempt to rally. Returns true if the
+    /// unit rallied.
+    fn rally(&mut self, ars: Ipv4Addr) -> Result<bool, RuleBreak> {
+        self.locations.get(&ars).ok_or(RuleBreak::E0001)?;
+        if self.phase != Phase::Rally || !self.broken(&ars) {
+            return Err(RuleBreak::E4100);
+        }
+        if self.has_marker(&ars, Marker::Rallied) {
+            return Err(RuleBreak::E4120);
+        }
+        let leader = self.led_by(&ars);
+        let on_its_own = self.leaders.contains_key(&ars)
+            || self.vehicles.contains_key(&ars);
+        if leader.is_none() && !on_its_own {
+            return Err(RuleBreak::E4110);
+        }
+        let roll = self.dice.roll();
+        let leadership = leader.map_or(0, |l| self.leaders[&l].leadership);
+        let rallied =
+            roll.total() as i8 + leadership <= self.morale(&ars) as i8;
+        self.add_marker(ars, Marker::Rallied);
+        if rallied {
+            self.conditions.insert(ars, Condition::Composed);
+        }
+        self.events.push(Event::Rally { ars, roll, rallied });
+        Ok(rallied)
+    }
+}
+
 // SL4.9 Game Turn
 // After the end of SL4.8 Phase:CloseCombat the Player::Attacker and
 // Player::Defender switches.
@@ -439,17 +2057,76 @@ enum Phase {
 //
 // A Game Turn is considered complete when both the attacking entity and the
 // defending entity have gone through steps SL4.1 to SL4.8.
-// TODO: The Battlemanger can now increment the gameturn_counter by 1.
-// TODO: If  a scenario is being modeled then increment the Scenario Turn Record
-// Counter by 1.
-fn update_scenario_turn() {
-    // Phase.counter() mod 16 represents the number of complete Game Turns.
-    // Everytime the result is 0, increment the scenario game turn by one.
-    // Check if scenario game turn has reached limit and the game is over.
-    // TODO: implement Phase.counter() mod 16
-    // TODO: implement check if Phase.counter() > Scenario Turn Record Chart
-    // which would mean that the simulation/game is over.
-    todo!()
+//
+// The ScenarioManager keeps the Scenario Turn Record Chart, counting every
+// phase played. A scenario may end on a half turn, where only the side that
+// moves first gets to play its player turn in the last game turn.
+const PHASES_PER_GAME_TURN: u16 = 16;
+const PHASES_PER_PLAYER_TURN: u16 = 8;
+
+/// Why the battle came to an end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EndReason {
+    // The last game turn on the Turn Record Chart has been played.
+    TurnLimit,
+    // A21.3 A sudden death victory condition was met.
+    SuddenDeath,
+}
+
+/// The final result of a battle.
+#[derive(Debug, Clone, PartialEq)]
+struct GameOver {
+    // The last game turn that was played.
+    turn: u8,
+    reason: EndReason,
+    // The side that won, or None if the battle is a draw.
+    winner: Option<Side>,
+}
+
+impl ScenarioManager {
+    /// The number of phases played before the scenario reaches its turn
+    /// limit.
+    fn phase_limit(&self) -> u16 {
+        let half = if self.half_turn { PHASES_PER_PLAYER_TURN } else { 0 };
+        self.turns as u16 * PHASES_PER_GAME_TURN + half
+    }
+
+    /// Records the phase that the battle just ended on the Turn Record Chart.
+    /// Every 16th phase completes a game turn, and the next one starts, or
+    /// the game is over. The victory conditions are evaluated after each
+    /// game turn. Returns the final result once the battle is over.
+    fn update_scenario_turn(
+        &mut self,
+        bm: &mut BattleManager,
+    ) -> Option<GameOver> {
+        if self.game_over.is_some() {
+            return self.game_over.clone();
+        }
+        bm.remember_scenario(self);
+        self.phases += 1;
+        let turn_over = self.phases % PHASES_PER_GAME_TURN == 0;
+        let sudden_death =
+            if turn_over { self.sudden_death(bm) } else { None };
+        let ending = if self.phases >= self.phase_limit() {
+            Some((EndReason::TurnLimit, self.winner(bm)))
+        } else {
+            sudden_death.map(|side| (EndReason::SuddenDeath, Some(side)))
+        };
+        if let Some((reason, winner)) = ending {
+            let turn = (self.phases + PHASES_PER_GAME_TURN - 1)
+                / PHASES_PER_GAME_TURN;
+            let game_over = GameOver { turn: turn as u8, reason, winner };
+            bm.events.push(Event::GameOver(game_over.clone()));
+            self.game_over = Some(game_over);
+        } else if turn_over {
+            self.turn += 1;
+            bm.events.push(Event::GameTurn(self.turn));
+        }
+        if self.game_over.is_none() {
+            self.release_reinforcements(bm);
+        }
+        self.game_over.clone()
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -466,20 +2143,135 @@ pub trait Orderable {
 }
 
 /// A trait denotating any type that can carry equipment and portable weapons.
+/// The weapons actually carried are tracked by the BattleManager, see
+/// `BattleManager::carry` and `BattleManager::abandon`.
 pub trait Carrier {
-    fn carry() -> bool;
-    fn abandon() -> bool;
+    /// SL5.71 The portage points that can be carried without any MF penalty.
+    fn portage_allowance(&self) -> u8;
+    /// SL5.73 The portage points that can be carried during Phase::Advance.
+    fn advance_portage(&self) -> u8;
+    /// SL5.74 Carrying this many portage points, or more, during
+    /// Phase::Movement prevents support weapon fire in Phase::AdvancingFire.
+    fn fire_portage(&self) -> u8;
+}
+
+impl<State> Carrier for Squad<State> {
+    fn portage_allowance(&self) -> u8 {
+        SQUAD_PORTAGE_ALLOWANCE
+    }
+
+    fn advance_portage(&self) -> u8 {
+        SQUAD_ADVANCE_PORTAGE
+    }
+
+    fn fire_portage(&self) -> u8 {
+        SQUAD_FIRE_PORTAGE
+    }
+}
+
+impl Carrier for Leader {
+    fn portage_allowance(&self) -> u8 {
+        LEADER_PORTAGE_ALLOWANCE
+    }
+
+    fn advance_portage(&self) -> u8 {
+        LEADER_ADVANCE_PORTAGE
+    }
+
+    fn fire_portage(&self) -> u8 {
+        LEADER_FIRE_PORTAGE
+    }
 }
 
 // Handles: Phase::Advance
 // Any unit that the player selects to move must pass the checks of not being
-fn advance_unit<T: Orderable>(unit: &T, dest: Hex) -> bool {
-    // if unit.condition != Condition::Broken {}
-    todo!()
+// broken, and of not having advanced already during the phase.
+impl BattleManager {
+    fn advance(
+        &mut self,
+        units: &[Ipv4Addr],
+        dest: HexId,
+    ) -> Result<(), RuleBreak> {
+        let first = units.first().ok_or(RuleBreak::E0001)?;
+        let origin = *self.locations.get(first).ok_or(RuleBreak::E0001)?;
+        let side = self.sides[first];
+        for ars in units {
+            self.unit(ars).ok_or(RuleBreak::E0001)?;
+            if self.phase != Phase::Advance
+                || self.player(self.sides[ars]) != Player::Attacker
+                || self.broken(ars)
+                || self.has_marker(ars, Marker::Advanced)
+            {
+                return Err(RuleBreak::E4700);
+            }
+            if self.locations[ars] != origin {
+                return Err(RuleBreak::A5200);
+            }
+            // A18.2 Vehicles only move during Phase::Movement.
+            if self.vehicles.contains_key(ars) {
+                return Err(RuleBreak::E4700);
+            }
+            self.check_dismounted(ars)?;
+            self.carry_during_advance_phase(ars)?;
+        }
+        let hex = self.map.hex(&dest).ok_or(RuleBreak::E0002)?;
+        if self.map.distance(&origin, &dest) != Some(1) {
+            return Err(RuleBreak::A5100);
+        }
+        if hex.terrain.contains(&Terrain::Blaze) {
+            return Err(RuleBreak::A8930);
+        }
+        if hex.terrain.contains(&Terrain::Water) {
+            return Err(RuleBreak::A1200);
+        }
+        self.check_stacking(side, units, &dest)?;
+        for ars in units {
+            self.locations.insert(*ars, dest);
+            self.add_marker(*ars, Marker::Advanced);
+        }
+        self.events.push(Event::Advanced { units: units.to_vec(), to: dest });
+        self.trigger_mines(units, &[dest]);
+        self.claim_path(units, &[dest]);
+        Ok(())
+    }
 }
 
 // A broken unit in cover has to move if it finds itself adjacent to an enemy unit.
 // Handles: Phase::Rout
+impl BattleManager {
+    /// SL4.6 Orders a broken unit to rout along the given path. A routing
+    /// unit may spend its full MF allowance, but may never move into a hex
+    /// occupied by the enemy.
+    fn rout(
+        &mut self,
+        ars: Ipv4Addr,
+        path: &[HexId],
+    ) -> Result<(), RuleBreak> {
+        let origin = *self.locations.get(&ars).ok_or(RuleBreak::E0001)?;
+        // A18.1 Vehicles with broken crews stay put rather than rout.
+        if self.phase != Phase::Rout
+            || !self.broken(&ars)
+            || self.vehicles.contains_key(&ars)
+        {
+            return Err(RuleBreak::E4600);
+        }
+        self.check_dismounted(&ars)?;
+        let side = self.sides[&ars];
+        let cost = self.path_cost(side, &origin, path)?;
+        let spent = self.mf_spent.get(&ars).copied().unwrap_or(0);
+        if spent.saturating_add(cost) > self.mf_allowance(&ars) {
+            return Err(RuleBreak::E5200);
+        }
+        let dest = *path.last().unwrap_or(&origin);
+        self.check_stacking(side, &[ars], &dest)?;
+        *self.mf_spent.entry(ars).or_default() += cost;
+        self.locations.insert(ars, dest);
+        self.events.push(Event::Routed { ars, path: path.to_vec() });
+        self.trigger_mines(&[ars], path);
+        Ok(())
+    }
+}
+
 fn broken_in_cover() {
     // For every broken unit, check that no enemy unit is in an adjacent hex.
     // If an enemy is adjacent then the unit has to be routed into another cover.
@@ -495,29 +2287,6 @@ fn order_defensive_fire() {
     todo!("Determine which units that are viable targets based on LOS")
 }
 
-fn order_moved_fire(unit: Unit, target: Unit) {
-    // The unit's firepower is halfed because it moved during Phase::Movement
-    // and thus did not have time to properly find targets and aim at them.
-    // unit.firepower / 2
-    todo!("Calculate units halved firepower, rounded up.")
-}
-
-fn order_aimed_fire(unit: Unit, target: Unit) {
-    // The unit neither prep fired nor moved. This enable the unit to fire at
-    // its full firepower.
-    // Unit's which Phase::PrepFire may not fire during the advancing fire phase.
-    if unit.has_moved() {
-        String::from("The unit moved and thus cannot fire during the advanced fire phase");
-    } else {
-        determine_fire_effect(unit, target);
-    }
-    todo!("Fire with full firepower at enemy unit.")
-}
-
-fn determine_fire_effect(attacker: Unit, defender: Unit) {
-    todo!()
-}
-
 // Player::Defender may order any unbroken units to fire at any enemy units
 // that are either: 1) in their current LOS, or, 2) moved through their LOS
 // during Phase::Movement. All targets that passed through the LOS are marked
@@ -540,6 +2309,163 @@ enum OrderResponse {
     PrepFired,
 }
 
+/// RuleBreak
+/// An order that would break the rules is refused. Each rule break is coded
+/// after the paragraph of the rule that is broken, E5100 for instance refers
+/// to SL5.1, while addendum rules are coded after their AX.Y label. Codes in
+/// the E0xxx range refer to orders that do not make sense on the battlefield.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RuleBreak {
+    E0001, // The order refers to a unit that is not on the battlefield.
+    E0002, // The order refers to a hex that is not on the map.
+    E0003, // The order refers to a support weapon that is not in the game.
+    E0004, // The same unit, or weapon, is referred to more than once.
+    E0005, // The order to move does not name any hex to move through.
+    E1810, // Enemy vehicles, and the guns mounted in them, cannot be captured.
+    E2300, // The target hex is beyond double the range of the firer.
+    E2400, // Broken units may not fire.
+    E4000, // Fire is not allowed during the current phase.
+    E4100, // Only broken units may rally, and only during Phase::Rally.
+    E4110, // A broken unit may only rally when stacked with a leader.
+    E4120, // A unit may only attempt to rally once per phase.
+    E4200, // Only Player::Attacker may fire during Phase::PrepFire.
+    E4300, // Only Player::Attacker's unbroken units move in Phase::Movement.
+    E4400, // Only Player::Defender may fire during Phase::DefensiveFire.
+    E4500, // Units that prep fired may not fire during Phase::AdvancingFire.
+    E4600, // Only broken units may rout, and only during Phase::Rout.
+    E4700, // Only Player::Attacker's unbroken units advance, once per phase.
+    E5100, // Units that prep fired may not move in the ensuing Phase::Movement.
+    E5200, // The unit does not have enough MF left to complete the move.
+    E5600, // Units may only move into an enemy occupied hex in Phase::Advance.
+    E5730, // The unit carries too many portage points to advance.
+    E5740, // The unit carried too many portage points to fire the weapon.
+    E5750, // The unit has already fired a weapon of the same family.
+    E6100, // The move would leave the hex stacked beyond the stacking limit.
+    E7100, // The target hex is not within the line of sight of the firer.
+    A1100, // There is no target to damage.
+    A1200, // Water can only be crossed by way of a bridge.
+    A1810, // Weapons mounted in a vehicle cannot be removed from it.
+    A1820, // Vehicles move on their own, in vehicle MF.
+    A1821, // The terrain is impassable to the vehicle.
+    A1830, // The vehicle has no turret.
+    A1831, // The target is outside the covered arc of the vehicle.
+    A1840, // The unit cannot board, or leave, the vehicle.
+    A1841, // Passengers only move along with their vehicle.
+    A2170, // The unit is not a reinforcement that has arrived off board.
+    A2171, // Reinforcements may only enter the map through their entry hexes.
+    A4700, // Demolition charges are placed, and cannot be fired.
+    A4710, // Demolition charges may only be placed in an adjacent hex.
+    A5100, // Units may only move from one hex into an adjacent hex.
+    A5200, // The units of a stack must all start moving from the same hex.
+    A5700, // The weapon is not in the hex, or the units are not stacked.
+    A5800, // Only mines can be laid as minefields.
+    A5900, // Units may only exit the map from a hex along that edge.
+    A7500, // A mortar fires on its own, and cannot be part of a fire group.
+    A7510, // The target is within the minimum range of the mortar.
+    A7520, // The spotter must be an unbroken friendly unit on foot.
+    A7600, // Only the leader carrying the radio may call for a fire mission.
+    A7620, // The artillery module has no fire missions left.
+    A7700, // Only squads on foot may throw smoke grenades.
+    A7710, // Smoke grenades may only be thrown into the same or an adjacent hex.
+    A7720, // A squad may only attempt to place smoke once per phase.
+    A7730, // Only mortars may fire smoke rounds.
+    A8100, // A fire group must consist of units in the same or adjacent hexes.
+    A8200, // A unit may only fire once in each fire phase.
+    A8300, // Support weapons must be fired by a member of the fire group.
+    A8400, // A support weapon that has malfunctioned cannot be fired.
+    A8500, // There are no enemy units to fire on in the target hex.
+    A8600, // The members of a fire group must all belong to the same side.
+    A8700, // The weapon has no armor penetration to fire at vehicles with.
+    A8800, // The vehicle has been immobilized.
+    A8810, // The crew of the vehicle is shocked.
+    A8900, // A flamethrower cannot be part of a fire group.
+    A8910, // The target is beyond the range of the flamethrower.
+    A8930, // The hex is ablaze and cannot be entered.
+}
+
+impl fmt::Display for RuleBreak {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            RuleBreak::E0001 => "unknown unit",
+            RuleBreak::E0002 => "unknown hex",
+            RuleBreak::E0003 => "unknown support weapon",
+            RuleBreak::E0004 => "the same unit or weapon is used twice",
+            RuleBreak::E0005 => "there is no hex to move to",
+            RuleBreak::E1810 => "vehicles cannot be captured",
+            RuleBreak::E2300 => "the target is beyond double range",
+            RuleBreak::E2400 => "the unit is broken and may not fire",
+            RuleBreak::E4000 => "no fire is allowed during this phase",
+            RuleBreak::E4100 => "only broken units rally, in the rally phase",
+            RuleBreak::E4110 => "there is no leader to rally the unit",
+            RuleBreak::E4120 => "the unit already attempted to rally",
+            RuleBreak::E4200 => "only the attacker may prep fire",
+            RuleBreak::E4300 => "only the attacker's unbroken units may move",
+            RuleBreak::E4400 => "only the defender may fire defensively",
+            RuleBreak::E4500 => "the unit prep fired and may not fire again",
+            RuleBreak::E4600 => "only broken units may rout",
+            RuleBreak::E4700 => "the unit may not advance",
+            RuleBreak::E5100 => "the unit prep fired and may not move",
+            RuleBreak::E5200 => "the unit does not have enough MF left",
+            RuleBreak::E5600 => "the hex is occupied by enemy units",
+            RuleBreak::E5730 => "the unit is too encumbered to advance",
+            RuleBreak::E5740 => "the unit was too encumbered while moving",
+            RuleBreak::E5750 => "a weapon of that family was already fired",
+            RuleBreak::E6100 => "the hex would be overstacked",
+            RuleBreak::E7100 => "the target is not within line of sight",
+            RuleBreak::A1100 => "there is no such target",
+            RuleBreak::A1200 => "the hex is water",
+            RuleBreak::A1810 => "the weapon is mounted in a vehicle",
+            RuleBreak::A1820 => "vehicles must move on their own",
+            RuleBreak::A1821 => "the terrain is impassable to the vehicle",
+            RuleBreak::A1830 => "the vehicle has no turret",
+            RuleBreak::A1831 => "the target is outside the covered arc",
+            RuleBreak::A1840 => "the unit cannot board or leave the vehicle",
+            RuleBreak::A1841 => "the unit is riding in a vehicle",
+            RuleBreak::A2170 => "the unit has not arrived off board",
+            RuleBreak::A2171 => "the hex is not an entry hex",
+            RuleBreak::A4700 => "demolition charges cannot be fired",
+            RuleBreak::A4710 => "the charge cannot be placed there",
+            RuleBreak::A5100 => "the hexes moved through are not adjacent",
+            RuleBreak::A5200 => "the stack is not in a single hex",
+            RuleBreak::A5700 => "the weapon cannot be reached",
+            RuleBreak::A5800 => "the weapon is not a mine",
+            RuleBreak::A5900 => "the hex is not along that edge of the map",
+            RuleBreak::A7500 => "mortars fire on their own",
+            RuleBreak::A7510 => "the target is within minimum range",
+            RuleBreak::A7520 => "the spotter cannot direct the fire",
+            RuleBreak::A7600 => "the radio is not carried by a leader",
+            RuleBreak::A7620 => "there are no fire missions left",
+            RuleBreak::A7700 => "the unit cannot throw smoke grenades",
+            RuleBreak::A7710 => "the hex is out of reach of smoke grenades",
+            RuleBreak::A7720 => "the squad already attempted to place smoke",
+            RuleBreak::A7730 => "the weapon cannot fire smoke rounds",
+            RuleBreak::A8100 => "the fire group is not in adjacent hexes",
+            RuleBreak::A8200 => "the unit has already fired this phase",
+            RuleBreak::A8300 => "the weapon is not carried by the fire group",
+            RuleBreak::A8400 => "the weapon has malfunctioned",
+            RuleBreak::A8500 => "there is no enemy in the target hex",
+            RuleBreak::A8600 => "the fire group mixes units of both sides",
+            RuleBreak::A8700 => "the weapon cannot penetrate armor",
+            RuleBreak::A8800 => "the vehicle is immobilized",
+            RuleBreak::A8810 => "the crew is shocked",
+            RuleBreak::A8900 => "flamethrowers fire on their own",
+            RuleBreak::A8910 => "the target is beyond flamethrower range",
+            RuleBreak::A8930 => "the hex is ablaze",
+        };
+        write!(f, "{:?}: {}", self, description)
+    }
+}
+
+/// Checks that an order does not refer to the same unit twice.
+fn check_distinct(units: &[Ipv4Addr]) -> Result<(), RuleBreak> {
+    let distinct: BTreeSet<&Ipv4Addr> = units.iter().collect();
+    if distinct.len() == units.len() {
+        Ok(())
+    } else {
+        Err(RuleBreak::E0004)
+    }
+}
+
 fn response_to_order(response: OrderResponse) -> String {
     match response {
         OrderResponse::CopyThat => String::from("Copy that, Sir! Executing order."),
@@ -579,10 +2505,18 @@ fn move_to_dest() {
 
 /// Markers are used to indicate state and help the player get a sense of what's
 /// going on in the battlefield.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Marker {
     PrepFire,       // The unit fired during the PrepFire phase.
+    Moved,          // The unit moved during the Movement phase.
+    Fired,          // The unit fired during the current fire phase.
+    Advanced,       // The unit advanced during the Advance phase.
     EnPassant, // During its movement phase, the unit passed through one or more enemy unit's LOS .
     ProximityPanic, // The marker indicates that a broken unit has to be moved because of enemy proximity.
+    Shocked,        // A8.8 The crew of the vehicle is shocked by a hit.
+    Immobilized,    // A8.8 The vehicle has been immobilized by a hit.
+    SmokeGrenade,   // A7.73 The squad attempted to place smoke this phase.
+    Rallied,        // SL4.1 The unit attempted to rally this phase.
 }
 
 // Fire Phase
@@ -593,8 +2527,20 @@ fn terrain_effect_movement(hex: Hex) -> u8 {
 }
 
 // Game Loop
-fn game_loop() {
-    todo!("Implement a state machine which steps throug the game phases.");
+/// Steps the battle through its phases until the scenario is over. At the
+/// start of every phase, the players give their orders through `orders`.
+fn game_loop(
+    scenario: &mut ScenarioManager,
+    bm: &mut BattleManager,
+    mut orders: impl FnMut(&mut BattleManager),
+) -> GameOver {
+    loop {
+        orders(bm);
+        bm.end_phase();
+        if let Some(game_over) = scenario.update_scenario_turn(bm) {
+            return game_over;
+        }
+    }
 }
 
 // Fire
@@ -623,10 +2569,10 @@ fn can_penetrate() -> bool {
     todo!()
 }
 
-fn terrain_effect_combat(hex: Hex) -> u8 {
+fn terrain_effect_combat(hex: &Hex) -> i8 {
     // types of terrain in the hex
     // accumulate terrain types to calculate effect
-    todo!()
+    hex.terrain.iter().map(Terrain::combat_modifier).sum()
 }
 
 // Close Combat
@@ -649,15 +2595,34 @@ fn close_combat() {
 // TODO: mechanism for limiting condition to _ensuing_.
 // TODO: _consider using TypeState to assert rules.
 impl BattleManager {
-    fn unit_can_move(&self, u: &Unit, p: &Phase) {
-        todo!()
-        /*
-        // Check the HashMap, did the unit move during the prep fire phase?
-        match !self.Moved.contains_key(p, u) {
-            Ok() => return true,
-            Err(e) => return RuleBreak::E5100, //UNIT_TRYING_TO_MOVE_POST_PREP_FIRED
+    /// Checks that the unit is on the map, and may be ordered to move.
+    fn unit_can_move(&self, ars: &Ipv4Addr) -> Result<(), RuleBreak> {
+        // A21.7 Units held off board may only be ordered to enter the map.
+        if !self.locations.contains_key(ars) {
+            return Err(RuleBreak::E0001);
+        }
+        self.check_may_move(ars)
+    }
+
+    /// Checks that the unit may be ordered to move in this phase, wherever
+    /// it is.
+    fn check_may_move(&self, ars: &Ipv4Addr) -> Result<(), RuleBreak> {
+        let side = *self.sides.get(ars).ok_or(RuleBreak::E0001)?;
+        // SL4.3 Player::Attacker may order unbroken units to move.
+        if self.phase != Phase::Movement
+            || self.player(side) != Player::Attacker
+            || self.broken(ars)
+        {
+            return Err(RuleBreak::E4300);
+        }
+        // Check the markers, did the unit fire during the prep fire phase?
+        if self.has_marker(ars, Marker::PrepFire) {
+            return Err(RuleBreak::E5100);
+        }
+        if self.has_marker(ars, Marker::Shocked) {
+            return Err(RuleBreak::A8810);
         }
-        */
+        Ok(())
     }
 }
 
@@ -667,31 +2632,156 @@ impl BattleManager {
 // carried, enemy fire, etc.
 
 // SL5.3.1 Unites can move over a hex containing other units.
-// SL5.3.2 One hex can contain more than one unit but not more than ???
-// TODO: decide how many units that can be stacked in one hex.
+// SL5.3.2 One hex can contain more than one unit but not more than the
+// stacking limit of the scenario. See SL6.
 
 // SL5.4 All counters, except vehicles, have movement factors (MF) alloted to them:
 // Support Weapons must be carried by Squad, Leader, or Vehicle
-// TODO: rules to handle movement of support weapons by leader, squad, or vehicle.
 enum MF {
     Squad = 4, // SL5.41
     Leader = 6, // SL5.42
-               // Vehicles MF is a field of vechicle structs.
-               // TODO: implement Vehicle struct with MF.
+               // Vehicles MF is a field of vehicle structs, see `Vehicle::mf`.
 }
 
 // SL5.44 If a squad spends the entire Phase::Movement in the company with
 // a leader, then it will recive a MF bonus of 2.
-// TODO: handle led by Leader and stacked with Leader for entire movement phase.
-fn calcuate_mf_bonus(s: Squad<Unphased>) {
-    todo!()
-    /*
-    if s.stacked_with_leader() {
-        2
-    } else {
-        0
+// A squad is in the company of a leader if the two started moving together,
+// and have not moved apart since. See `BattleManager::move_stack`.
+fn calcuate_mf_bonus(bm: &BattleManager, s: &Squad<Unphased>) -> u8 {
+    match bm.escorts.get(&s.ars) {
+        Some(leader) if !bm.broken(leader) => 2,
+        _ => 0,
+    }
+}
+
+impl BattleManager {
+    /// Returns the number of MF a unit may spend during Phase::Movement.
+    fn mf_allowance(&self, ars: &Ipv4Addr) -> u8 {
+        let mf = if let Some(squad) = self.squads.get(ars) {
+            MF::Squad as u8 + calcuate_mf_bonus(self, squad)
+        } else if self.leaders.contains_key(ars) {
+            MF::Leader as u8
+        } else if let Some(vehicle) = self.vehicles.get(ars) {
+            vehicle.mf
+        } else {
+            0
+        };
+        mf.saturating_sub(self.portage_penalty(ars))
+    }
+
+    /// Orders a stack of units, all located in the same hex, to move along
+    /// the given path of adjacent hexes. The MF cost of every hex entered is
+    /// deducted from each unit in the stack, and the order is refused if any
+    /// unit in the stack lacks the MF to follow the full path.
+    fn move_stack(
+        &mut self,
+        stack: &[Ipv4Addr],
+        path: &[HexId],
+    ) -> Result<u8, RuleBreak> {
+        let first = stack.first().ok_or(RuleBreak::E0001)?;
+        let start = *self.locations.get(first).ok_or(RuleBreak::E0001)?;
+        check_distinct(stack)?;
+        if path.is_empty() {
+            return Err(RuleBreak::E0005);
+        }
+        for ars in stack {
+            self.unit_can_move(ars)?;
+            self.check_dismounted(ars)?;
+            if self.vehicles.contains_key(ars) {
+                return Err(RuleBreak::A1820);
+            }
+            if self.locations.get(ars) != Some(&start) {
+                return Err(RuleBreak::A5200);
+            }
+        }
+        let side = self.sides[first];
+        let cost = self.path_cost(side, &start, path)?;
+
+        // SL5.44 A squad keeps the company of a leader as long as they move
+        // together. A squad that starts moving in the company of a leader
+        // enjoys the MF bonus for the rest of the phase.
+        let mut escorts = self.escorts.clone();
+        escorts.retain(|squad, leader| {
+            stack.contains(squad) == stack.contains(leader)
+        });
+        let leader = stack.iter().find(|ars| self.leaders.contains_key(ars));
+        for ars in stack.iter().filter(|ars| self.squads.contains_key(ars)) {
+            let moved = self.mf_spent.get(ars).copied().unwrap_or(0) > 0;
+            if let (Some(leader), false) = (leader, moved) {
+                escorts.insert(*ars, *leader);
+            }
+        }
+        let escorts = std::mem::replace(&mut self.escorts, escorts);
+        for ars in stack {
+            let spent = self.mf_spent.get(ars).copied().unwrap_or(0);
+            if spent.saturating_add(cost) > self.mf_allowance(ars) {
+                self.escorts = escorts;
+                return Err(RuleBreak::E5200);
+            }
+        }
+
+        let destination = *path.last().unwrap_or(&start);
+        if let Err(rule_break) = self.check_stacking(side, stack, &destination)
+        {
+            self.escorts = escorts;
+            return Err(rule_break);
+        }
+        for ars in stack {
+            *self.mf_spent.entry(*ars).or_default() += cost;
+            self.locations.insert(*ars, destination);
+            self.add_marker(*ars, Marker::Moved);
+            let portage = self.portage(ars);
+            let carried = self.moved_portage.entry(*ars).or_default();
+            *carried = portage.max(*carried);
+        }
+        self.events
+            .push(Event::Moved { units: stack.to_vec(), path: path.to_vec() });
+        self.trigger_mines(stack, path);
+        self.claim_path(stack, path);
+        Ok(cost)
+    }
+
+    /// Returns the total MF cost for a unit of the given side to move from
+    /// the start hex along the path.
+    fn path_cost(
+        &self,
+        side: Side,
+        start: &HexId,
+        path: &[HexId],
+    ) -> Result<u8, RuleBreak> {
+        let mut cost: u8 = 0;
+        let mut from = *start;
+        let mut road = 0;
+        for to in path {
+            let hex = self.map.hex(to).ok_or(RuleBreak::E0002)?;
+            if self.map.distance(&from, to) != Some(1) {
+                return Err(RuleBreak::A5100);
+            }
+            // A8.93 Burning hexes cannot be entered.
+            if hex.terrain.contains(&Terrain::Blaze) {
+                return Err(RuleBreak::A8930);
+            }
+            if hex.terrain.contains(&Terrain::Water) {
+                return Err(RuleBreak::A1200);
+            }
+            // SL5.6 Units may only move into a hex containing an enemy unit
+            // during Phase::Advance.
+            if self.units_in(to).iter().any(|ars| self.sides[ars] != side) {
+                return Err(RuleBreak::E5600);
+            }
+            // SL5.52 Moving from one road hex to another only costs 1 MF for
+            // every second road hex traversed.
+            if self.map.along_road(&from, to) {
+                road = (road + 1) % 2;
+                cost = cost.saturating_add(road);
+            } else {
+                road = 0;
+                cost = cost.saturating_add(self.map.movement_cost(&from, to));
+            }
+            from = *to;
+        }
+        Ok(cost)
     }
-    */
 }
 
 // SL5.5 Moving into a hex has a MF cost, depending on the type of terrain.
@@ -699,6 +2789,7 @@ fn calcuate_mf_bonus(s: Squad<Unphased>) {
 // SL5.52 A unit moving from one road hex to another will only pay 1 MF for every second
 // road hex it traverses.
 // TODO: read up on enums and data types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Terrain {
     OpenGround,
     Shellhole,
@@ -709,6 +2800,15 @@ enum Terrain {
     EnterBuilding,
     WithinBuilding,
     OverWall,
+    Bunker,
+    Rubble,
+    // A8.93 The hex is on fire. Burning hexes block LOS, and cannot be
+    // entered, see `BattleManager::path_cost`, until the blaze burns out,
+    // see `BattleManager::burn`.
+    Blaze,
+    // A1.2 Water can only be crossed by way of a bridge, and cannot be
+    // entered once the bridge is destroyed.
+    Water,
 }
 
 impl Terrain {
@@ -725,6 +2825,85 @@ impl Terrain {
             Terrain::EnterBuilding => 2,
             Terrain::WithinBuilding => 2,
             Terrain::OverWall => 1,
+            Terrain::Bunker => 2,
+            Terrain::Rubble => 2,
+            Terrain::Blaze => 0,
+            Terrain::Water => 0,
+        }
+    }
+
+    /// A18.2 The vehicle MF cost of entering the terrain, or None if the
+    /// terrain is impassable to vehicles of that kind.
+    fn vehicle_movement_cost(&self, locomotion: Locomotion) -> Option<u8> {
+        use Locomotion::*;
+        match (self, locomotion) {
+            (Terrain::OpenGround | Terrain::Wheatfield, Wheeled) => Some(2),
+            (Terrain::OpenGround | Terrain::Wheatfield, _) => Some(1),
+            (Terrain::Shellhole, Tracked) => Some(2),
+            (Terrain::Shellhole, HalfTracked) => Some(3),
+            (Terrain::Shellhole, Wheeled) => Some(4),
+            (Terrain::OnRoad | Terrain::OntoRoad, _) => Some(1),
+            (Terrain::Woods, Tracked) => Some(4),
+            (Terrain::Woods, HalfTracked) => Some(6),
+            (Terrain::OverWall, Tracked | HalfTracked) => Some(2),
+            (Terrain::Woods | Terrain::OverWall, Wheeled) => None,
+            (Terrain::Rubble, Tracked) => Some(4),
+            (Terrain::Rubble, HalfTracked | Wheeled) => None,
+            (
+                Terrain::EnterBuilding
+                | Terrain::WithinBuilding
+                | Terrain::Bunker
+                | Terrain::Blaze
+                | Terrain::Water,
+                _,
+            ) => None,
+        }
+    }
+
+    /// The Terrain Effect Modifier (TEM) added to the dice roll of any fire
+    /// directed at a unit that occupies the terrain.
+    fn combat_modifier(&self) -> i8 {
+        match self {
+            Terrain::Shellhole => 1,
+            Terrain::Woods => 1,
+            Terrain::EnterBuilding | Terrain::WithinBuilding => 2,
+            Terrain::OverWall => 1,
+            Terrain::Bunker => 3,
+            Terrain::Rubble => 2,
+            _ => 0,
+        }
+    }
+
+    /// SL7.3 LOS extends into woods and buildings but not through them.
+    fn blocks_los(&self) -> bool {
+        matches!(
+            self,
+            Terrain::Woods
+                | Terrain::EnterBuilding
+                | Terrain::WithinBuilding
+                | Terrain::Blaze
+        )
+    }
+
+    /// A8.93 Returns true if the terrain can be set on fire.
+    fn burnable(&self) -> bool {
+        matches!(
+            self,
+            Terrain::Woods
+                | Terrain::Wheatfield
+                | Terrain::EnterBuilding
+                | Terrain::WithinBuilding
+        )
+    }
+
+    /// A8.94 The terrain left behind once the terrain has burned down.
+    fn burned(&self) -> Terrain {
+        match self {
+            Terrain::Woods | Terrain::Wheatfield => Terrain::OpenGround,
+            Terrain::EnterBuilding | Terrain::WithinBuilding => {
+                Terrain::Rubble
+            }
+            terrain => *terrain,
         }
     }
 }
@@ -750,125 +2929,7344 @@ impl Squad<Unphased> {
 // but may only move into a hex containing an enemy unit during Phase:Advance.
 // TODO: add fn which prohibts movements into enemy controlled hex.
 
-// SL5.70-Carrying support weapons and portage costs.
-//
+// A5.8 Minefields are laid in hexes during the setup of a scenario, and are
+// hidden from the opposing side until they attack. Every time a unit enters
+// a mined hex, or spends MF within it, the minefield attacks the unit.
+// Anti-personnel mines attack infantry on the Infantry Fire Table, without
+// any terrain effect modifiers, and infantry broken by the mines stop in the
+// mined hex. Anti-tank mines only affect vehicles, and resolve their attack
+// on the damage table against the weakest armor of the vehicle. Mines do not
+// tell friend from foe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Minefield {
+    // Either WeaponType::APMine or WeaponType::ATMine.
+    weapon: WeaponType,
+    // The firepower of anti-personnel mines, or the armor penetration of
+    // anti-tank mines.
+    strength: u8,
+    owner: Side,
+    revealed: bool,
+}
 
-// SL5.73 Regardless of terrain and/or weapons portage, a squad or crew may
-// always carry up to 5 (TODO: turn into global CONST?) portage points, and
-// a leader carry 3 portage points, up to 1 hex during the `Phase::Advance`.
-fn carry_during_advance_phase(c: impl Carrier, p: Phase) {
-    todo!()
-    /*
-    if c::Squad && u.portage < 6 && p == Phase::Advance {
-        // TODO: instruct BattleManager / SceneManger to move unit one hex.
-    } else if u::Leader && u.portage < 4 && p::Advance {
-        // TODO: instruct BattleManager / SceneManger to move unit one hex.
-    } else {
-        // TODO: Improve mechanism for Informing General.
-        println!("Unit is too encoumbered to move.")
+impl Minefield {
+    fn new(weapon: WeaponType, strength: u8, owner: Side) -> Minefield {
+        Minefield { weapon, strength, owner, revealed: false }
     }
-    */
 }
 
-// SL5.74 A `Squad` carrying 4 or more portage points, or a `Leader` carrying 2
-// or more portage points (TODO: turn into global CONST), during the
-// `Phase::Movement` may _not_ fire a `SupportWeapon` during the _ensuing_
-// `Phase::AdvancedFire`.
-fn may_fire_support_weapon(u: Unit, s: SupportWeapon, p: Phase) {
-    todo!()
-    /*
-    if p::AdvanceFire && (u::Leader.portage < 2 || u::Squad.portage < 4) {
-        // May fire support weapon.
-        // TODO: issue to handler responsible for the firing of weapons.
-    } else {
-        // TODO: add "Inform General" functionality to inform when commands are
-        // break a rule. Also log to BattleManager?
-        //
-        println!("Unit {:?} may not fire the support weapon {:?} because the unit
-        exceeded the allowed portage points during the movement phase {:?}.", u, s, p);
-    }
-    */
+/// A5.81 The battlefield as seen by one side. Enemy units are only seen
+/// within the LOS of the side's own units, and enemy minefields remain hidden
+/// until they have been revealed.
+#[derive(Debug, Clone, PartialEq)]
+struct View {
+    side: Side,
+    units: BTreeMap<Ipv4Addr, HexId>,
+    minefields: BTreeMap<HexId, Minefield>,
 }
 
-// Handled by A1.2.
-// TODO: move this to fire section.
-// SL5.75 An infantry unit may only fire one _type_ of `SupportWeapon` in the
-// same Phase::FirePhase, Phase::PrepFire, or Phase::AdvanceFire.
-// WeaponType::{LMG, MMG, HMG} are all considred weapons of the same _type_.
-// TODO: enforce this how? Perhaps by each unit tracking which weapon type
-// it has fired in the same fire phase:
-//
-// ```
-// Unit.fire(w: WeaponType) {
-//  if !Unit.weapon_types_fired.contains(w) {
-//      w.fire();
-//      Unit.weapon_types_fired.insert(w)
-//  }
-// }
-// ```
-// ...or let a battlemanager track and decide if a unit is allowed to fire
-// a particular type of support weapon.
+impl BattleManager {
+    /// A5.8 Lays a minefield in the hex.
+    fn lay_minefield(
+        &mut self,
+        hex: HexId,
+        minefield: Minefield,
+    ) -> Result<(), RuleBreak> {
+        self.map.hex(&hex).ok_or(RuleBreak::E0002)?;
+        if minefield.weapon.family() != WeaponFamily::Mine {
+            return Err(RuleBreak::A5800);
+        }
+        self.minefields.insert(hex, minefield);
+        Ok(())
+    }
 
-// SL5.76 Portage costs are assumed to refer to `Squad` usage costs.
-// TODO: Consider how `Leader` should be handled with relation to portage cost.
+    /// A5.81 Returns the battlefield as the side sees it.
+    fn view(&self, side: Side) -> View {
+        let seen = |hex: &HexId| {
+            self.locations.iter().any(|(ars, at)| {
+                self.sides[ars] == side && self.map.los(at, hex)
+            })
+        };
+        View {
+            side,
+            units: self
+                .locations
+                .iter()
+                .filter(|(ars, hex)| self.sides[*ars] == side || seen(hex))
+                .map(|(ars, hex)| (*ars, *hex))
+                .collect(),
+            minefields: self
+                .minefields
+                .iter()
+                .filter(|(_, m)| m.owner == side || m.revealed)
+                .map(|(hex, m)| (*hex, *m))
+                .collect(),
+        }
+    }
 
-////////////////////////////////////////////////////////////////////////////////
-/// SL6. Stacking
+    /// A5.8 Attacks the units with every minefield along the path they moved
+    /// through.
+    fn trigger_mines(&mut self, units: &[Ipv4Addr], path: &[HexId]) {
+        let mut moving = units.to_vec();
+        for (index, hex) in path.iter().enumerate() {
+            let Some(minefield) = self.minefields.get(hex).copied() else {
+                continue;
+            };
+            moving.retain(|ars| self.locations.contains_key(ars));
+            let targets: Vec<Ipv4Addr> = moving
+                .iter()
+                .filter(|ars| {
+                    let vehicle = self.vehicles.contains_key(ars);
+                    let riding = self.passengers.contains_key(ars);
+                    match minefield.weapon {
+                        WeaponType::ATMine => vehicle,
+                        _ => !vehicle && !riding,
+                    }
+                })
+                .copied()
+                .collect();
+            if targets.is_empty() {
+                continue;
+            }
+            let roll = self.dice.roll();
+            self.events.push(Event::MineAttack {
+                hex: *hex,
+                units: targets.clone(),
+                roll,
+            });
+            if !minefield.revealed {
+                if let Some(m) = self.minefields.get_mut(hex) {
+                    m.revealed = true;
+                }
+                self.events.push(Event::MinefieldRevealed(*hex));
+            }
+            let stopped: Vec<Ipv4Addr> = match minefield.weapon {
+                WeaponType::ATMine => {
+                    let mut stopped = Vec::new();
+                    for ars in targets {
+                        let armor = Target::armor(&self.vehicles[&ars]);
+                        let total = roll.total() as i8 + armor as i8
+                            - minefield.strength as i8;
+                        let result = damage_table(total);
+                        self.apply_armor_result(ars, result);
+                        if result == ArmorResult::Immobilized {
+                            stopped.extend(self.riders(&ars));
+                            stopped.push(ars);
+                        }
+                    }
+                    stopped
+                }
+                _ => {
+                    let result = infantry_fire_table(
+                        minefield.strength,
+                        roll.total() as i8,
+                    );
+                    self.apply_fire_result(result, targets)
+                        .into_iter()
+                        .filter(|(_, outcome)| *outcome == FireOutcome::Broken)
+                        .map(|(ars, _)| ars)
+                        .collect()
+                }
+            };
+            moving.retain(|ars| !stopped.contains(ars));
+            self.stop(&stopped, path, index);
+        }
+    }
 
-////////////////////////////////////////////////////////////////////////////////
-/// SL7. Line of Sight (LOS)
-// SL7.1 Line of sight - what a unit can "see" and fire upon, is measured from
-// the center dot of the hex (of the unit) and a straight line to the center dot
-// of the target hex.
-// If an obstacle on the map can be observed on both sides of this line then the
-// LOS is obstructed.
-fn los_obstructed(orig: Hex, dest: Hex) -> bool {
-    // TODO: setup a database to hold all hex data, including LOS
-    todo!("Look-table or database hit to get LOS for passed hexes.")
+    /// A5.8 Stops the units in the mined hex at the index along the path they
+    /// moved through, or in the last hex before it where they can stop
+    /// without overstacking, and cuts the move recorded for them short. Units
+    /// that cannot stop in any of these hexes without overstacking stop in
+    /// the mined hex.
+    fn stop(&mut self, units: &[Ipv4Addr], path: &[HexId], index: usize) {
+        let Some(side) = units.first().map(|ars| self.sides[ars]) else {
+            return;
+        };
+        let index = (0..=index)
+            .rev()
+            .find(|i| self.check_stacking(side, units, &path[*i]).is_ok())
+            .unwrap_or(index);
+        for ars in units {
+            self.locations.insert(*ars, path[index]);
+        }
+        if index + 1 == path.len() {
+            return;
+        }
+        let moved = |event: &Event| match event {
+            Event::Moved { units: moved, path: full } => {
+                full == path && moved.iter().any(|ars| units.contains(ars))
+            }
+            Event::Routed { ars, path: full } => {
+                full == path && units.contains(ars)
+            }
+            _ => false,
+        };
+        let Some(at) = self.events.iter().rposition(moved) else {
+            return;
+        };
+        let path = path[..=index].to_vec();
+        match &mut self.events[at] {
+            Event::Moved { units: moved, path: full } => {
+                if moved.iter().all(|ars| units.contains(ars)) {
+                    *full = path;
+                } else {
+                    moved.retain(|ars| !units.contains(ars));
+                    let units = units.to_vec();
+                    self.events.insert(at + 1, Event::Moved { units, path });
+                }
+            }
+            Event::Routed { path: full, .. } => *full = path,
+            _ => {}
+        }
+    }
 }
 
-// SL7.2 LOS is only blocked if something in the hex is actually blocking it.
-// It's not enough that there's woods or a build in the hex, the LOS actually
-// has to be blocked.
-// TODO: fn to verify LOS obstructed by gfx in hex.
-// Weapon's fire may be traced through a hex containg units without affecting
-// them, if the firer prefers this.
-// TODO: option to fire through without affecting unit blocking LOs.
-// TODO: Exception: 17.6
-
-// SL7.3 LOS extends into woods and buildings but not through them.
-// TODO: verify that LOS is obstructed by second woods/building hex so that
-// fire is not able to pass through woods/buildings.
+// A5.9 Units may exit the map during Phase::Movement, from a hex along one of
+// the edges of the map, by spending the MF of entering a hex of open ground.
+// Units that exit the map may not return, and count towards the exit victory
+// conditions of a scenario. Passengers exit along with their vehicle.
+const EXIT_COST: u8 = 1;
 
-// SL7.4
+/// The four edges of the map. The boards are joined north to south.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Edge {
+    North,
+    East,
+    South,
+    West,
+}
 
-///////////////////////////////////////////////////////////////////////////////
-// SL8
+impl Map {
+    /// Returns true if the hex lies along the edge of the map.
+    fn on_edge(&self, hex: &HexId, edge: Edge) -> bool {
+        let Some((column, row)) = self.coordinates(hex) else {
+            return false;
+        };
+        let rows = self.boards.len() as i32 * BOARD_ROWS as i32;
+        match edge {
+            Edge::North => row == 0,
+            Edge::East => column == BOARD_COLUMNS as i32 - 1,
+            Edge::South => row == rows - 1,
+            Edge::West => column == 0,
+        }
+    }
+}
 
-///////////////////////////////////////////////////////////////////////////////
-// SL16 Defensive Fire Principles
+impl BattleManager {
+    /// A5.9 Orders a stack of units, all located in the same hex along the
+    /// edge, to exit the map by that edge.
+    fn exit_map(
+        &mut self,
+        stack: &[Ipv4Addr],
+        edge: Edge,
+    ) -> Result<(), RuleBreak> {
+        let first = stack.first().ok_or(RuleBreak::E0001)?;
+        let start = *self.locations.get(first).ok_or(RuleBreak::E0001)?;
+        for ars in stack {
+            self.unit_can_move(ars)?;
+            self.check_dismounted(ars)?;
+            if self.locations.get(ars) != Some(&start) {
+                return Err(RuleBreak::A5200);
+            }
+            if self.has_marker(ars, Marker::Immobilized) {
+                return Err(RuleBreak::A8800);
+            }
+            let spent = self.mf_spent.get(ars).copied().unwrap_or(0);
+            if spent + EXIT_COST > self.mf_allowance(ars) {
+                return Err(RuleBreak::E5200);
+            }
+        }
+        if !self.map.on_edge(&start, edge) {
+            return Err(RuleBreak::A5900);
+        }
+        let mut units = stack.to_vec();
+        for ars in stack {
+            units.extend(self.riders(ars));
+        }
+        for ars in &units {
+            *self.mf_spent.entry(*ars).or_default() += EXIT_COST;
+            self.locations.remove(ars);
+            self.exited.insert(*ars, edge);
+        }
+        self.events.push(Event::Exited { units, edge });
+        Ok(())
+    }
+}
 
-///////////////////////////////////////////////////////////////////////////////
-// SL16 Defensive Fire Principles
+// SL5.70-Carrying support weapons and portage costs.
+//
+// Every support weapon has a portage cost, expressed in portage points (PP).
+// SL5.71 A squad may carry up to 3 PP, and a leader 1 PP, without penalty.
+// Every PP carried beyond that reduces the unit's MF by one.
+const SQUAD_PORTAGE_ALLOWANCE: u8 = 3;
+const LEADER_PORTAGE_ALLOWANCE: u8 = 1;
 
-///////////////////////////////////////////////////////////////////////////////
-// SL15 Leadership
+// SL5.73 Regardless of terrain and/or weapons portage, a squad or crew may
+// always carry up to 5 portage points, and a leader carry 3 portage points,
+// up to 1 hex during the `Phase::Advance`.
+const SQUAD_ADVANCE_PORTAGE: u8 = 5;
+const LEADER_ADVANCE_PORTAGE: u8 = 3;
 
-///////////////////////////////////////////////////////////////////////////////
-// SL16 Defensive Fire Principles
+// SL5.74 A `Squad` carrying 4 or more portage points, or a `Leader` carrying 2
+// or more portage points, during the `Phase::Movement` may _not_ fire a
+// `SupportWeapon` during the _ensuing_ `Phase::AdvancedFire`.
+const SQUAD_FIRE_PORTAGE: u8 = 4;
+const LEADER_FIRE_PORTAGE: u8 = 2;
 
-///////////////////////////////////////////////////////////////////////////////
-// SL16 Defensive Fire Principles
+impl BattleManager {
+    fn carrier(&self, ars: &Ipv4Addr) -> Option<&dyn Carrier> {
+        if let Some(squad) = self.squads.get(ars) {
+            Some(squad)
+        } else if let Some(leader) = self.leaders.get(ars) {
+            Some(leader)
+        } else if let Some(vehicle) = self.vehicles.get(ars) {
+            Some(vehicle)
+        } else {
+            None
+        }
+    }
 
-///////////////////////////////////////////////////////////////////////////////
-// SL17 Machine Guns
+    /// Returns the total portage points of the support weapons carried by
+    /// the unit.
+    fn portage(&self, ars: &Ipv4Addr) -> u8 {
+        self.carriers
+            .iter()
+            .filter(|(_, carrier)| *carrier == ars)
+            .map(|(id, _)| self.weapons[id].portage)
+            .fold(0, u8::saturating_add)
+    }
 
-///////////////////////////////////////////////////////////////////////////////
-// SL18 Fate
+    /// SL5.71 Returns the MF penalty of the unit for the portage points it
+    /// carries beyond its allowance.
+    fn portage_penalty(&self, ars: &Ipv4Addr) -> u8 {
+        let allowance = self.carrier(ars).map_or(0, |c| c.portage_allowance());
+        self.portage(ars).saturating_sub(allowance)
+    }
+
+    /// SL5.73 Checks that the unit is not too encumbered to advance.
+    fn carry_during_advance_phase(
+        &self,
+        ars: &Ipv4Addr,
+    ) -> Result<(), RuleBreak> {
+        let carrier = self.carrier(ars).ok_or(RuleBreak::E0001)?;
+        if self.portage(ars) > carrier.advance_portage() {
+            return Err(RuleBreak::E5730);
+        }
+        Ok(())
+    }
+
+    /// SL5.74 Checks that the unit did not carry too many portage points
+    /// during Phase::Movement to fire a support weapon in the ensuing
+    /// Phase::AdvancingFire.
+    fn may_fire_support_weapon(
+        &self,
+        ars: &Ipv4Addr,
+    ) -> Result<(), RuleBreak> {
+        let carrier = self.carrier(ars).ok_or(RuleBreak::E0001)?;
+        let carried = self.moved_portage.get(ars).copied().unwrap_or(0);
+        if self.phase == Phase::AdvancingFire
+            && carried >= carrier.fire_portage()
+        {
+            return Err(RuleBreak::E5740);
+        }
+        Ok(())
+    }
+
+    /// Orders a unit to pick up a support weapon abandoned in its hex.
+    /// Picking up a weapon during Phase::Movement costs 1 MF.
+    fn carry(&mut self, ars: Ipv4Addr, weapon: u32) -> Result<(), RuleBreak> {
+        let hex = *self.locations.get(&ars).ok_or(RuleBreak::E0001)?;
+        self.weapons.get(&weapon).ok_or(RuleBreak::E0003)?;
+        if self.abandoned.get(&weapon) != Some(&hex) {
+            return Err(RuleBreak::A5700);
+        }
+        self.unit_can_move(&ars)?;
+        self.check_dismounted(&ars)?;
+        self.check_capture(self.sides[&ars], &weapon)?;
+        let spent = self.mf_spent.get(&ars).copied().unwrap_or(0);
+        if spent + 1 > self.mf_allowance(&ars) {
+            return Err(RuleBreak::E5200);
+        }
+        // SL6.1 The weapon counts against the stacking limit once carried.
+        self.carriers.insert(weapon, ars);
+        if let Err(rule_break) =
+            self.check_stacking(self.sides[&ars], &[], &hex)
+        {
+            self.carriers.remove(&weapon);
+            return Err(rule_break);
+        }
+        *self.mf_spent.entry(ars).or_default() += 1;
+        self.abandoned.remove(&weapon);
+        let portage = self.portage(&ars);
+        let carried = self.moved_portage.entry(ars).or_default();
+        *carried = portage.max(*carried);
+        self.events.push(Event::PickedUp { ars, weapon });
+        self.trigger_mines(&[ars], &[hex]);
+        if self.captured(&weapon) {
+            self.events.push(Event::Captured { ars, weapon });
+        }
+        Ok(())
+    }
+
+    /// Orders a unit to abandon a support weapon it carries. The weapon is
+    /// left in the unit's hex, from where any unit may pick it up again.
+    fn abandon(
+        &mut self,
+        ars: Ipv4Addr,
+        weapon: u32,
+    ) -> Result<(), RuleBreak> {
+        let hex = *self.locations.get(&ars).ok_or(RuleBreak::E0001)?;
+        if self.carriers.get(&weapon) != Some(&ars) {
+            return Err(RuleBreak::A8300);
+        }
+        if self.mounted.contains(&weapon) {
+            return Err(RuleBreak::A1810);
+        }
+        self.carriers.remove(&weapon);
+        self.abandoned.insert(weapon, hex);
+        self.events.push(Event::Abandoned { ars, weapon, hex });
+        Ok(())
+    }
+
+    /// Orders a unit to hand a support weapon over to another unit of the
+    /// same side, stacked in the same hex.
+    fn transfer(
+        &mut self,
+        weapon: u32,
+        from: Ipv4Addr,
+        to: Ipv4Addr,
+    ) -> Result<(), RuleBreak> {
+        if self.carriers.get(&weapon) != Some(&from) {
+            return Err(RuleBreak::A8300);
+        }
+        if self.mounted.contains(&weapon) {
+            return Err(RuleBreak::A1810);
+        }
+        let hex = self.locations.get(&to).ok_or(RuleBreak::E0001)?;
+        if self.locations.get(&from) != Some(hex)
+            || self.sides.get(&from) != self.sides.get(&to)
+        {
+            return Err(RuleBreak::A5700);
+        }
+        self.unit_can_move(&from)?;
+        self.unit_can_move(&to)?;
+        self.carriers.insert(weapon, to);
+        self.events.push(Event::Transferred { weapon, from, to });
+        Ok(())
+    }
+}
+
+// SL5.75 An infantry unit may only fire one _type_ of `SupportWeapon` in the
+// same Phase::FirePhase, Phase::PrepFire, or Phase::AdvanceFire.
+// WeaponType::{LMG, MMG, HMG} are all considred weapons of the same _type_,
+// see `WeaponType::family`.
+// The BattleManager tracks the weapon families each unit has fired in
+// `weapon_types_fired`, which is cleared whenever the phase changes.
+
+// SL5.76 Portage costs are assumed to refer to `Squad` usage costs.
+// TODO: Consider how `Leader` should be handled with relation to portage cost.
+
+////////////////////////////////////////////////////////////////////////////////
+// SL6. Stacking
+//
+// SL6.1 Each side may only stack a limited number of squads, leaders and
+// support weapons in a single hex. The limit is checked at the end of every
+// move, advance and rout. Units may still move through a hex that would be
+// overstacked if they stopped in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StackingLimit {
+    squads: u8,
+    leaders: u8,
+    support_weapons: u8,
+    vehicles: u8,
+}
+
+// The default limit can be tuned by scenario designers for each scenario.
+impl Default for StackingLimit {
+    fn default() -> StackingLimit {
+        StackingLimit {
+            squads: 3,
+            leaders: 4,
+            support_weapons: 4,
+            vehicles: 2,
+        }
+    }
+}
+
+impl BattleManager {
+    fn set_stacking_limit(&mut self, limit: StackingLimit) {
+        self.stacking_limit = limit;
+    }
+
+    /// Counts the squads, leaders, support weapons and vehicles that the side
+    /// has in the hex. Units given in `arriving` are counted as if they were
+    /// already in the hex.
+    fn stack_size(
+        &self,
+        side: Side,
+        hex: &HexId,
+        arriving: &[Ipv4Addr],
+    ) -> StackingLimit {
+        let mut units: BTreeSet<Ipv4Addr> = self
+            .units_in(hex)
+            .into_iter()
+            .filter(|ars| self.sides[ars] == side)
+            .collect();
+        units.extend(arriving);
+        let count = |n: usize| n.min(u8::MAX as usize) as u8;
+        StackingLimit {
+            squads: count(
+                units.iter().filter(|a| self.squads.contains_key(a)).count(),
+            ),
+            leaders: count(
+                units.iter().filter(|a| self.leaders.contains_key(a)).count(),
+            ),
+            // Weapons mounted in vehicles are counted as part of the vehicle.
+            support_weapons: count(
+                self.carriers
+                    .iter()
+                    .filter(|(id, c)| {
+                        units.contains(c) && !self.mounted.contains(id)
+                    })
+                    .count(),
+            ),
+            vehicles: count(
+                units.iter().filter(|a| self.vehicles.contains_key(a)).count(),
+            ),
+        }
+    }
+
+    /// Returns how many squads, leaders and support weapons in total that the
+    /// side has in the hex beyond the stacking limit.
+    fn overstacked(&self, side: Side, hex: &HexId) -> u8 {
+        let stack = self.stack_size(side, hex, &[]);
+        let limit = self.stacking_limit;
+        stack.squads.saturating_sub(limit.squads)
+            + stack.leaders.saturating_sub(limit.leaders)
+            + stack.support_weapons.saturating_sub(limit.support_weapons)
+            + stack.vehicles.saturating_sub(limit.vehicles)
+    }
+
+    /// Checks that the units can end their move in the hex without
+    /// overstacking it.
+    fn check_stacking(
+        &self,
+        side: Side,
+        units: &[Ipv4Addr],
+        hex: &HexId,
+    ) -> Result<(), RuleBreak> {
+        let stack = self.stack_size(side, hex, units);
+        let limit = self.stacking_limit;
+        if stack.squads > limit.squads
+            || stack.leaders > limit.leaders
+            || stack.support_weapons > limit.support_weapons
+            || stack.vehicles > limit.vehicles
+        {
+            return Err(RuleBreak::E6100);
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+/// SL7. Line of Sight (LOS)
+// SL7.1 Line of sight - what a unit can "see" and fire upon, is measured from
+// the center dot of the hex (of the unit) and a straight line to the center dot
+// of the target hex.
+// If an obstacle on the map can be observed on both sides of this line then the
+// LOS is obstructed.
+fn los_obstructed(orig: Hex, dest: Hex) -> bool {
+    // TODO: setup a database to hold all hex data, including LOS
+    todo!("Look-table or database hit to get LOS for passed hexes.")
+}
+
+// SL7.2 LOS is only blocked if something in the hex is actually blocking it.
+// It's not enough that there's woods or a build in the hex, the LOS actually
+// has to be blocked.
+// TODO: fn to verify LOS obstructed by gfx in hex.
+// Weapon's fire may be traced through a hex containg units without affecting
+// them, if the firer prefers this.
+// TODO: option to fire through without affecting unit blocking LOs.
+// TODO: Exception: 17.6
+
+// SL7.3 LOS extends into woods and buildings but not through them.
+// TODO: verify that LOS is obstructed by second woods/building hex so that
+// fire is not able to pass through woods/buildings.
+
+// SL7.4
+
+// A7.5 Indirect fire: mortars can fire on targets outside their own LOS, as
+// long as a friendly spotter has LOS to the target hex. A mortar fires on its
+// own, between its minimum range and its normal range, and its firepower is
+// neither halved at long range nor doubled at point blank range.
+const MORTAR_MINIMUM_RANGE: u8 = 2;
+// A7.52 Indirect fire lands on target if the accuracy roll is equal to, or
+// less than, the accuracy number. Fire directed by a spotter is less
+// accurate. Fire that misses scatters into the adjacent hex given by the
+// coloured die, counted clockwise from the hexside facing north.
+const MORTAR_ACCURACY: u8 = 8;
+const SPOTTED_ACCURACY: u8 = 6;
+
+impl BattleManager {
+    /// A7.5 Returns true if the fire group is an indirect fire attack, and
+    /// checks that the mortar fires on its own.
+    fn indirect_attack(&self, group: &FireGroup) -> Result<bool, RuleBreak> {
+        let mortars = group
+            .weapons
+            .iter()
+            .filter_map(|id| self.weapons.get(id))
+            .filter(|sw| sw.weapon == WeaponType::Mortar)
+            .count();
+        if mortars == 0 {
+            return Ok(false);
+        }
+        if group.weapons.len() > 1 || !group.members.is_empty() {
+            return Err(RuleBreak::A7500);
+        }
+        Ok(true)
+    }
+
+    /// A7.5 Checks that the target hex is within the range of the mortar,
+    /// and within the LOS of either the mortar or its spotter.
+    fn check_indirect_range(
+        &self,
+        ars: &Ipv4Addr,
+        spotter: Option<&Ipv4Addr>,
+        target: &HexId,
+        range: u8,
+    ) -> Result<Option<FireModifier>, RuleBreak> {
+        let origin = self.locations.get(ars).ok_or(RuleBreak::E0001)?;
+        let distance = self.map.distance(origin, target);
+        let distance = distance.ok_or(RuleBreak::E0002)?;
+        if distance > range {
+            return Err(RuleBreak::E2300);
+        }
+        if distance < MORTAR_MINIMUM_RANGE {
+            return Err(RuleBreak::A7510);
+        }
+        if self.map.los(origin, target) {
+            return Ok(None);
+        }
+        let spotter = spotter.ok_or(RuleBreak::E7100)?;
+        let hex = self.locations.get(spotter).ok_or(RuleBreak::E0001)?;
+        if self.sides.get(spotter) != self.sides.get(ars)
+            || self.broken(spotter)
+            || self.passengers.contains_key(spotter)
+        {
+            return Err(RuleBreak::A7520);
+        }
+        if !self.map.los(hex, target) {
+            return Err(RuleBreak::E7100);
+        }
+        Ok(Some(FireModifier::Spotted(*spotter)))
+    }
+
+    /// A7.52 Rolls for the accuracy of indirect fire, and returns the hex
+    /// where it lands, if it lands on the map at all. The modifiers of the
+    /// target hex are replaced by those of the hex where the fire lands.
+    fn scatter(
+        &mut self,
+        side: Side,
+        target: HexId,
+        mut modifiers: Vec<FireModifier>,
+    ) -> (Option<HexId>, Vec<FireModifier>) {
+        let spotted =
+            modifiers.iter().any(|m| matches!(m, FireModifier::Spotted(_)));
+        let accuracy =
+            if spotted { SPOTTED_ACCURACY } else { MORTAR_ACCURACY };
+        let roll = self.dice.roll();
+        if roll.total() <= accuracy {
+            return (Some(target), modifiers);
+        }
+        let (dx, dy, dz) = HEXSIDES[(roll.coloured as usize + 5) % 6];
+        let impact = self
+            .map
+            .cube(&target)
+            .and_then(|(x, y, z)| self.map.at_cube((x + dx, y + dy, z + dz)));
+        modifiers.retain(|m| {
+            !matches!(
+                m,
+                FireModifier::Terrain(_) | FireModifier::Overstacked(_)
+            )
+        });
+        if let Some(hex) = &impact {
+            modifiers.extend(self.hex_modifiers(side, hex, false));
+        }
+        modifiers.push(FireModifier::Scattered(impact));
+        (impact, modifiers)
+    }
+}
+
+// A7.6 Off-board artillery: a side may have one or more artillery modules
+// supporting it from beyond the map. Each module is tied to a radio, carried
+// by a leader, who may call for a fire mission instead of firing. The leader
+// must first make radio contact, and then gain access to the battery. A fire
+// mission may be called on a hex within the leader's LOS, or on any of the
+// hexes the module pre-registered before the battle.
+//
+// A7.61 The leader makes radio contact if the contact roll is equal to, or
+// less than, the contact number, and gains access to the battery if the
+// access roll is equal to, or less than, the access number of the module.
+const RADIO_CONTACT: u8 = 9;
+// A7.62 The barrage lands on target if the accuracy roll is equal to, or
+// less than, the accuracy number. Pre-registered hexes are more accurately
+// hit. A barrage that misses scatters as many hexes as the white die shows,
+// in the direction given by the coloured die.
+const ARTILLERY_ACCURACY: u8 = 6;
+const PRE_REGISTERED_ACCURACY: u8 = 8;
+// A7.63 The blast of a barrage covers the hex where it lands, and all the
+// hexes adjacent to it. Each hex is attacked with the firepower of the module
+// on the Infantry Fire Table, and structures and vehicles in a hex that is
+// hit take damage equal to the firepower. Open ground is turned into
+// shellholes on a blast roll equal to, or less than, this number.
+const SHELLHOLE_ROLL: u8 = 5;
+
+#[derive(Debug, Clone)]
+struct ArtilleryModule {
+    side: Side,
+    firepower: u8,
+    // The leader carrying the radio of the module.
+    radio: Ipv4Addr,
+    access: u8,
+    // The number of fire missions the module can still fire.
+    missions: u8,
+    pre_registered: Vec<HexId>,
+}
+
+impl ArtilleryModule {
+    fn new(
+        side: Side,
+        firepower: u8,
+        radio: Ipv4Addr,
+        access: u8,
+        missions: u8,
+    ) -> ArtilleryModule {
+        ArtilleryModule {
+            side,
+            firepower,
+            radio,
+            access,
+            missions,
+            pre_registered: Vec::new(),
+        }
+    }
+
+    fn with_pre_registered(mut self, hex: HexId) -> ArtilleryModule {
+        self.pre_registered.push(hex);
+        self
+    }
+}
+
+/// A complete breakdown of how a fire mission was resolved.
+#[derive(Debug, Clone, PartialEq)]
+struct ArtilleryResolution {
+    target: HexId,
+    contact: DiceRoll,
+    access: Option<DiceRoll>,
+    accuracy: Option<DiceRoll>,
+    // The hex where the barrage landed. None if the barrage never came, or
+    // if it landed off the map.
+    impact: Option<HexId>,
+    blasts: Vec<Blast>,
+}
+
+/// The effect of a barrage on a single hex of its blast.
+#[derive(Debug, Clone, PartialEq)]
+struct Blast {
+    hex: HexId,
+    result: FireResult,
+    outcomes: Vec<(Ipv4Addr, FireOutcome)>,
+}
+
+impl BattleManager {
+    fn add_artillery(
+        &mut self,
+        id: u32,
+        module: ArtilleryModule,
+    ) -> Result<(), RuleBreak> {
+        if !self.leaders.contains_key(&module.radio) {
+            return Err(RuleBreak::A7600);
+        }
+        for hex in &module.pre_registered {
+            self.map.hex(hex).ok_or(RuleBreak::E0002)?;
+        }
+        self.artillery.insert(id, module);
+        Ok(())
+    }
+
+    /// A7.6 Orders the leader carrying the radio of the module to call for a
+    /// fire mission on the target hex.
+    fn call_fire_mission(
+        &mut self,
+        module: u32,
+        target: HexId,
+        ammunition: Ammunition,
+    ) -> Result<ArtilleryResolution, RuleBreak> {
+        let artillery = self.artillery.get(&module).ok_or(RuleBreak::E0003)?;
+        let (radio, side) = (artillery.radio, artillery.side);
+        let (access, firepower) = (artillery.access, artillery.firepower);
+        let pre_registered = artillery.pre_registered.contains(&target);
+        if artillery.missions == 0 {
+            return Err(RuleBreak::A7620);
+        }
+        self.map.hex(&target).ok_or(RuleBreak::E0002)?;
+        let hex = *self.locations.get(&radio).ok_or(RuleBreak::A7600)?;
+        if self.sides[&radio] != side || self.passengers.contains_key(&radio) {
+            return Err(RuleBreak::A7600);
+        }
+        self.check_may_fire(&radio, side)?;
+        if self.has_marker(&radio, Marker::Fired) {
+            return Err(RuleBreak::A8200);
+        }
+        if !pre_registered && !self.map.los(&hex, &target) {
+            return Err(RuleBreak::E7100);
+        }
+        self.add_marker(radio, Marker::Fired);
+        if self.phase == Phase::PrepFire {
+            self.add_marker(radio, Marker::PrepFire);
+        }
+
+        let mut resolution = ArtilleryResolution {
+            target,
+            contact: self.dice.roll(),
+            access: None,
+            accuracy: None,
+            impact: None,
+            blasts: Vec::new(),
+        };
+        if resolution.contact.total() <= RADIO_CONTACT {
+            resolution.access = Some(self.dice.roll());
+        }
+        self.events.push(Event::FireMission {
+            module,
+            target,
+            contact: resolution.contact,
+            access: resolution.access,
+        });
+        if !resolution.access.is_some_and(|roll| roll.total() <= access) {
+            return Ok(resolution);
+        }
+        if let Some(artillery) = self.artillery.get_mut(&module) {
+            artillery.missions -= 1;
+        }
+
+        let accuracy = self.dice.roll();
+        resolution.accuracy = Some(accuracy);
+        let Some(mut center) = self.map.cube(&target) else {
+            return Ok(resolution);
+        };
+        let on_target = if pre_registered {
+            PRE_REGISTERED_ACCURACY
+        } else {
+            ARTILLERY_ACCURACY
+        };
+        if accuracy.total() > on_target {
+            let (dx, dy, dz) = HEXSIDES[(accuracy.coloured as usize + 5) % 6];
+            let n = accuracy.white as i32;
+            center = (center.0 + dx * n, center.1 + dy * n, center.2 + dz * n);
+        }
+        resolution.impact = self.map.at_cube(center);
+        let blast = std::iter::once((0, 0, 0)).chain(HEXSIDES).filter_map(
+            |(dx, dy, dz)| {
+                self.map.at_cube((center.0 + dx, center.1 + dy, center.2 + dz))
+            },
+        );
+        for hex in blast.collect::<Vec<HexId>>() {
+            match ammunition {
+                Ammunition::HighExplosive => {
+                    let outcome = self.blast(hex, firepower);
+                    resolution.blasts.push(outcome);
+                }
+                Ammunition::Smoke => self.place_smoke(side, hex),
+            }
+        }
+        Ok(resolution)
+    }
+
+    /// A7.63 Attacks every unit, vehicle and structure in the hex with the
+    /// firepower of the barrage.
+    fn blast(&mut self, hex: HexId, firepower: u8) -> Blast {
+        let tem = terrain_effect_combat(&self.map.hexes[&hex]);
+        let roll = self.dice.roll();
+        let result = infantry_fire_table(firepower, roll.total() as i8 + tem);
+        self.events.push(Event::Blast { hex, roll, result });
+        let units = self.units_in(&hex);
+        let vehicles: Vec<Ipv4Addr> = units
+            .iter()
+            .filter(|ars| self.vehicles.contains_key(ars))
+            .copied()
+            .collect();
+        let outcomes = self.apply_fire_result(result, units);
+        if result != FireResult::NoEffect {
+            for ars in vehicles {
+                if self.locations.contains_key(&ars) {
+                    let _ = self.damage(TargetId::Vehicle(ars), firepower);
+                }
+            }
+            if self.structures.contains_key(&hex) {
+                let _ = self.damage(TargetId::Structure(hex), firepower);
+            }
+        }
+        if roll.total() <= SHELLHOLE_ROLL {
+            if let Some(terrain) =
+                self.map.hex_mut(&hex).map(|h| &mut h.terrain)
+            {
+                if *terrain == [Terrain::OpenGround] {
+                    *terrain = vec![Terrain::Shellhole];
+                    self.events.push(Event::Shellholed(hex));
+                }
+            }
+        }
+        Blast { hex, result, outcomes }
+    }
+}
 
 ///////////////////////////////////////////////////////////////////////////////
-// SL19 Movement and Fire
+// A7.7 Smoke
+//
+// Smoke is placed by squads throwing smoke grenades, or by mortars and
+// artillery firing smoke rounds. Fresh smoke blocks any LOS traced through
+// its hex. At the start of the next player turn of the side that placed it,
+// the smoke disperses, and only hinders the LOS through its hex. At the start
+// of the player turn after that, the smoke is gone.
+//
+// A7.71 Every hex of dispersed smoke between the firer and the target adds
+// this much to the dice roll of the attack.
+const SMOKE_HINDRANCE: i8 = 1;
+// A7.73 A squad places smoke grenades in its own, or an adjacent hex, during
+// Phase::Movement by spending MF. The attempt succeeds if the coloured die
+// is equal to, or less than, the smoke number.
+const SMOKE_GRENADE_COST: u8 = 1;
+const SMOKE_GRENADE: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Smoke {
+    // The side that placed the smoke.
+    side: Side,
+    dispersed: bool,
+}
+
+/// A7.74 The rounds fired by mortars and artillery.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Ammunition {
+    #[default]
+    HighExplosive,
+    Smoke,
+}
+
+impl Map {
+    /// A7.71 Returns the hindrance of dispersed smoke to the LOS between the
+    /// origin and the target hex.
+    fn hindrance(&self, origin: &HexId, target: &HexId) -> i8 {
+        let hindrances = self
+            .line(origin, target)
+            .iter()
+            .filter_map(|id| self.hex(id))
+            .filter(|hex| hex.smoke.is_some_and(|smoke| smoke.dispersed))
+            .count();
+        hindrances as i8 * SMOKE_HINDRANCE
+    }
+}
+
+impl BattleManager {
+    /// A7.73 Orders the squad to throw smoke grenades into the hex. Returns
+    /// true if smoke was placed.
+    fn throw_smoke(
+        &mut self,
+        ars: Ipv4Addr,
+        hex: HexId,
+    ) -> Result<bool, RuleBreak> {
+        self.unit_can_move(&ars)?;
+        if !self.squads.contains_key(&ars)
+            || self.passengers.contains_key(&ars)
+        {
+            return Err(RuleBreak::A7700);
+        }
+        let origin = self.locations.get(&ars).ok_or(RuleBreak::E0001)?;
+        self.map.hex(&hex).ok_or(RuleBreak::E0002)?;
+        if self.map.distance(origin, &hex).map_or(true, |d| d > 1) {
+            return Err(RuleBreak::A7710);
+        }
+        if self.has_marker(&ars, Marker::SmokeGrenade) {
+            return Err(RuleBreak::A7720);
+        }
+        let spent = self.mf_spent.get(&ars).copied().unwrap_or(0);
+        if spent + SMOKE_GRENADE_COST > self.mf_allowance(&ars) {
+            return Err(RuleBreak::E5200);
+        }
+        *self.mf_spent.entry(ars).or_default() += SMOKE_GRENADE_COST;
+        self.add_marker(ars, Marker::SmokeGrenade);
+        self.add_marker(ars, Marker::Moved);
+        let roll = self.dice.roll();
+        self.events.push(Event::SmokeGrenade { ars, hex, roll });
+        let placed = roll.coloured <= SMOKE_GRENADE;
+        if placed {
+            self.place_smoke(self.sides[&ars], hex);
+        }
+        Ok(placed)
+    }
+
+    /// Fills the hex with fresh smoke placed by the side.
+    fn place_smoke(&mut self, side: Side, hex: HexId) {
+        if let Some(hex) = self.map.hex_mut(&hex) {
+            hex.smoke = Some(Smoke { side, dispersed: false });
+            self.events.push(Event::SmokePlaced(hex.id));
+        }
+    }
+
+    /// Disperses, or clears, the smoke placed by the side now starting its
+    /// player turn.
+    fn dissipate_smoke(&mut self) {
+        let mut hexes: Vec<HexId> = self
+            .map
+            .hexes
+            .values()
+            .filter(|hex| {
+                hex.smoke.is_some_and(|smoke| smoke.side == self.attacker)
+            })
+            .map(|hex| hex.id)
+            .collect();
+        hexes.sort();
+        for id in hexes {
+            let Some(hex) = self.map.hex_mut(&id) else {
+                continue;
+            };
+            match hex.smoke {
+                Some(Smoke { dispersed: true, .. }) => {
+                    hex.smoke = None;
+                    self.events.push(Event::SmokeCleared(id));
+                }
+                Some(ref mut smoke) => {
+                    smoke.dispersed = true;
+                    self.events.push(Event::SmokeDispersed(id));
+                }
+                None => {}
+            }
+        }
+    }
+}
 
 ///////////////////////////////////////////////////////////////////////////////
-// SL20 Close Combat
+// SL8
 //
+// A8.1 Fire groups: several units in the same hex, or in a chain of adjacent
+// hexes, may combine their firepower, and the firepower of the support
+// weapons they carry, into one single attack on a target hex. A single unit
+// firing on its own is simply a fire group of one.
+#[derive(Debug, Clone, Default)]
+struct FireGroup {
+    members: Vec<Ipv4Addr>,
+    // Support weapons fired by the group. Each weapon must be carried by one
+    // of the members.
+    weapons: Vec<u32>,
+    // A7.5 A friendly unit that directs the indirect fire of a mortar.
+    spotter: Option<Ipv4Addr>,
+    // A7.74 The rounds fired by a mortar.
+    ammunition: Ammunition,
+}
+
+impl FireGroup {
+    fn new(members: &[Ipv4Addr]) -> FireGroup {
+        FireGroup {
+            members: members.to_vec(),
+            weapons: Vec::new(),
+            spotter: None,
+            ammunition: Ammunition::HighExplosive,
+        }
+    }
+
+    fn with_weapon(mut self, weapon: u32) -> FireGroup {
+        self.weapons.push(weapon);
+        self
+    }
+
+    fn with_spotter(mut self, spotter: Ipv4Addr) -> FireGroup {
+        self.spotter = Some(spotter);
+        self
+    }
+
+    fn with_ammunition(mut self, ammunition: Ammunition) -> FireGroup {
+        self.ammunition = ammunition;
+        self
+    }
+}
+
+/// The firepower that a single unit, or a support weapon operated by that
+/// unit, adds to the attack of a fire group.
+#[derive(Debug, Clone, PartialEq)]
+struct FireContribution {
+    ars: Ipv4Addr,
+    weapon: Option<u32>,
+    firepower: u8,
+}
+
+/// Every modifier applied to an attack. Modifiers either change the firepower
+/// of a single contribution or, like terrain, the dice roll of the attack.
+#[derive(Debug, Clone, PartialEq)]
+enum FireModifier {
+    // SL4.5 The unit moved during Phase::Movement, firepower is halved.
+    Moved(Ipv4Addr),
+    // SL2.3 The target is beyond normal range of the unit, or the weapon it
+    // fires, firepower is halved.
+    LongRange(Ipv4Addr, Option<u32>),
+    // SL2.3 The target is adjacent to the firer, firepower is doubled.
+    PointBlank(Ipv4Addr, Option<u32>),
+    // SL2.7 The weapon is fired by its captors, and breaks down more easily.
+    Captured(u32),
+    // SL2.6 The fire is directed by a leader, whose leadership is added to
+    // the dice roll.
+    Leadership(Ipv4Addr, i8),
+    // Terrain Effect Modifier of the target hex, added to the dice roll.
+    Terrain(i8),
+    // SL6.2 The target hex is overstacked, added to the dice roll.
+    Overstacked(i8),
+    // A8.7 The gun missed the target hex, and adds no firepower.
+    Missed(u32),
+    // A7.5 The indirect fire was directed by a spotter.
+    Spotted(Ipv4Addr),
+    // A7.52 The indirect fire scattered into another hex, or off the map.
+    Scattered(Option<HexId>),
+    // A8.7 The target vehicle moved, added to the to-hit roll.
+    TargetMoved(Ipv4Addr),
+    // A8.7 The size of the target vehicle, added to the to-hit roll.
+    TargetSize(i8),
+    // A7.72 The fire is traced into or out of a smoke hex, firepower is
+    // halved.
+    Smoke(HexId),
+    // A7.71 The LOS passes through dispersed smoke, added to the dice roll.
+    Hindrance(i8),
+}
+
+/// The result of an attack, as read from the Infantry Fire Table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FireResult {
+    NoEffect,
+    // Every unit in the target hex must pass a morale check, with the given
+    // number added to the dice roll.
+    MoraleCheck(u8),
+    // Every unit in the target hex is eliminated.
+    Kill,
+}
+
+/// What happened to a single unit in the target hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FireOutcome {
+    Unaffected,
+    Broken,
+    Eliminated,
+}
+
+/// A complete breakdown of how an attack was resolved.
+#[derive(Debug, Clone, PartialEq)]
+struct FireResolution {
+    target: HexId,
+    contributions: Vec<FireContribution>,
+    modifiers: Vec<FireModifier>,
+    firepower: u8,
+    drm: i8,
+    roll: DiceRoll,
+    result: FireResult,
+    outcomes: Vec<(Ipv4Addr, FireOutcome)>,
+}
+
+/// A8.2 The Infantry Fire Table (IFT) cross-references the combined firepower
+/// of an attack with the modified dice roll. Each column holds the results of
+/// the firepower heading it, where `K` eliminates the target, a number calls
+/// for a morale check with that number added to the roll, and `N` calls for a
+/// normal morale check.
+const IFT_COLUMNS: [u8; 11] = [1, 2, 4, 6, 8, 12, 16, 20, 24, 30, 36];
+const IFT: [&str; 13] = [
+    // 1  2  4  6  8 12 16 20 24 30 36
+    "K  K  K  K  K  K  K  K  K  K  K", // 0 or less
+    "2  K  K  K  K  K  K  K  K  K  K", // 1
+    "1  2  K  K  K  K  K  K  K  K  K", // 2
+    "N  1  2  3  K  K  K  K  K  K  K", // 3
+    "-  N  1  2  3  K  K  K  K  K  K", // 4
+    "-  -  N  1  2  3  4  K  K  K  K", // 5
+    "-  -  -  N  1  2  3  4  K  K  K", // 6
+    "-  -  -  -  N  1  2  3  4  4  K", // 7
+    "-  -  -  -  -  N  1  2  3  3  4", // 8
+    "-  -  -  -  -  -  N  1  2  2  3", // 9
+    "-  -  -  -  -  -  -  N  1  1  2", // 10
+    "-  -  -  -  -  -  -  -  N  N  1", // 11
+    "-  -  -  -  -  -  -  -  -  -  N", // 12
+];
+
+/// Returns the sum of the DRM among the modifiers of an attack.
+fn fire_drm(modifiers: &[FireModifier]) -> i8 {
+    modifiers
+        .iter()
+        .map(|m| match m {
+            FireModifier::Leadership(_, drm) => *drm,
+            FireModifier::Terrain(drm) => *drm,
+            FireModifier::Overstacked(drm) => *drm,
+            FireModifier::Hindrance(drm) => *drm,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Looks up the result of an attack on the Infantry Fire Table. Firepower is
+/// rounded down to the nearest column, and rolls above 12 have no effect.
+fn infantry_fire_table(firepower: u8, roll: i8) -> FireResult {
+    let Some(column) = IFT_COLUMNS.iter().rposition(|c| *c <= firepower)
+    else {
+        return FireResult::NoEffect;
+    };
+    if roll > 12 {
+        return FireResult::NoEffect;
+    }
+    let row = IFT[roll.max(0) as usize];
+    match row.split_whitespace().nth(column) {
+        Some("K") => FireResult::Kill,
+        Some("N") => FireResult::MoraleCheck(0),
+        Some(n) => {
+            n.parse().map_or(FireResult::NoEffect, FireResult::MoraleCheck)
+        }
+        None => FireResult::NoEffect,
+    }
+}
+
+impl BattleManager {
+    /// Checks that the fire group may attack the target hex, and returns the
+    /// firepower each member adds to the attack along with any modifiers.
+    /// Every member must be eligible to fire in the current phase, and must
+    /// have both LOS and range to the target hex.
+    fn check_fire_group(
+        &self,
+        group: &FireGroup,
+        target: &HexId,
+    ) -> Result<(Vec<FireContribution>, Vec<FireModifier>), RuleBreak> {
+        self.map.hex(target).ok_or(RuleBreak::E0002)?;
+        let firers = self.firers(group)?;
+        let first = firers.first().ok_or(RuleBreak::E0001)?;
+        let side = *self.sides.get(first).ok_or(RuleBreak::E0001)?;
+        let mut contributions = Vec::new();
+        let mut modifiers = Vec::new();
+        let flame = self.flame_attack(group)?;
+        let indirect = self.indirect_attack(group)?;
+        if group.ammunition == Ammunition::Smoke && !indirect {
+            return Err(RuleBreak::A7730);
+        }
+        for ars in &firers {
+            self.unit(ars).ok_or(RuleBreak::E0001)?;
+            if self.sides[ars] != side {
+                return Err(RuleBreak::A8600);
+            }
+            self.check_may_fire(ars, side)?;
+        }
+        for ars in &group.members {
+            if self.has_marker(ars, Marker::Fired) {
+                return Err(RuleBreak::A8200);
+            }
+            if let Some(squad) = self.squads.get(ars) {
+                let range =
+                    self.check_range(ars, None, target, squad.range)?;
+                modifiers.extend(range);
+                contributions.push(FireContribution {
+                    ars: *ars,
+                    weapon: None,
+                    firepower: squad.firepower,
+                });
+            }
+        }
+        let mut fired: BTreeSet<(Ipv4Addr, WeaponFamily)> = BTreeSet::new();
+        for id in &group.weapons {
+            let weapon = &self.weapons[id];
+            let carrier = &self.carriers[id];
+            if self.malfunctioned.contains(id) {
+                return Err(RuleBreak::A8400);
+            }
+            self.may_fire_support_weapon(carrier)?;
+            if self.captured(id) {
+                modifiers.push(FireModifier::Captured(*id));
+            }
+            // SL5.75 A unit may only fire one weapon of each family in the
+            // same fire phase.
+            let family = weapon.weapon.family();
+            let earlier = self.weapon_types_fired.get(carrier);
+            if earlier.is_some_and(|families| families.contains(&family))
+                || !fired.insert((*carrier, family))
+            {
+                return Err(RuleBreak::E5750);
+            }
+            let range = if indirect {
+                let spotter = group.spotter.as_ref();
+                modifiers.extend(self.check_indirect_range(
+                    carrier,
+                    spotter,
+                    target,
+                    weapon.range,
+                )?);
+                None
+            } else {
+                self.check_range(carrier, Some(*id), target, weapon.range)?
+            };
+            // A8.91 The firepower of a flamethrower is not affected by range,
+            // but it cannot fire beyond its normal range.
+            match range {
+                Some(FireModifier::LongRange(..)) if flame => {
+                    return Err(RuleBreak::A8910)
+                }
+                _ if flame => {}
+                range => modifiers.extend(range),
+            }
+            contributions.push(FireContribution {
+                ars: *carrier,
+                weapon: Some(*id),
+                firepower: weapon.firepower,
+            });
+        }
+        if !self.adjacent_group(&firers) {
+            return Err(RuleBreak::A8100);
+        }
+        // SL4.5 The penalty for firing after movement is that firepower is
+        // halved, rounded down.
+        if self.phase == Phase::AdvancingFire {
+            for ars in &firers {
+                if self.has_marker(ars, Marker::Moved) {
+                    modifiers.push(FireModifier::Moved(*ars));
+                }
+            }
+        }
+        // A7.72 Direct fire into or out of smoke is halved, and dispersed
+        // smoke along the LOS hinders it. Indirect fire is aimed by its
+        // spotter, and is not affected.
+        if !indirect {
+            let mut hexes: Vec<HexId> = firers
+                .iter()
+                .filter_map(|ars| self.locations.get(ars))
+                .chain(std::iter::once(target))
+                .copied()
+                .collect();
+            hexes.sort();
+            hexes.dedup();
+            let smoky = hexes.iter().filter(|hex| {
+                self.map.hex(hex).is_some_and(|hex| hex.smoke.is_some())
+            });
+            modifiers.extend(smoky.map(|hex| FireModifier::Smoke(*hex)));
+            let hindrance = firers
+                .iter()
+                .filter_map(|ars| self.locations.get(ars))
+                .map(|origin| self.map.hindrance(origin, target))
+                .max()
+                .unwrap_or(0);
+            if hindrance > 0 {
+                modifiers.push(FireModifier::Hindrance(hindrance));
+            }
+        }
+        // Firepower is doubled before it is halved, so that a unit firing
+        // at point blank range after moving keeps its full firepower.
+        for contribution in contributions.iter_mut() {
+            let (ars, weapon) = (contribution.ars, contribution.weapon);
+            let smoke = [self.locations.get(&ars), Some(target)]
+                .into_iter()
+                .flatten()
+                .any(|hex| modifiers.contains(&FireModifier::Smoke(*hex)));
+            if modifiers.contains(&FireModifier::PointBlank(ars, weapon)) {
+                contribution.firepower =
+                    contribution.firepower.saturating_mul(2);
+            }
+            if modifiers.contains(&FireModifier::LongRange(ars, weapon)) {
+                contribution.firepower /= 2;
+            }
+            if modifiers.contains(&FireModifier::Moved(ars)) {
+                contribution.firepower /= 2;
+            }
+            if smoke {
+                contribution.firepower /= 2;
+            }
+        }
+        // SL2.6 A leader that is part of the fire group directs its fire,
+        // provided that every member is stacked in the leader's hex.
+        let hexes: BTreeSet<&HexId> =
+            firers.iter().filter_map(|a| self.locations.get(a)).collect();
+        let leader = group
+            .members
+            .iter()
+            .filter_map(|ars| self.leaders.get(ars))
+            .min_by_key(|leader| leader.leadership);
+        if let (Some(leader), 1) = (leader, hexes.len()) {
+            if leader.leadership != 0 {
+                modifiers.push(FireModifier::Leadership(
+                    leader.ars,
+                    leader.leadership,
+                ));
+            }
+        }
+        modifiers.extend(self.hex_modifiers(side, target, flame));
+        Ok((contributions, modifiers))
+    }
+
+    /// Returns the modifiers of fire by the side into the target hex.
+    fn hex_modifiers(
+        &self,
+        side: Side,
+        target: &HexId,
+        flame: bool,
+    ) -> Vec<FireModifier> {
+        let mut modifiers = Vec::new();
+        // A8.92 Flames ignore the cover of the target hex.
+        let tem = terrain_effect_combat(&self.map.hexes[target]);
+        if tem != 0 && !flame {
+            modifiers.push(FireModifier::Terrain(tem));
+        }
+        // SL6.2 Every squad, leader or support weapon beyond the stacking
+        // limit makes the target hex more vulnerable to fire.
+        let excess = self.overstacked(side.opponent(), target);
+        if excess > 0 {
+            modifiers.push(FireModifier::Overstacked(-(excess as i8)));
+        }
+        modifiers
+    }
+
+    /// Returns every unit taking part in the attack of the fire group, that is
+    /// the members of the group followed by the carriers of the weapons fired.
+    fn firers(&self, group: &FireGroup) -> Result<Vec<Ipv4Addr>, RuleBreak> {
+        check_distinct(&group.members)?;
+        let mut firers = group.members.clone();
+        for id in &group.weapons {
+            self.weapons.get(id).ok_or(RuleBreak::E0003)?;
+            let carrier = self.carriers.get(id).ok_or(RuleBreak::A8300)?;
+            if !firers.contains(carrier) {
+                firers.push(*carrier);
+            }
+        }
+        Ok(firers)
+    }
+
+    /// Checks that a unit is eligible to fire in the current phase.
+    fn check_may_fire(
+        &self,
+        ars: &Ipv4Addr,
+        side: Side,
+    ) -> Result<(), RuleBreak> {
+        let player = self.player(side);
+        match (self.phase, player) {
+            (Phase::PrepFire, Player::Attacker) => {}
+            (Phase::PrepFire, Player::Defender) => {
+                return Err(RuleBreak::E4200)
+            }
+            (Phase::DefensiveFire, Player::Defender) => {}
+            (Phase::DefensiveFire, Player::Attacker) => {
+                return Err(RuleBreak::E4400)
+            }
+            (Phase::AdvancingFire, Player::Attacker) => {
+                if self.has_marker(ars, Marker::PrepFire) {
+                    return Err(RuleBreak::E4500);
+                }
+            }
+            _ => return Err(RuleBreak::E4000),
+        }
+        if self.broken(ars) {
+            return Err(RuleBreak::E2400);
+        }
+        if self.has_marker(ars, Marker::Shocked) {
+            return Err(RuleBreak::A8810);
+        }
+        Ok(())
+    }
+
+    /// Checks that the target hex is within LOS and range of the firer, and
+    /// returns the range modifier, if any, of the unit or weapon firing.
+    ///
+    /// SL2.3 Targets beyond the normal range of the firer, up to double that
+    /// range, are fired upon at long range with half firepower. Targets in
+    /// an adjacent hex are fired upon at point blank range with double
+    /// firepower. Targets beyond double range cannot be fired upon.
+    fn check_range(
+        &self,
+        ars: &Ipv4Addr,
+        weapon: Option<u32>,
+        target: &HexId,
+        range: u8,
+    ) -> Result<Option<FireModifier>, RuleBreak> {
+        let origin = self.locations.get(ars).ok_or(RuleBreak::E0001)?;
+        if !self.map.los(origin, target) {
+            return Err(RuleBreak::E7100);
+        }
+        if self.vehicles.contains_key(ars) {
+            self.check_covered_arc(ars, target)?;
+        }
+        let distance = self.map.distance(origin, target);
+        let distance = distance.ok_or(RuleBreak::E0002)?;
+        if distance > range.saturating_mul(2) {
+            Err(RuleBreak::E2300)
+        } else if distance > range {
+            Ok(Some(FireModifier::LongRange(*ars, weapon)))
+        } else if distance <= 1 {
+            Ok(Some(FireModifier::PointBlank(*ars, weapon)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns true if the hexes of all the units form a single chain of
+    /// adjacent hexes.
+    fn adjacent_group(&self, members: &[Ipv4Addr]) -> bool {
+        let mut hexes: Vec<HexId> = members
+            .iter()
+            .filter_map(|ars| self.locations.get(ars))
+            .copied()
+            .collect();
+        hexes.sort();
+        hexes.dedup();
+        let Some(first) = hexes.first().copied() else {
+            return false;
+        };
+        let mut reached = vec![first];
+        let mut i = 0;
+        while i < reached.len() {
+            for neighbour in self.map.neighbours(&reached[i]) {
+                if hexes.contains(&neighbour) && !reached.contains(&neighbour)
+                {
+                    reached.push(neighbour);
+                }
+            }
+            i += 1;
+        }
+        reached.len() == hexes.len()
+    }
+
+    /// Applies the result of an attack on the Infantry Fire Table to every
+    /// unit in the target hex.
+    fn apply_fire_result(
+        &mut self,
+        result: FireResult,
+        defenders: Vec<Ipv4Addr>,
+    ) -> Vec<(Ipv4Addr, FireOutcome)> {
+        defenders
+            .into_iter()
+            .map(|ars| {
+                let outcome = match result {
+                    _ if self.armored(&ars) => FireOutcome::Unaffected,
+                    FireResult::NoEffect => FireOutcome::Unaffected,
+                    FireResult::MoraleCheck(n) => {
+                        self.morale_check(ars, n as i8)
+                    }
+                    FireResult::Kill => {
+                        self.eliminate(ars);
+                        FireOutcome::Eliminated
+                    }
+                };
+                (ars, outcome)
+            })
+            .collect()
+    }
+
+    /// SL2.9 A support weapon that breaks down malfunctions, except for
+    /// flamethrowers, which are used up.
+    fn break_down(&mut self, weapon: u32) {
+        if self.weapons[&weapon].weapon == WeaponType::Flamethrower {
+            self.expend(weapon);
+        } else {
+            self.malfunctioned.insert(weapon);
+            self.events.push(Event::Malfunctioned(weapon));
+        }
+    }
+
+    /// Removes a used up support weapon from the game.
+    fn expend(&mut self, weapon: u32) {
+        self.carriers.remove(&weapon);
+        self.abandoned.remove(&weapon);
+        self.charges.remove(&weapon);
+        self.events.push(Event::Expended(weapon));
+    }
+
+    /// Orders the fire group to fire on the target hex. The combined
+    /// firepower is resolved with a single dice roll on the Infantry Fire
+    /// Table, and the result applies to every enemy unit in the target hex.
+    fn fire(
+        &mut self,
+        group: &FireGroup,
+        target: HexId,
+    ) -> Result<FireResolution, RuleBreak> {
+        let (contributions, modifiers) =
+            self.check_fire_group(group, &target)?;
+        let firers = self.firers(group)?;
+        let side = self.sides[&firers[0]];
+        let defenders: Vec<Ipv4Addr> = self
+            .units_in(&target)
+            .into_iter()
+            .filter(|ars| self.sides[ars] != side)
+            .collect();
+        let smoke = group.ammunition == Ammunition::Smoke;
+        if defenders.is_empty() && !smoke {
+            return Err(RuleBreak::A8500);
+        }
+        let (contributions, modifiers) =
+            self.gun_hits(contributions, modifiers, &target);
+        // A7.52 Indirect fire must land on target before it has any effect,
+        // and then affects every unit in the hex where it lands.
+        let (impact, defenders, modifiers) = if self.indirect_attack(group)? {
+            let (impact, modifiers) = self.scatter(side, target, modifiers);
+            let defenders = match impact {
+                Some(hex) if !smoke => self.units_in(&hex),
+                _ => Vec::new(),
+            };
+            (impact, defenders, modifiers)
+        } else {
+            (Some(target), defenders, modifiers)
+        };
+        let firepower = contributions
+            .iter()
+            .fold(0u8, |sum, c| sum.saturating_add(c.firepower));
+        let drm = fire_drm(&modifiers);
+        let roll = self.dice.roll();
+        let result = if smoke {
+            FireResult::NoEffect
+        } else {
+            infantry_fire_table(firepower, roll.total() as i8 + drm)
+        };
+
+        // SL2.9 A support weapon breaks down if the original dice roll is
+        // equal to, or higher than, its breakdown number.
+        for id in &group.weapons {
+            if roll.total() >= self.breakdown(id) {
+                self.break_down(*id);
+            }
+        }
+        // The members of the group have used their inherent firepower for
+        // this phase, while the carriers have only used their weapons.
+        for ars in &group.members {
+            self.add_marker(*ars, Marker::Fired);
+        }
+        for id in &group.weapons {
+            let family = self.weapons[id].weapon.family();
+            let carrier = self.carriers[id];
+            self.weapon_types_fired.entry(carrier).or_default().insert(family);
+        }
+        if self.phase == Phase::PrepFire {
+            for ars in &firers {
+                self.add_marker(*ars, Marker::PrepFire);
+            }
+        }
+        self.events.push(Event::Fired {
+            members: group.members.clone(),
+            weapons: group.weapons.clone(),
+            target,
+            roll,
+        });
+
+        let outcomes = self.apply_fire_result(result, defenders);
+        // A7.74 Smoke rounds fill the hex where they land with smoke.
+        if let (Some(hex), true) = (impact, smoke) {
+            self.place_smoke(side, hex);
+        }
+        // A8.93 Flames may set the target hex on fire.
+        if self.flame_attack(group)? && roll.total() <= FLAME_IGNITION {
+            self.ignite(target);
+        }
+        Ok(FireResolution {
+            target,
+            contributions,
+            modifiers,
+            firepower,
+            drm,
+            roll,
+            result,
+            outcomes,
+        })
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// A8.7 Anti-tank fire
+//
+// Guns must hit their target before they have any effect. The firer rolls
+// against a to-hit number that depends on the range to the target, and the
+// dice roll is modified by the size and movement of the target, as well as
+// the movement of the firer and the terrain in the target hex. A hit is
+// achieved if the modified dice roll is equal to, or less than, the to-hit
+// number.
+//
+// A8.8 A hit on a vehicle is compared to the armor of the facing that was
+// hit. The damage roll is modified by the difference between the armor and
+// the armor penetration of the weapon.
+
+/// A8.7 The to-hit number of guns for each range band, where each band is
+/// given by the longest range in hexes that it covers.
+const TO_HIT: [(u8, u8); 6] =
+    [(1, 10), (3, 9), (6, 8), (10, 7), (16, 6), (u8::MAX, 5)];
+// A8.7 Firing at a vehicle that moved, or firing after having moved, adds
+// this much to the to-hit roll.
+const MOVING_TO_HIT_MODIFIER: i8 = 2;
+// A8.7 Firing beyond the normal range of the weapon lowers the to-hit number.
+const LONG_RANGE_TO_HIT_PENALTY: u8 = 2;
+
+fn to_hit_number(distance: u8) -> u8 {
+    TO_HIT.iter().find(|(range, _)| distance <= *range).map_or(0, |(_, n)| *n)
+}
+
+/// A8.8 The facing of a vehicle that a hit strikes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArmorFacing {
+    Front,
+    Side,
+    Rear,
+}
+
+impl Armor {
+    fn facing(&self, facing: ArmorFacing) -> u8 {
+        match facing {
+            ArmorFacing::Front => self.front,
+            ArmorFacing::Side => self.side,
+            ArmorFacing::Rear => self.rear,
+        }
+    }
+}
+
+/// A8.8 The outcome of anti-tank fire against a vehicle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArmorResult {
+    Miss,
+    NoEffect,
+    // The crew is shocked, and may neither move nor fire until the start of
+    // its next player turn.
+    Shocked,
+    // The vehicle may no longer move, but it may still fire.
+    Immobilized,
+    // The vehicle is destroyed, and its passengers must bail out.
+    Destroyed,
+}
+
+/// A8.8 Reads the result of a damage roll, after it has been modified by the
+/// armor of the facing hit and the armor penetration of the weapon.
+fn damage_table(roll: i8) -> ArmorResult {
+    match roll {
+        ..=4 => ArmorResult::Destroyed,
+        5..=6 => ArmorResult::Immobilized,
+        7..=8 => ArmorResult::Shocked,
+        _ => ArmorResult::NoEffect,
+    }
+}
+
+/// A complete breakdown of how anti-tank fire was resolved.
+#[derive(Debug, Clone, PartialEq)]
+struct AntiTankResolution {
+    target: Ipv4Addr,
+    modifiers: Vec<FireModifier>,
+    to_hit: u8,
+    drm: i8,
+    roll: DiceRoll,
+    // The facing hit, and the damage roll, when the target was hit.
+    facing: Option<ArmorFacing>,
+    damage: Option<DiceRoll>,
+    result: ArmorResult,
+}
+
+impl BattleManager {
+    /// A8.7 Returns the to-hit number of the weapon against a target at the
+    /// given distance.
+    fn to_hit(&self, weapon: &u32, distance: u8) -> u8 {
+        let to_hit = to_hit_number(distance);
+        if distance > self.weapons[weapon].range {
+            to_hit.saturating_sub(LONG_RANGE_TO_HIT_PENALTY)
+        } else {
+            to_hit
+        }
+    }
+
+    /// A8.7 Guns firing as part of a fire group on the Infantry Fire Table
+    /// must first hit the target hex. Each gun rolls to hit, and the
+    /// firepower of every gun that misses is lost.
+    fn gun_hits(
+        &mut self,
+        mut contributions: Vec<FireContribution>,
+        mut modifiers: Vec<FireModifier>,
+        target: &HexId,
+    ) -> (Vec<FireContribution>, Vec<FireModifier>) {
+        let tem = terrain_effect_combat(&self.map.hexes[target]);
+        for contribution in contributions.iter_mut() {
+            let Some(id) = contribution.weapon else { continue };
+            if self.weapons[&id].weapon.family() != WeaponFamily::Gun {
+                continue;
+            }
+            let origin = &self.locations[&contribution.ars];
+            let distance = self.map.distance(origin, target).unwrap_or(0);
+            let mut drm = tem;
+            if modifiers.contains(&FireModifier::Moved(contribution.ars)) {
+                drm += MOVING_TO_HIT_MODIFIER;
+            }
+            let roll = self.dice.roll();
+            if roll.total() as i8 + drm > self.to_hit(&id, distance) as i8 {
+                contribution.firepower = 0;
+                modifiers.push(FireModifier::Missed(id));
+            }
+        }
+        (contributions, modifiers)
+    }
+
+    /// A8.8 Returns the facing of the target vehicle that faces the firer.
+    fn facing_hit(&self, firer: &Ipv4Addr, target: &Ipv4Addr) -> ArmorFacing {
+        let bearing = self.map.bearing(
+            &self.locations[target],
+            self.facings[target],
+            &self.locations[firer],
+        );
+        match bearing {
+            Some(bearing) if bearing <= COVERED_ARC + 1e-6 => {
+                ArmorFacing::Front
+            }
+            Some(bearing) if bearing < 180.0 - COVERED_ARC - 1e-6 => {
+                ArmorFacing::Side
+            }
+            _ => ArmorFacing::Rear,
+        }
+    }
+
+    /// A8.8 Applies the result of a hit to the vehicle.
+    fn apply_armor_result(&mut self, target: Ipv4Addr, result: ArmorResult) {
+        match result {
+            ArmorResult::Shocked => self.add_marker(target, Marker::Shocked),
+            ArmorResult::Immobilized => {
+                self.add_marker(target, Marker::Immobilized)
+            }
+            ArmorResult::Destroyed => self.destroy_vehicle(target),
+            _ => {}
+        }
+    }
+
+    /// A8.7 Orders a unit to fire a weapon with armor penetration at an
+    /// enemy vehicle. The weapon must be eligible to fire just as if it was
+    /// firing on the Infantry Fire Table, and the vehicle must be within its
+    /// LOS and range.
+    fn fire_at_vehicle(
+        &mut self,
+        ars: Ipv4Addr,
+        weapon: u32,
+        target: Ipv4Addr,
+    ) -> Result<AntiTankResolution, RuleBreak> {
+        let sw = self.weapons.get(&weapon).ok_or(RuleBreak::E0003)?;
+        let (range, armor_penetration) = (sw.range, sw.armor_penetration);
+        let family = sw.weapon.family();
+        if self.carriers.get(&weapon) != Some(&ars) {
+            return Err(RuleBreak::A8300);
+        }
+        if armor_penetration == 0 {
+            return Err(RuleBreak::A8700);
+        }
+        let side = *self.sides.get(&ars).ok_or(RuleBreak::E0001)?;
+        let vehicle = self.vehicles.get(&target).ok_or(RuleBreak::E0001)?;
+        let (armor, size) = (vehicle.armor, vehicle.kind.size_modifier());
+        let hex = *self.locations.get(&target).ok_or(RuleBreak::E0001)?;
+        if self.sides[&target] == side {
+            return Err(RuleBreak::A8600);
+        }
+        self.check_may_fire(&ars, side)?;
+        if self.malfunctioned.contains(&weapon) {
+            return Err(RuleBreak::A8400);
+        }
+        self.may_fire_support_weapon(&ars)?;
+        if self
+            .weapon_types_fired
+            .get(&ars)
+            .is_some_and(|families| families.contains(&family))
+        {
+            return Err(RuleBreak::E5750);
+        }
+        let mut modifiers = Vec::new();
+        modifiers.extend(self.check_range(&ars, Some(weapon), &hex, range)?);
+        if self.captured(&weapon) {
+            modifiers.push(FireModifier::Captured(weapon));
+        }
+        if self.phase == Phase::AdvancingFire
+            && self.has_marker(&ars, Marker::Moved)
+        {
+            modifiers.push(FireModifier::Moved(ars));
+        }
+        if self.has_marker(&target, Marker::Moved) {
+            modifiers.push(FireModifier::TargetMoved(target));
+        }
+        if size != 0 {
+            modifiers.push(FireModifier::TargetSize(size));
+        }
+        let tem = terrain_effect_combat(&self.map.hexes[&hex]);
+        if tem != 0 {
+            modifiers.push(FireModifier::Terrain(tem));
+        }
+        let drm = modifiers
+            .iter()
+            .map(|m| match m {
+                FireModifier::Moved(_) | FireModifier::TargetMoved(_) => {
+                    MOVING_TO_HIT_MODIFIER
+                }
+                FireModifier::TargetSize(drm) => *drm,
+                FireModifier::Terrain(drm) => *drm,
+                _ => 0,
+            })
+            .sum();
+        let distance = self.map.distance(&self.locations[&ars], &hex);
+        let to_hit = self.to_hit(&weapon, distance.unwrap_or(0));
+
+        let roll = self.dice.roll();
+        if roll.total() >= self.breakdown(&weapon) {
+            self.malfunctioned.insert(weapon);
+            self.events.push(Event::Malfunctioned(weapon));
+        }
+        self.weapon_types_fired.entry(ars).or_default().insert(family);
+        if self.phase == Phase::PrepFire {
+            self.add_marker(ars, Marker::PrepFire);
+        }
+        let (facing, damage, result) =
+            if roll.total() as i8 + drm <= to_hit as i8 {
+                let facing = self.facing_hit(&ars, &target);
+                let damage = self.dice.roll();
+                let total = damage.total() as i8 + armor.facing(facing) as i8
+                    - armor_penetration as i8;
+                (Some(facing), Some(damage), damage_table(total))
+            } else {
+                (None, None, ArmorResult::Miss)
+            };
+        self.events.push(Event::AntiTankFire {
+            ars,
+            weapon,
+            target,
+            roll,
+            result,
+        });
+        self.apply_armor_result(target, result);
+        Ok(AntiTankResolution {
+            target,
+            modifiers,
+            to_hit,
+            drm,
+            roll,
+            facing,
+            damage,
+            result,
+        })
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// A4.71 Demolition charges
+//
+// A unit carrying a demolition charge may place it in an adjacent hex during
+// Phase::Advance, instead of advancing. Placed charges are detonated at the
+// end of the phase, and attack every unit in the hex with a fixed firepower
+// that ignores the terrain in the hex. Structures and vehicles in the hex are
+// damaged as well. A charge is used up when it is detonated, and is a dud if
+// the detonation roll is equal to, or higher than, its breakdown number.
+const DEMOLITION_FIREPOWER: u8 = 30;
+
+impl BattleManager {
+    /// A4.71 Orders a unit to place a demolition charge it carries in an
+    /// adjacent hex.
+    fn place_charge(
+        &mut self,
+        ars: Ipv4Addr,
+        weapon: u32,
+        hex: HexId,
+    ) -> Result<(), RuleBreak> {
+        let origin = *self.locations.get(&ars).ok_or(RuleBreak::E0001)?;
+        let sw = self.weapons.get(&weapon).ok_or(RuleBreak::E0003)?;
+        if sw.weapon != WeaponType::Demolition {
+            return Err(RuleBreak::A4710);
+        }
+        if self.carriers.get(&weapon) != Some(&ars) {
+            return Err(RuleBreak::A8300);
+        }
+        self.map.hex(&hex).ok_or(RuleBreak::E0002)?;
+        if self.phase != Phase::Advance
+            || self.player(self.sides[&ars]) != Player::Attacker
+            || self.broken(&ars)
+            || self.has_marker(&ars, Marker::Advanced)
+        {
+            return Err(RuleBreak::E4700);
+        }
+        self.check_dismounted(&ars)?;
+        if self.map.distance(&origin, &hex) != Some(1) {
+            return Err(RuleBreak::A4710);
+        }
+        self.carriers.remove(&weapon);
+        self.charges.insert(weapon, (ars, hex));
+        self.add_marker(ars, Marker::Advanced);
+        self.events.push(Event::ChargePlaced { ars, weapon, hex });
+        Ok(())
+    }
+
+    /// A4.71 Detonates every placed demolition charge.
+    fn detonate_charges(&mut self) {
+        let charges = std::mem::take(&mut self.charges);
+        for (weapon, (_, hex)) in charges {
+            let roll = self.dice.roll();
+            let result = if roll.total() >= self.weapons[&weapon].breakdown {
+                FireResult::NoEffect
+            } else {
+                infantry_fire_table(DEMOLITION_FIREPOWER, roll.total() as i8)
+            };
+            self.events.push(Event::Detonated { weapon, hex, roll, result });
+            self.expend(weapon);
+            if result == FireResult::NoEffect {
+                continue;
+            }
+            let defenders = self.units_in(&hex);
+            let vehicles: Vec<Ipv4Addr> = defenders
+                .iter()
+                .filter(|ars| self.vehicles.contains_key(ars))
+                .copied()
+                .collect();
+            self.apply_fire_result(result, defenders);
+            for ars in vehicles {
+                if self.locations.contains_key(&ars) {
+                    let _ = self
+                        .damage(TargetId::Vehicle(ars), DEMOLITION_FIREPOWER);
+                }
+            }
+            if self.structures.contains_key(&hex) {
+                let _ = self
+                    .damage(TargetId::Structure(hex), DEMOLITION_FIREPOWER);
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// A8.9 Flamethrowers
+//
+// A8.9 A flamethrower attacks on its own, and cannot be part of a fire group
+// with any other unit or weapon.
+// A8.91 Flamethrowers cannot fire beyond their normal range, and their
+// firepower is neither halved at long range nor doubled at point blank range.
+// A8.92 Flames ignore the terrain effect modifiers of the target hex.
+// A8.93 A flamethrower attack whose original dice roll is equal to, or less
+// than, this number sets burnable terrain in the target hex ablaze.
+const FLAME_IGNITION: u8 = 4;
+
+impl BattleManager {
+    /// Returns true if the fire group is a flamethrower attack, and checks
+    /// that special weapons are only fired in the manner allowed for them.
+    fn flame_attack(&self, group: &FireGroup) -> Result<bool, RuleBreak> {
+        let kinds: Vec<WeaponType> = group
+            .weapons
+            .iter()
+            .filter_map(|id| self.weapons.get(id))
+            .map(|sw| sw.weapon)
+            .collect();
+        // A4.71 Demolition charges are placed, never fired.
+        if kinds.contains(&WeaponType::Demolition) {
+            return Err(RuleBreak::A4700);
+        }
+        if !kinds.contains(&WeaponType::Flamethrower) {
+            return Ok(false);
+        }
+        if kinds.len() > 1 || !group.members.is_empty() {
+            return Err(RuleBreak::A8900);
+        }
+        Ok(true)
+    }
+
+    /// A8.93 Sets the burnable terrain of the hex ablaze.
+    fn ignite(&mut self, target: HexId) {
+        let Some(hex) = self.map.hex_mut(&target) else { return };
+        if hex.terrain.iter().any(Terrain::burnable)
+            && !hex.terrain.contains(&Terrain::Blaze)
+        {
+            hex.terrain.push(Terrain::Blaze);
+            self.events.push(Event::Ablaze(target));
+        }
+    }
+
+    /// A8.94 Burns down the blazes on the map at the end of a player turn. A
+    /// blaze consumes the burnable terrain of its hex at the end of the
+    /// player turn it started in, and burns out at the end of the next one.
+    /// A building that burns down collapses into rubble.
+    fn burn(&mut self) {
+        let mut hexes: Vec<HexId> = self
+            .map
+            .hexes
+            .values()
+            .filter(|hex| hex.terrain.contains(&Terrain::Blaze))
+            .map(|hex| hex.id)
+            .collect();
+        hexes.sort();
+        for id in hexes {
+            let Some(hex) = self.map.hex_mut(&id) else {
+                continue;
+            };
+            if hex.terrain.iter().any(Terrain::burnable) {
+                let mut terrain: Vec<Terrain> =
+                    hex.terrain.iter().map(Terrain::burned).collect();
+                terrain.dedup();
+                hex.terrain = terrain;
+                if self.structures.get(&id).is_some_and(|structure| {
+                    structure.kind == StructureKind::Building
+                }) {
+                    self.structures.remove(&id);
+                    let target = TargetId::Structure(id);
+                    self.events.push(Event::Destroyed(target));
+                }
+            } else {
+                hex.terrain.retain(|terrain| *terrain != Terrain::Blaze);
+                self.events.push(Event::BurnedOut(id));
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// SL16 Defensive Fire Principles
+
+///////////////////////////////////////////////////////////////////////////////
+// SL16 Defensive Fire Principles
+
+///////////////////////////////////////////////////////////////////////////////
+// SL15 Leadership
+
+///////////////////////////////////////////////////////////////////////////////
+// SL16 Defensive Fire Principles
+
+///////////////////////////////////////////////////////////////////////////////
+// SL16 Defensive Fire Principles
+
+///////////////////////////////////////////////////////////////////////////////
+// SL17 Machine Guns
+
+///////////////////////////////////////////////////////////////////////////////
+// SL18 Fate
+
+///////////////////////////////////////////////////////////////////////////////
+// SL19 Movement and Fire
+
+///////////////////////////////////////////////////////////////////////////////
+// SL20 Close Combat
+//
+
+///////////////////////////////////////////////////////////////////////////////
+// A21 Scenarios
+//
+// A scenario describes a battle: the boards it is fought on, the number of
+// game turns, the side that sets up first and the side that moves first,
+// the units of each side and where they start out, any scenario special
+// rules (SSR), and the conditions for victory.
+//
+// A21.1 Scenarios are written as plain text, one entry per line. Words are
+// separated by whitespace, and anything following a `#` is a comment. The
+// entries of a side follow a `side` line. For example:
+//
+// ```text
+// scenario The Guards Counterattack
+// boards 1
+// turns 5
+// setup Allies
+// first Axis
+// # A scenario ending on a half turn is written as `turns 5.5`.
+// rule stacking 3 4 4 2
+// rule The Allied player may not use smoke.
+// terrain 1D5 EnterBuilding WithinBuilding
+// elevation 1E5 1
+// # building, bunker or bridge <hex> <armor> <health>
+// building 1D5 10 5
+// # building-hexes <number> <hexes>
+// building-hexes 1 1D5 1D6
+//
+// side Axis
+// # squad <ars> <firepower> <range> <morale> <hex>
+// squad 1.1.1.1 4 6 7 1C3
+// # leader <ars> <leadership> <morale> <hex> <identity>
+// leader 1.1.1.0 -1 8 1C3 Lt. Weber
+// # weapon <id> <type> <firepower> <penetration> <range> <breakdown>
+// #        <portage> <carrier or hex>
+// weapon 1 LMG 2 3 6 12 1 1.1.1.1
+// # vehicle <ars> <type> <mf> <front/side/rear> <morale> <hex> <facing>
+// #         <identity>
+// vehicle 1.1.9.1 Tank 8 6/4/3 7 1A1 2 PzKpfw IV
+// # artillery <id> <firepower> <radio> <access> <missions> <registered hexes>
+// artillery 1 20 1.1.1.0 7 2 1H8
+//
+// side Allies
+// # area <hexes or boards>, the setup area that the side starts out
+// # controlling
+// area 1D5 1D6 1E5
+// squad 2.1.1.1 4 6 7 1D5
+// minefield 1C4 APMine 8
+// # reinforcements <turn> <edge or hexes> [roll <arrival number>], followed
+// # by the units of the group, written without a hex or facing
+// reinforcements 2 West roll 8
+// squad 2.2.1.1 4 6 7
+// leader 2.2.1.0 -1 8 Sgt. Baker
+//
+// # victory <side> <condition> [and <condition>]...
+// victory Axis control 1D5 and exit East 4
+// victory Axis casualties 6
+// victory Axis control-building 1
+// sudden-death Allies casualties 8
+// ```
+
+/// A21.2 Scenario special rules, that change the rules for a single battle.
+#[derive(Debug, Clone, PartialEq)]
+enum SpecialRule {
+    // The stacking limit of the scenario.
+    Stacking(StackingLimit),
+    // Any other rule, which the players must see to themselves.
+    Note(String),
+}
+
+/// A21.3 The conditions that decide who wins the battle. A side wins once it
+/// meets every condition of one of its victories. A victory may be declared
+/// a sudden death victory, which ends the battle at the end of any game turn
+/// in which it is met. Otherwise, victories are only evaluated when the
+/// battle reaches its turn limit.
+#[derive(Debug, Clone, PartialEq)]
+enum VictoryCondition {
+    // The side controls every one of the hexes.
+    Control(Vec<HexId>),
+    // The side controls every one of the buildings.
+    ControlBuildings(Vec<u32>),
+    // The side has exited units worth this many victory points off the edge
+    // of the map.
+    Exit(Edge, u16),
+    // The side has inflicted this many casualty points on the enemy.
+    Casualties(u16),
+    // The side meets every one of the conditions.
+    All(Vec<VictoryCondition>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Victory {
+    side: Side,
+    condition: VictoryCondition,
+    sudden_death: bool,
+}
+
+/// The ScenarioManager is reponsible for tracking scenario relevant events
+/// on the battlefield, such as the turn limit and the victory conditions.
+#[derive(Debug, Clone)]
+struct ScenarioManager {
+    name: String,
+    boards: Vec<u8>,
+    turns: u8,
+    // SL4.9 The scenario ends with a half turn, played by the side that
+    // moves first only.
+    half_turn: bool,
+    // The number of phases played so far, and the current game turn.
+    phases: u16,
+    turn: u8,
+    game_over: Option<GameOver>,
+    // The side that sets up its units first.
+    setup: Side,
+    // The side that moves first, and is Player::Attacker in the first
+    // player turn of each game turn.
+    first: Side,
+    rules: Vec<SpecialRule>,
+    victory: Vec<Victory>,
+    reinforcements: Vec<Reinforcement>,
+}
+
+/// Why a scenario could not be loaded, along with the line of the scenario
+/// where it happened.
+#[derive(Debug, Clone, PartialEq)]
+enum ScenarioError {
+    // The line could not be read.
+    Syntax(usize, String),
+    // The line sets up something that breaks the rules.
+    Setup(usize, RuleBreak),
+    // The scenario lacks an entry that every scenario must have.
+    Missing(&'static str),
+    // The line sets up a unit, weapon or artillery module whose ars or id
+    // was already set up by an earlier line.
+    Duplicate(usize, String),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Syntax(line, reason) => {
+                write!(f, "line {line}: {reason}")
+            }
+            ScenarioError::Setup(line, rule_break) => {
+                write!(f, "line {line}: {rule_break}")
+            }
+            ScenarioError::Missing(entry) => {
+                write!(f, "the scenario has no `{entry}` entry")
+            }
+            ScenarioError::Duplicate(line, item) => {
+                write!(f, "line {line}: {item} is already set up")
+            }
+        }
+    }
+}
+
+impl FromStr for Side {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Side, ()> {
+        match s {
+            "Allies" => Ok(Side::Allies),
+            "Axis" => Ok(Side::Axis),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for WeaponType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<WeaponType, ()> {
+        match s {
+            "Rifle" => Ok(WeaponType::Rifle),
+            "LMG" => Ok(WeaponType::LMG),
+            "MMG" => Ok(WeaponType::MMG),
+            "HMG" => Ok(WeaponType::HMG),
+            "Mortar" => Ok(WeaponType::Mortar),
+            "Demolition" => Ok(WeaponType::Demolition),
+            "Flamethrower" => Ok(WeaponType::Flamethrower),
+            "APMine" => Ok(WeaponType::APMine),
+            "ATMine" => Ok(WeaponType::ATMine),
+            "ATRifle" => Ok(WeaponType::ATRifle),
+            "ATGun" => Ok(WeaponType::ATGun),
+            "TankGun" => Ok(WeaponType::TankGun),
+            "Artillery" => Ok(WeaponType::Artillery),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for Edge {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Edge, ()> {
+        match s {
+            "North" => Ok(Edge::North),
+            "East" => Ok(Edge::East),
+            "South" => Ok(Edge::South),
+            "West" => Ok(Edge::West),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for VehicleType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<VehicleType, ()> {
+        match s {
+            "Tank" => Ok(VehicleType::Tank),
+            "AssaultGun" => Ok(VehicleType::AssaultGun),
+            "Halftrack" => Ok(VehicleType::Halftrack),
+            "ArmouredCar" => Ok(VehicleType::ArmouredCar),
+            "Truck" => Ok(VehicleType::Truck),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for Terrain {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Terrain, ()> {
+        match s {
+            "OpenGround" => Ok(Terrain::OpenGround),
+            "Shellhole" => Ok(Terrain::Shellhole),
+            "Wheatfield" => Ok(Terrain::Wheatfield),
+            "OnRoad" => Ok(Terrain::OnRoad),
+            "OntoRoad" => Ok(Terrain::OntoRoad),
+            "Woods" => Ok(Terrain::Woods),
+            "EnterBuilding" => Ok(Terrain::EnterBuilding),
+            "WithinBuilding" => Ok(Terrain::WithinBuilding),
+            "OverWall" => Ok(Terrain::OverWall),
+            "Bunker" => Ok(Terrain::Bunker),
+            "Rubble" => Ok(Terrain::Rubble),
+            "Blaze" => Ok(Terrain::Blaze),
+            "Water" => Ok(Terrain::Water),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for Armor {
+    type Err = ();
+
+    /// Reads the armor factors written as `front/side/rear`.
+    fn from_str(s: &str) -> Result<Armor, ()> {
+        let mut factors = s.split('/').map(|f| f.parse::<u8>());
+        match (factors.next(), factors.next(), factors.next(), factors.next())
+        {
+            (Some(Ok(front)), Some(Ok(side)), Some(Ok(rear)), None) => {
+                Ok(Armor { front, side, rear })
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+/// The length of a scenario, written as a number of game turns, optionally
+/// followed by `.5` for a closing half turn.
+struct Turns(u8, bool);
+
+impl FromStr for Turns {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Turns, ()> {
+        match s.split_once('.') {
+            None => s.parse().map(|turns| Turns(turns, false)).map_err(|_| ()),
+            Some((turns, "5")) => {
+                turns.parse().map(|turns| Turns(turns, true)).map_err(|_| ())
+            }
+            Some(_) => Err(()),
+        }
+    }
+}
+
+/// The words of a single line of a scenario.
+struct Entry<'a> {
+    line: usize,
+    words: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> Entry<'a> {
+    /// Reads the next word of the entry as the expected item.
+    fn next<T: FromStr>(
+        &mut self,
+        expected: &str,
+    ) -> Result<T, ScenarioError> {
+        let word = self.words.next().ok_or_else(|| {
+            ScenarioError::Syntax(self.line, format!("missing {expected}"))
+        })?;
+        word.parse().map_err(|_| {
+            let reason = format!("expected {expected}, found `{word}`");
+            ScenarioError::Syntax(self.line, reason)
+        })
+    }
+
+    /// Reads every remaining word of the entry as the expected item, up to
+    /// the end of the entry, the next `and`, or the `:` leading the dice of
+    /// a game record.
+    fn all<T: FromStr>(
+        &mut self,
+        expected: &str,
+    ) -> Result<Vec<T>, ScenarioError> {
+        let mut items = Vec::new();
+        while !matches!(self.words.clone().next(), None | Some("and" | ":")) {
+            items.push(self.next(expected)?);
+        }
+        Ok(items)
+    }
+
+    /// Returns the rest of the entry as text.
+    fn rest(&mut self) -> String {
+        self.words.by_ref().collect::<Vec<_>>().join(" ")
+    }
+
+    /// Checks that the entry has nothing left to read.
+    fn end(&mut self) -> Result<(), ScenarioError> {
+        match self.words.next() {
+            None => Ok(()),
+            Some(word) => Err(ScenarioError::Syntax(
+                self.line,
+                format!("unexpected `{word}`"),
+            )),
+        }
+    }
+
+    fn setup(&self, rule_break: RuleBreak) -> ScenarioError {
+        ScenarioError::Setup(self.line, rule_break)
+    }
+
+    /// Reads the next word of the entry as the ars of a unit that has not
+    /// been set up yet.
+    fn new_unit(
+        &mut self,
+        bm: &BattleManager,
+    ) -> Result<Ipv4Addr, ScenarioError> {
+        let ars = self.next("an ars")?;
+        if bm.sides.contains_key(&ars) {
+            let unit = format!("unit {ars}");
+            return Err(ScenarioError::Duplicate(self.line, unit));
+        }
+        Ok(ars)
+    }
+}
+
+/// Splits the scenario into its entries, skipping comments and blank lines.
+fn entries(scenario: &str) -> impl Iterator<Item = (&str, Entry<'_>)> {
+    scenario.lines().enumerate().filter_map(|(i, line)| {
+        let line_text = line.split('#').next().unwrap_or("");
+        let mut words = line_text.split_whitespace();
+        let keyword = words.next()?;
+        Some((keyword, Entry { line: i + 1, words }))
+    })
+}
+
+impl ScenarioManager {
+    /// A21.1 Reads the scenario, and sets up a BattleManager ready to play
+    /// out the battle that it describes. The header entries are read first,
+    /// then every other entry is set up in the order it is written, except
+    /// that the entries of the side that sets up first are all set up before
+    /// those of the other side.
+    fn load(
+        scenario: &str,
+        seed: u64,
+    ) -> Result<(ScenarioManager, BattleManager), ScenarioError> {
+        let (mut name, mut boards, mut turns) = (None, None, None);
+        let (mut setup, mut first) = (None, None);
+        for (keyword, mut entry) in entries(scenario) {
+            match keyword {
+                "scenario" => name = Some(entry.rest()),
+                "boards" => boards = Some(entry.all("a board number")?),
+                "turns" => turns = Some(entry.next::<Turns>("turns")?),
+                "setup" => setup = Some(entry.next("a side")?),
+                "first" => first = Some(entry.next("a side")?),
+                _ => continue,
+            }
+            entry.end()?;
+        }
+        let first = first.ok_or(ScenarioError::Missing("first"))?;
+        let Turns(turns, half_turn) =
+            turns.ok_or(ScenarioError::Missing("turns"))?;
+        let mut manager = ScenarioManager {
+            name: name.ok_or(ScenarioError::Missing("scenario"))?,
+            boards: boards.ok_or(ScenarioError::Missing("boards"))?,
+            turns,
+            half_turn,
+            phases: 0,
+            turn: 1,
+            game_over: None,
+            setup: setup.ok_or(ScenarioError::Missing("setup"))?,
+            first,
+            rules: Vec::new(),
+            victory: Vec::new(),
+            reinforcements: Vec::new(),
+        };
+        let map = Map::new(&manager.boards);
+        let mut bm = BattleManager::new(map, first, seed);
+        let order = [manager.setup, manager.setup.opponent()];
+        for (pass, setting_up) in order.into_iter().enumerate() {
+            let (mut side, mut group) = (None, None);
+            for (keyword, mut entry) in entries(scenario) {
+                match keyword {
+                    "scenario" | "boards" | "turns" | "setup" | "first" => {
+                        continue
+                    }
+                    "side" => {
+                        side = Some(entry.next("a side")?);
+                        group = None;
+                    }
+                    "rule" | "victory" | "sudden-death" | "terrain"
+                    | "elevation" | "building" | "bunker" | "bridge"
+                    | "building-hexes"
+                        if pass > 0 =>
+                    {
+                        continue
+                    }
+                    "rule" => {
+                        let rule = manager.read_rule(&mut entry)?;
+                        if let SpecialRule::Stacking(limit) = &rule {
+                            bm.set_stacking_limit(*limit);
+                        }
+                        manager.rules.push(rule);
+                    }
+                    "victory" | "sudden-death" => {
+                        let victory = Victory {
+                            side: entry.next("a side")?,
+                            condition: manager.read_victory(&mut entry)?,
+                            sudden_death: keyword == "sudden-death",
+                        };
+                        manager.victory.push(victory);
+                    }
+                    "terrain" | "elevation" | "building" | "bunker"
+                    | "bridge" => {
+                        bm.read_map_entry(keyword, &mut entry)?;
+                    }
+                    "building-hexes" => {
+                        let building = entry.next("a building number")?;
+                        let hexes: Vec<HexId> = entry.all("a hex")?;
+                        for hex in &hexes {
+                            bm.map
+                                .hex(hex)
+                                .ok_or(entry.setup(RuleBreak::E0002))?;
+                        }
+                        bm.buildings.insert(building, hexes);
+                    }
+                    _ => {
+                        let side = side.ok_or_else(|| {
+                            let reason =
+                                format!("`{keyword}` outside of a side");
+                            ScenarioError::Syntax(entry.line, reason)
+                        })?;
+                        if side != setting_up {
+                            continue;
+                        }
+                        if keyword == "reinforcements" {
+                            let reinforcement =
+                                bm.read_reinforcement(side, &mut entry)?;
+                            manager.reinforcements.push(reinforcement);
+                            group = Some(manager.reinforcements.len() - 1);
+                        } else {
+                            let offboard = group.is_some();
+                            let unit = bm.read_side_entry(
+                                side, keyword, &mut entry, offboard,
+                            )?;
+                            if let (Some(group), Some(ars)) = (group, unit) {
+                                manager.reinforcements[group].units.push(ars);
+                            }
+                        }
+                    }
+                }
+                entry.end()?;
+            }
+        }
+        bm.update_control();
+        manager.release_reinforcements(&mut bm);
+        Ok((manager, bm))
+    }
+
+    fn read_rule(
+        &self,
+        entry: &mut Entry,
+    ) -> Result<SpecialRule, ScenarioError> {
+        if entry.words.clone().next() != Some("stacking") {
+            return Ok(SpecialRule::Note(entry.rest()));
+        }
+        entry.words.next();
+        Ok(SpecialRule::Stacking(StackingLimit {
+            squads: entry.next("a number of squads")?,
+            leaders: entry.next("a number of leaders")?,
+            support_weapons: entry.next("a number of support weapons")?,
+            vehicles: entry.next("a number of vehicles")?,
+        }))
+    }
+
+    /// Reads the conditions of a victory, joined together by `and`.
+    fn read_victory(
+        &self,
+        entry: &mut Entry,
+    ) -> Result<VictoryCondition, ScenarioError> {
+        let mut conditions = Vec::new();
+        loop {
+            let kind: String = entry.next("a victory condition")?;
+            conditions.push(match kind.as_str() {
+                "control" => VictoryCondition::Control(entry.all("a hex")?),
+                "control-building" => VictoryCondition::ControlBuildings(
+                    entry.all("a building number")?,
+                ),
+                "exit" => VictoryCondition::Exit(
+                    entry.next("an edge")?,
+                    entry.next("a number of victory points")?,
+                ),
+                "casualties" => VictoryCondition::Casualties(
+                    entry.next("a number of casualty points")?,
+                ),
+                _ => {
+                    return Err(ScenarioError::Syntax(
+                        entry.line,
+                        format!("unknown victory condition `{kind}`"),
+                    ))
+                }
+            });
+            if entry.words.clone().next() != Some("and") {
+                break;
+            }
+            entry.words.next();
+        }
+        if conditions.len() == 1 {
+            Ok(conditions.remove(0))
+        } else {
+            Ok(VictoryCondition::All(conditions))
+        }
+    }
+}
+
+impl BattleManager {
+    /// Sets up the terrain, elevation or structure of a hex.
+    fn read_map_entry(
+        &mut self,
+        keyword: &str,
+        entry: &mut Entry,
+    ) -> Result<(), ScenarioError> {
+        let id: HexId = entry.next("a hex")?;
+        let hex =
+            self.map.hex_mut(&id).ok_or(entry.setup(RuleBreak::E0002))?;
+        match keyword {
+            "terrain" => hex.terrain = entry.all("a terrain")?,
+            "elevation" => hex.elevation = entry.next("an elevation")?,
+            _ => {
+                let kind = match keyword {
+                    "bunker" => StructureKind::Bunker,
+                    "bridge" => StructureKind::Bridge,
+                    _ => StructureKind::Building,
+                };
+                let armor = entry.next("an armor factor")?;
+                let health = entry.next("a health")?;
+                let structure = Structure::new(kind, id, armor, health);
+                self.raise_structure(structure).map_err(|e| entry.setup(e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets up a unit, weapon, minefield or artillery module of the side.
+    /// Units held off board as reinforcements are written without a hex, or
+    /// a facing. Returns the unit that was set up, if any.
+    fn read_side_entry(
+        &mut self,
+        side: Side,
+        keyword: &str,
+        entry: &mut Entry,
+        offboard: bool,
+    ) -> Result<Option<Ipv4Addr>, ScenarioError> {
+        let mut unit = None;
+        let result = match keyword {
+            "squad" => {
+                let squad = Squad::new(
+                    entry.new_unit(self)?,
+                    entry.next("a firepower")?,
+                    entry.next("a range")?,
+                    entry.next("a morale")?,
+                );
+                unit = Some(squad.ars);
+                if offboard {
+                    self.squads.insert(squad.ars, squad);
+                    Ok(())
+                } else {
+                    self.deploy_squad(side, squad, entry.next("a hex")?)
+                }
+            }
+            "leader" => {
+                let ars = entry.new_unit(self)?;
+                let leadership = entry.next("a leadership")?;
+                let morale = entry.next("a morale")?;
+                let hex =
+                    if offboard { None } else { Some(entry.next("a hex")?) };
+                let leader =
+                    Leader::new(ars, &entry.rest(), leadership, morale);
+                unit = Some(ars);
+                match hex {
+                    Some(hex) => self.deploy_leader(side, leader, hex),
+                    None => {
+                        self.leaders.insert(ars, leader);
+                        Ok(())
+                    }
+                }
+            }
+            "weapon" => {
+                let id = entry.next("a weapon id")?;
+                if self.weapons.contains_key(&id) {
+                    let weapon = format!("weapon {id}");
+                    return Err(ScenarioError::Duplicate(entry.line, weapon));
+                }
+                let weapon = SupportWeapon::new(
+                    entry.next("a weapon type")?,
+                    entry.next("a firepower")?,
+                    entry.next("a penetration")?,
+                    entry.next("a range")?,
+                    entry.next("a breakdown number")?,
+                    entry.next("a portage cost")?,
+                );
+                let holder: String = entry.next("a carrier or a hex")?;
+                match (holder.parse::<Ipv4Addr>(), holder.parse::<HexId>()) {
+                    (Ok(vehicle), _)
+                        if self.vehicles.contains_key(&vehicle) =>
+                    {
+                        self.mount(id, weapon, vehicle)
+                    }
+                    (Ok(carrier), _) => {
+                        self.issue_support_weapon(id, weapon, carrier)
+                    }
+                    (_, Ok(hex)) => {
+                        self.leave_support_weapon(id, weapon, side, hex)
+                    }
+                    _ => Err(RuleBreak::E0001),
+                }
+            }
+            "vehicle" => {
+                let ars = entry.new_unit(self)?;
+                let kind = entry.next("a vehicle type")?;
+                let mf = entry.next("a MF")?;
+                let armor = entry.next("armor factors")?;
+                let morale = entry.next("a morale")?;
+                let position = if offboard {
+                    None
+                } else {
+                    Some((entry.next("a hex")?, entry.next::<u8>("a facing")?))
+                };
+                let identity = entry.rest();
+                let vehicle =
+                    Vehicle::new(ars, &identity, kind, mf, armor, morale);
+                unit = Some(ars);
+                match position {
+                    Some((hex, facing)) => self.deploy_vehicle(
+                        side,
+                        vehicle,
+                        hex,
+                        Facing(facing % 6),
+                    ),
+                    None => {
+                        self.vehicles.insert(ars, vehicle);
+                        Ok(())
+                    }
+                }
+            }
+            "minefield" => {
+                let hex = entry.next("a hex")?;
+                let weapon = entry.next("a mine type")?;
+                let strength = entry.next("a strength")?;
+                let minefield = Minefield::new(weapon, strength, side);
+                self.lay_minefield(hex, minefield)
+            }
+            "area" => {
+                let mut area = Vec::new();
+                for word in entry.all::<String>("a hex or a board")? {
+                    if let Ok(hex) = word.parse::<HexId>() {
+                        area.push(hex);
+                    } else if let Ok(board) = word.parse::<u8>() {
+                        if !self.map.boards.contains(&board) {
+                            return Err(entry.setup(RuleBreak::E0002));
+                        }
+                        let hexes = self.map.hexes.keys();
+                        area.extend(hexes.filter(|hex| hex.board == board));
+                    } else {
+                        let reason = format!(
+                            "expected a hex or a board, found `{word}`"
+                        );
+                        return Err(ScenarioError::Syntax(entry.line, reason));
+                    }
+                }
+                area.sort();
+                if area.iter().any(|hex| self.map.hex(hex).is_none()) {
+                    Err(RuleBreak::E0002)
+                } else {
+                    for hex in area {
+                        self.claim(side, hex);
+                    }
+                    Ok(())
+                }
+            }
+            "artillery" => {
+                let id = entry.next("an artillery id")?;
+                if self.artillery.contains_key(&id) {
+                    let module = format!("artillery module {id}");
+                    return Err(ScenarioError::Duplicate(entry.line, module));
+                }
+                let mut module = ArtilleryModule::new(
+                    side,
+                    entry.next("a firepower")?,
+                    entry.next("a radio")?,
+                    entry.next("an access number")?,
+                    entry.next("a number of fire missions")?,
+                );
+                for hex in entry.all("a hex")? {
+                    module = module.with_pre_registered(hex);
+                }
+                self.add_artillery(id, module)
+            }
+            _ => {
+                return Err(ScenarioError::Syntax(
+                    entry.line,
+                    format!("unknown entry `{keyword}`"),
+                ))
+            }
+        };
+        result.map_err(|e| entry.setup(e))?;
+        if let (true, Some(ars)) = (offboard, unit) {
+            self.hold_offboard(side, ars);
+        }
+        Ok(unit)
+    }
+
+    /// Reads a group of reinforcements: the game turn it may arrive in, the
+    /// edge or hexes it enters through, and the dice roll it must make to
+    /// arrive, if any.
+    fn read_reinforcement(
+        &self,
+        side: Side,
+        entry: &mut Entry,
+    ) -> Result<Reinforcement, ScenarioError> {
+        let turn = entry.next("a game turn")?;
+        let mut hexes = Vec::new();
+        let mut arrival = None;
+        while let Some(word) = entry.words.next() {
+            if word == "roll" {
+                arrival = Some(entry.next("an arrival number")?);
+            } else if let Ok(edge) = word.parse::<Edge>() {
+                let edge_hexes = self.map.hexes.keys();
+                hexes.extend(edge_hexes.filter(|h| self.map.on_edge(h, edge)));
+            } else if let Ok(hex) = word.parse::<HexId>() {
+                self.map.hex(&hex).ok_or(entry.setup(RuleBreak::E0002))?;
+                hexes.push(hex);
+            } else {
+                let reason =
+                    format!("expected an edge or a hex, found `{word}`");
+                return Err(ScenarioError::Syntax(entry.line, reason));
+            }
+        }
+        hexes.sort();
+        hexes.dedup();
+        Ok(Reinforcement {
+            side,
+            turn,
+            hexes,
+            arrival,
+            units: Vec::new(),
+            released: false,
+        })
+    }
+}
+
+// A21.4 Victory points are awarded for every enemy unit eliminated, and for
+// every friendly unit exited off the map. Passengers count on their own.
+const SQUAD_VICTORY_POINTS: u16 = 2;
+const LEADER_VICTORY_POINTS: u16 = 1;
+const VEHICLE_VICTORY_POINTS: u16 = 3;
+
+impl BattleManager {
+    /// A21.4 Returns the victory points that the unit is worth.
+    fn victory_points(&self, ars: &Ipv4Addr) -> u16 {
+        if self.squads.contains_key(ars) {
+            SQUAD_VICTORY_POINTS
+        } else if self.leaders.contains_key(ars) {
+            LEADER_VICTORY_POINTS
+        } else if self.vehicles.contains_key(ars) {
+            VEHICLE_VICTORY_POINTS
+        } else {
+            0
+        }
+    }
+
+    /// Returns the casualty points the side has inflicted on the enemy,
+    /// that is the victory points of every enemy unit eliminated.
+    fn casualty_points(&self, side: Side) -> u16 {
+        self.sides
+            .iter()
+            .filter(|(ars, s)| {
+                **s != side
+                    && !self.locations.contains_key(ars)
+                    && !self.exited.contains_key(ars)
+                    && !self.offboard.contains_key(ars)
+            })
+            .map(|(ars, _)| self.victory_points(ars))
+            .sum()
+    }
+
+    /// Returns the victory points of the units of the side that exited the
+    /// map by the edge.
+    fn exit_points(&self, side: Side, edge: Edge) -> u16 {
+        self.exited
+            .iter()
+            .filter(|(ars, e)| **e == edge && self.sides[ars] == side)
+            .map(|(ars, _)| self.victory_points(ars))
+            .sum()
+    }
+}
+
+impl VictoryCondition {
+    /// Returns true if the side meets the condition.
+    fn met(&self, side: Side, bm: &BattleManager) -> bool {
+        match self {
+            VictoryCondition::Control(hexes) => {
+                hexes.iter().all(|hex| bm.controller(hex) == Some(side))
+            }
+            VictoryCondition::ControlBuildings(buildings) => {
+                buildings.iter().all(|building| {
+                    bm.building_controller(building) == Some(side)
+                })
+            }
+            VictoryCondition::Exit(edge, points) => {
+                bm.exit_points(side, *edge) >= *points
+            }
+            VictoryCondition::Casualties(points) => {
+                bm.casualty_points(side) >= *points
+            }
+            VictoryCondition::All(conditions) => {
+                conditions.iter().all(|c| c.met(side, bm))
+            }
+        }
+    }
+}
+
+impl fmt::Display for VictoryCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VictoryCondition::Control(hexes) => {
+                write!(f, "control")?;
+                for hex in hexes {
+                    write!(f, " {hex}")?;
+                }
+                Ok(())
+            }
+            VictoryCondition::ControlBuildings(buildings) => {
+                write!(f, "control-building")?;
+                for building in buildings {
+                    write!(f, " {building}")?;
+                }
+                Ok(())
+            }
+            VictoryCondition::Exit(edge, points) => {
+                write!(f, "exit {edge:?} {points}")
+            }
+            VictoryCondition::Casualties(points) => {
+                write!(f, "casualties {points}")
+            }
+            VictoryCondition::All(conditions) => {
+                for (i, condition) in conditions.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " and ")?;
+                    }
+                    write!(f, "{condition}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ScenarioManager {
+    /// Returns the side that met a sudden death victory, if any.
+    fn sudden_death(&self, bm: &BattleManager) -> Option<Side> {
+        self.victory
+            .iter()
+            .find(|v| v.sudden_death && v.condition.met(v.side, bm))
+            .map(|v| v.side)
+    }
+
+    /// Returns the winner of the battle as it stands. A side that meets one
+    /// of its victories wins, unless both sides do. If neither side does,
+    /// and only one side has any victories to meet at the end of the battle,
+    /// the other side wins by holding it off.
+    fn winner(&self, bm: &BattleManager) -> Option<Side> {
+        let met = |side: Side| {
+            self.victory
+                .iter()
+                .any(|v| v.side == side && v.condition.met(side, bm))
+        };
+        match (met(Side::Allies), met(Side::Axis)) {
+            (true, false) => Some(Side::Allies),
+            (false, true) => Some(Side::Axis),
+            (true, true) => None,
+            (false, false) => {
+                let mut sides: Vec<Side> = self
+                    .victory
+                    .iter()
+                    .filter(|v| !v.sudden_death)
+                    .map(|v| v.side)
+                    .collect();
+                sides.sort();
+                sides.dedup();
+                match sides[..] {
+                    [side] => Some(side.opponent()),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Reports the state of every victory condition of the scenario.
+    fn report(&self, bm: &BattleManager) -> VictoryReport {
+        let sides = [Side::Allies, Side::Axis];
+        VictoryReport {
+            scenario: self.name.clone(),
+            turn: self.turn,
+            game_over: self.game_over.clone(),
+            winner: self
+                .game_over
+                .as_ref()
+                .map_or_else(|| self.winner(bm), |game_over| game_over.winner),
+            victories: self
+                .victory
+                .iter()
+                .map(|v| (v.clone(), v.condition.met(v.side, bm)))
+                .collect(),
+            casualty_points: sides
+                .iter()
+                .map(|side| (*side, bm.casualty_points(*side)))
+                .collect(),
+            exit_points: sides
+                .iter()
+                .map(|side| {
+                    let edges =
+                        [Edge::North, Edge::East, Edge::South, Edge::West];
+                    let points =
+                        edges.iter().map(|e| bm.exit_points(*side, *e));
+                    (*side, points.sum())
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A21.5 The victory report of a battle: the standing of every victory
+/// condition, and the points scored by each side.
+#[derive(Debug, Clone, PartialEq)]
+struct VictoryReport {
+    scenario: String,
+    turn: u8,
+    game_over: Option<GameOver>,
+    // The winner of the battle, or the side that would win if the battle
+    // ended now.
+    winner: Option<Side>,
+    victories: Vec<(Victory, bool)>,
+    casualty_points: BTreeMap<Side, u16>,
+    exit_points: BTreeMap<Side, u16>,
+}
+
+impl fmt::Display for VictoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.game_over {
+            Some(game_over) => writeln!(
+                f,
+                "{}: game over in game turn {} ({:?})",
+                self.scenario, game_over.turn, game_over.reason
+            )?,
+            None => writeln!(f, "{}: game turn {}", self.scenario, self.turn)?,
+        }
+        match self.winner {
+            Some(side) => writeln!(f, "{side:?} victory")?,
+            None => writeln!(f, "draw")?,
+        }
+        for (victory, met) in &self.victories {
+            let kind =
+                if victory.sudden_death { "sudden death" } else { "victory" };
+            let met = if *met { "met" } else { "not met" };
+            writeln!(
+                f,
+                "{:?} {kind}: {} ({met})",
+                victory.side, victory.condition
+            )?;
+        }
+        for (side, points) in &self.casualty_points {
+            let exit = self.exit_points.get(side).copied().unwrap_or(0);
+            writeln!(
+                f,
+                "{side:?}: {points} casualty points, {exit} exit points"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// A21.6 Control: every hex is controlled by the side whose unbroken units were
+// the last to be alone in it, whether they stopped in the hex or just moved
+// through it. A hex nobody has entered is controlled by the side whose setup
+// area it lies in, if any. A building that spans several hexes is
+// controlled by a side that controls every one of its hexes.
+
+impl BattleManager {
+    /// Returns the side controlling the hex, if any.
+    fn controller(&self, hex: &HexId) -> Option<Side> {
+        self.control.get(hex).copied()
+    }
+
+    /// Returns the side controlling every hex of the building, if any.
+    fn building_controller(&self, building: &u32) -> Option<Side> {
+        let hexes = self.buildings.get(building)?;
+        let mut sides = hexes.iter().map(|hex| self.controller(hex));
+        let first = sides.next()??;
+        sides.all(|side| side == Some(first)).then_some(first)
+    }
+
+    /// Returns every hex controlled by the side.
+    fn controlled_by(&self, side: Side) -> Vec<HexId> {
+        self.control
+            .iter()
+            .filter(|(_, s)| **s == side)
+            .map(|(hex, _)| *hex)
+            .collect()
+    }
+
+    /// Hands control of the hex to the side, unless the enemy has units in
+    /// it, or every unit of the side in it is broken.
+    fn claim(&mut self, side: Side, hex: HexId) {
+        let units = self.units_in(&hex);
+        if units.iter().any(|ars| self.sides[ars] != side)
+            || (!units.is_empty() && units.iter().all(|ars| self.broken(ars)))
+            || self.controller(&hex) == Some(side)
+        {
+            return;
+        }
+        let buildings: Vec<u32> = self
+            .buildings
+            .iter()
+            .filter(|(_, hexes)| hexes.contains(&hex))
+            .map(|(building, _)| *building)
+            .collect();
+        self.control.insert(hex, side);
+        self.events.push(Event::ControlChanged { hex, side });
+        for building in buildings {
+            if self.building_controller(&building) == Some(side) {
+                self.events.push(Event::BuildingControlled { building, side });
+            }
+        }
+    }
+
+    /// Claims the hexes along the path for the side of the units that moved
+    /// along it, up to the hex where the units ended up.
+    fn claim_path(&mut self, units: &[Ipv4Addr], path: &[HexId]) {
+        let Some(side) = units.first().and_then(|ars| self.sides.get(ars))
+        else {
+            return;
+        };
+        let side = *side;
+        let reached = path.iter().rposition(|hex| {
+            units.iter().any(|ars| self.locations.get(ars) == Some(hex))
+        });
+        if let Some(reached) = reached {
+            for hex in &path[..=reached] {
+                self.claim(side, *hex);
+            }
+        }
+    }
+
+    /// Hands control of every hex occupied by a single side to that side.
+    fn update_control(&mut self) {
+        let mut occupied: Vec<(HexId, Side)> = self
+            .locations
+            .iter()
+            .map(|(ars, hex)| (*hex, self.sides[ars]))
+            .collect();
+        occupied.sort();
+        occupied.dedup();
+        for (hex, side) in occupied {
+            self.claim(side, hex);
+        }
+    }
+
+    /// Returns the control overlay of the map, one line for every row of
+    /// hexes, where `A` marks hexes controlled by the Allies, `X` hexes
+    /// controlled by the Axis, and `.` uncontrolled hexes.
+    fn control_overlay(&self) -> String {
+        let rows = self.map.boards.len() as i32 * BOARD_ROWS as i32;
+        let mut overlay = String::new();
+        for row in 0..rows {
+            for column in 0..BOARD_COLUMNS as i32 {
+                let hex = self.map.at(column, row);
+                overlay.push(
+                    match hex.and_then(|hex| self.controller(&hex)) {
+                        Some(Side::Allies) => 'A',
+                        Some(Side::Axis) => 'X',
+                        None => '.',
+                    },
+                );
+            }
+            overlay.push('\n');
+        }
+        overlay
+    }
+}
+
+// A21.7 Reinforcements are held off board until the game turn they are due
+// to arrive in. At the start of each player turn of their side, from that
+// game turn on, reinforcements that must roll for their arrival arrive if
+// the dice roll is equal to, or less than, their arrival number. Once they
+// have arrived, reinforcements may enter the map during Phase::Movement in
+// one of their entry hexes, spending the MF of entering that hex from off
+// board, and may then move on with the MF they have left.
+
+/// A21.7 A group of reinforcements, arriving together.
+#[derive(Debug, Clone, PartialEq)]
+struct Reinforcement {
+    side: Side,
+    // The first game turn the group may arrive in.
+    turn: u8,
+    // The hexes that the group may enter the map through.
+    hexes: Vec<HexId>,
+    arrival: Option<u8>,
+    units: Vec<Ipv4Addr>,
+    released: bool,
+}
+
+impl ScenarioManager {
+    /// Releases the reinforcements of the side starting its player turn, if
+    /// they are due and arrive.
+    fn release_reinforcements(&mut self, bm: &mut BattleManager) {
+        if bm.phase != Phase::Rally {
+            return;
+        }
+        let turn = self.turn;
+        let due = self.reinforcements.iter_mut().filter(|group| {
+            !group.released && group.side == bm.attacker && group.turn <= turn
+        });
+        for group in due {
+            let roll = group.arrival.map(|_| bm.dice.roll());
+            let arrived = match (roll, group.arrival) {
+                (Some(roll), Some(arrival)) => roll.total() <= arrival,
+                _ => true,
+            };
+            bm.events.push(Event::Reinforcements {
+                units: group.units.clone(),
+                roll,
+                arrived,
+            });
+            if arrived {
+                group.released = true;
+                for ars in &group.units {
+                    bm.offboard.insert(*ars, group.hexes.clone());
+                }
+            }
+        }
+    }
+}
+
+impl BattleManager {
+    /// Holds a unit of the side off board, until it arrives as a
+    /// reinforcement.
+    fn hold_offboard(&mut self, side: Side, ars: Ipv4Addr) {
+        self.sides.insert(ars, side);
+        self.conditions.insert(ars, Condition::Composed);
+        self.markers.insert(ars, Vec::new());
+        self.offboard.insert(ars, Vec::new());
+    }
+
+    /// A21.7 Orders a stack of reinforcements to enter the map in the hex.
+    /// Every unit spends the MF of entering the hex from off board. Returns
+    /// the highest MF cost paid by any unit in the stack.
+    fn enter_map(
+        &mut self,
+        stack: &[Ipv4Addr],
+        hex: HexId,
+    ) -> Result<u8, RuleBreak> {
+        let first = stack.first().ok_or(RuleBreak::E0001)?;
+        check_distinct(stack)?;
+        let side = *self.sides.get(first).ok_or(RuleBreak::E0001)?;
+        let entered = self.map.hex(&hex).ok_or(RuleBreak::E0002)?;
+        let mut costs = Vec::new();
+        for ars in stack {
+            let hexes = self.offboard.get(ars).ok_or(RuleBreak::A2170)?;
+            if hexes.is_empty() {
+                return Err(RuleBreak::A2170);
+            }
+            if !hexes.contains(&hex) {
+                return Err(RuleBreak::A2171);
+            }
+            if self.sides[ars] != side {
+                return Err(RuleBreak::A5200);
+            }
+            self.check_may_move(ars)?;
+            let cost = match self.vehicles.get(ars) {
+                Some(vehicle) => {
+                    let locomotion = vehicle.kind.locomotion();
+                    entered
+                        .terrain
+                        .iter()
+                        .map(|t| t.vehicle_movement_cost(locomotion))
+                        .sum::<Option<u8>>()
+                        .ok_or(RuleBreak::A1821)?
+                }
+                None => get_terrain_effect_on_move(entered),
+            };
+            let spent = self.mf_spent.get(ars).copied().unwrap_or(0);
+            if spent + cost > self.mf_allowance(ars) {
+                return Err(RuleBreak::E5200);
+            }
+            costs.push(cost);
+        }
+        if entered.terrain.contains(&Terrain::Blaze) {
+            return Err(RuleBreak::A8930);
+        }
+        if entered.terrain.contains(&Terrain::Water) {
+            return Err(RuleBreak::A1200);
+        }
+        if self.units_in(&hex).iter().any(|ars| self.sides[ars] != side) {
+            return Err(RuleBreak::E5600);
+        }
+        self.check_stacking(side, stack, &hex)?;
+
+        // Vehicles enter facing away from the edge they entered by.
+        let edges = [Edge::North, Edge::East, Edge::South, Edge::West];
+        let facing = match edges.iter().find(|e| self.map.on_edge(&hex, **e)) {
+            Some(Edge::North) => Facing(3),
+            Some(Edge::East) => Facing(4),
+            Some(Edge::West) => Facing(1),
+            _ => Facing(0),
+        };
+        for (ars, cost) in stack.iter().zip(&costs) {
+            self.offboard.remove(ars);
+            self.locations.insert(*ars, hex);
+            *self.mf_spent.entry(*ars).or_default() += cost;
+            self.add_marker(*ars, Marker::Moved);
+            if let Some(vehicle) = self.vehicles.get(ars) {
+                if vehicle.kind.turret() {
+                    self.turrets.insert(*ars, facing);
+                }
+                self.facings.insert(*ars, facing);
+            }
+        }
+        self.events.push(Event::Entered { units: stack.to_vec(), hex });
+        self.trigger_mines(stack, &[hex]);
+        self.claim_path(stack, &[hex]);
+        Ok(costs.into_iter().max().unwrap_or(0))
+    }
+}
+
+// Saved games
+//
+// A battle may be saved at any point, and resumed later on.
+// The saved game holds everything needed to play on exactly as if the battle
+// had never been interrupted: the scenario and its turn record chart, the map,
+// every unit, weapon and marker, the state of the dice, and the event log.
+//
+// A saved game is plain text, starting with a line naming the version of the
+// format. Each part of the battle follows on a line of its own, led by the
+// name of the part, and written as whitespace separated words.
+const SAVE_FORMAT: &str = "squadleader-save";
+const SAVE_VERSION: u32 = 1;
+
+/// Why a saved game could not be loaded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SaveError {
+    // The text is not a saved game.
+    Format,
+    // The game was saved by an unsupported version of the format.
+    Version(u32),
+    // The part of the saved game could not be read.
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Format => write!(f, "not a saved game"),
+            SaveError::Version(version) => write!(
+                f,
+                "saved game version {version} is not supported, expected \
+                 version {SAVE_VERSION}"
+            ),
+            SaveError::Corrupt(part) => {
+                write!(f, "the `{part}` of the saved game is corrupt")
+            }
+        }
+    }
+}
+
+/// Writes the words of a saved game.
+struct SaveWriter {
+    text: String,
+}
+
+impl SaveWriter {
+    fn word(&mut self, word: impl fmt::Display) {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push(' ');
+        }
+        self.text.push_str(&word.to_string());
+    }
+
+    /// Writes a part of the saved game on a line of its own.
+    fn part(&mut self, name: &str, value: &impl Save) {
+        self.text.push('\n');
+        self.word(name);
+        value.save(self);
+    }
+}
+
+/// Reads the words of a saved game, keeping track of the part being read.
+struct SaveReader<'a> {
+    words: std::str::SplitWhitespace<'a>,
+    part: &'static str,
+}
+
+impl<'a> SaveReader<'a> {
+    fn word(&mut self) -> Result<&'a str, SaveError> {
+        self.words.next().ok_or(SaveError::Corrupt(self.part))
+    }
+
+    fn parse<T: FromStr>(&mut self) -> Result<T, SaveError> {
+        let part = self.part;
+        self.word()?.parse().map_err(|_| SaveError::Corrupt(part))
+    }
+
+    fn part<T: Save>(&mut self, name: &'static str) -> Result<T, SaveError> {
+        self.part = name;
+        if self.word()? != name {
+            return Err(SaveError::Corrupt(name));
+        }
+        T::load(self)
+    }
+}
+
+/// Anything that is part of the state of a battle, and therefore must be
+/// written to, and read back from, a saved game.
+trait Save: Sized {
+    fn save(&self, out: &mut SaveWriter);
+    fn load(input: &mut SaveReader) -> Result<Self, SaveError>;
+}
+
+// Values written as a single word.
+macro_rules! save_word {
+    ($($type:ty),* $(,)?) => {
+        $(
+            impl Save for $type {
+                fn save(&self, out: &mut SaveWriter) {
+                    out.word(self);
+                }
+
+                fn load(input: &mut SaveReader) -> Result<$type, SaveError> {
+                    input.parse()
+                }
+            }
+        )*
+    };
+}
+
+save_word!(u8, u16, u32, u64, i8, bool, Ipv4Addr, HexId);
+
+// Structs are written field by field, in the order the fields are listed.
+macro_rules! save_struct {
+    ($name:ident { $($field:ident),* $(,)? }) => {
+        impl Save for $name {
+            fn save(&self, out: &mut SaveWriter) {
+                $(self.$field.save(out);)*
+            }
+
+            fn load(input: &mut SaveReader) -> Result<$name, SaveError> {
+                Ok($name { $($field: Save::load(input)?),* })
+            }
+        }
+    };
+}
+
+// Enums are written as the name of the variant, followed by its fields.
+macro_rules! save_enum {
+    ($name:ident {
+        $($variant:ident $(($($value:ident),*))? $({$($field:ident),*})?),*
+        $(,)?
+    }) => {
+        impl Save for $name {
+            fn save(&self, out: &mut SaveWriter) {
+                match self {
+                    $($name::$variant $(($($value),*))? $({$($field),*})? => {
+                        out.word(stringify!($variant));
+                        $($($value.save(out);)*)?
+                        $($($field.save(out);)*)?
+                    })*
+                }
+            }
+
+            fn load(input: &mut SaveReader) -> Result<$name, SaveError> {
+                match input.word()? {
+                    $(stringify!($variant) => Ok($name::$variant
+                        $(($({
+                            let $value = Save::load(input)?;
+                            $value
+                        }),*))?
+                        $({$($field: Save::load(input)?),*})?
+                    ),)*
+                    _ => Err(SaveError::Corrupt(input.part)),
+                }
+            }
+        }
+    };
+}
+
+// Text is written as a single word, with whitespace and `%` escaped by their
+// character code. The leading `"` makes sure that even empty text is a word.
+impl Save for String {
+    fn save(&self, out: &mut SaveWriter) {
+        let mut word = String::from("\"");
+        for c in self.chars() {
+            if c.is_whitespace() || c == '%' {
+                word.push_str(&format!("%{:x};", c as u32));
+            } else {
+                word.push(c);
+            }
+        }
+        out.word(word);
+    }
+
+    fn load(input: &mut SaveReader) -> Result<String, SaveError> {
+        let corrupt = SaveError::Corrupt(input.part);
+        let word = input.word()?.strip_prefix('"').ok_or(corrupt)?;
+        let mut escapes = word.split('%');
+        let mut text = escapes.next().unwrap_or_default().to_string();
+        for escape in escapes {
+            let (code, rest) = escape.split_once(';').ok_or(corrupt)?;
+            let code = u32::from_str_radix(code, 16).map_err(|_| corrupt)?;
+            let c = char::from_u32(code).ok_or(corrupt)?;
+            text.push(c);
+            text.push_str(rest);
+        }
+        Ok(text)
+    }
+}
+
+impl<T: Save> Save for Option<T> {
+    fn save(&self, out: &mut SaveWriter) {
+        match self {
+            None => out.word("-"),
+            Some(value) => {
+                out.word("+");
+                value.save(out);
+            }
+        }
+    }
+
+    fn load(input: &mut SaveReader) -> Result<Option<T>, SaveError> {
+        match input.word()? {
+            "-" => Ok(None),
+            "+" => Ok(Some(T::load(input)?)),
+            _ => Err(SaveError::Corrupt(input.part)),
+        }
+    }
+}
+
+// Collections are written as the number of items, followed by the items.
+impl<T: Save> Save for Vec<T> {
+    fn save(&self, out: &mut SaveWriter) {
+        out.word(self.len());
+        for item in self {
+            item.save(out);
+        }
+    }
+
+    fn load(input: &mut SaveReader) -> Result<Vec<T>, SaveError> {
+        let len: usize = input.parse()?;
+        (0..len).map(|_| T::load(input)).collect()
+    }
+}
+
+impl<T: Save + Ord> Save for BTreeSet<T> {
+    fn save(&self, out: &mut SaveWriter) {
+        out.word(self.len());
+        for item in self {
+            item.save(out);
+        }
+    }
+
+    fn load(input: &mut SaveReader) -> Result<BTreeSet<T>, SaveError> {
+        let len: usize = input.parse()?;
+        (0..len).map(|_| T::load(input)).collect()
+    }
+}
+
+impl<K: Save + Ord, V: Save> Save for BTreeMap<K, V> {
+    fn save(&self, out: &mut SaveWriter) {
+        out.word(self.len());
+        for (key, value) in self {
+            key.save(out);
+            value.save(out);
+        }
+    }
+
+    fn load(input: &mut SaveReader) -> Result<BTreeMap<K, V>, SaveError> {
+        let len: usize = input.parse()?;
+        (0..len).map(|_| Ok((K::load(input)?, V::load(input)?))).collect()
+    }
+}
+
+impl<A: Save, B: Save> Save for (A, B) {
+    fn save(&self, out: &mut SaveWriter) {
+        self.0.save(out);
+        self.1.save(out);
+    }
+
+    fn load(input: &mut SaveReader) -> Result<(A, B), SaveError> {
+        Ok((A::load(input)?, B::load(input)?))
+    }
+}
+
+impl Save for Facing {
+    fn save(&self, out: &mut SaveWriter) {
+        self.0.save(out);
+    }
+
+    fn load(input: &mut SaveReader) -> Result<Facing, SaveError> {
+        Ok(Facing(Save::load(input)?))
+    }
+}
+
+impl Save for Squad<Unphased> {
+    fn save(&self, out: &mut SaveWriter) {
+        self.ars.save(out);
+        self.firepower.save(out);
+        self.range.save(out);
+        self.morale.save(out);
+    }
+
+    fn load(input: &mut SaveReader) -> Result<Squad<Unphased>, SaveError> {
+        let ars = Save::load(input)?;
+        let firepower = Save::load(input)?;
+        let range = Save::load(input)?;
+        let morale = Save::load(input)?;
+        Ok(Squad::new(ars, firepower, range, morale))
+    }
+}
+
+// Hexes are written in order, so that the same battle is always saved the
+// same way.
+impl Save for Map {
+    fn save(&self, out: &mut SaveWriter) {
+        self.boards.save(out);
+        let mut hexes: Vec<&Hex> = self.hexes.values().collect();
+        hexes.sort_by_key(|hex| hex.id);
+        out.word(hexes.len());
+        for hex in hexes {
+            hex.save(out);
+        }
+    }
+
+    fn load(input: &mut SaveReader) -> Result<Map, SaveError> {
+        let boards = Save::load(input)?;
+        let hexes: Vec<Hex> = Save::load(input)?;
+        let hexes = hexes.into_iter().map(|hex| (hex.id, hex)).collect();
+        Ok(Map { boards, hexes })
+    }
+}
+
+save_struct!(Hex { id, elevation, terrain, smoke });
+save_struct!(Smoke { side, dispersed });
+save_struct!(Dice { state, rolls });
+save_struct!(DiceRoll { white, coloured });
+save_struct!(Leader { ars, identity, leadership, morale });
+save_struct!(Vehicle { ars, identity, kind, mf, armor, health, morale });
+save_struct!(Armor { front, side, rear });
+save_struct!(Structure { kind, location, armor, health });
+save_struct!(Minefield { weapon, strength, owner, revealed });
+save_struct!(StackingLimit { squads, leaders, support_weapons, vehicles });
+save_struct!(GameOver { turn, reason, winner });
+save_struct!(Victory { side, condition, sudden_death });
+save_struct!(SupportWeapon {
+    weapon,
+    firepower,
+    penetration,
+    range,
+    breakdown,
+    portage,
+    armor_penetration,
+});
+save_struct!(ArtilleryModule {
+    side,
+    firepower,
+    radio,
+    access,
+    missions,
+    pre_registered,
+});
+save_struct!(Reinforcement { side, turn, hexes, arrival, units, released });
+
+save_enum!(Side { Allies, Axis });
+save_enum!(Phase {
+    Rally,
+    PrepFire,
+    Movement,
+    DefensiveFire,
+    AdvancingFire,
+    Rout,
+    Advance,
+    CloseCombat,
+});
+save_enum!(Condition { Broken, Composed });
+save_enum!(Marker {
+    PrepFire,
+    Moved,
+    Fired,
+    Advanced,
+    EnPassant,
+    ProximityPanic,
+    Shocked,
+    Immobilized,
+    SmokeGrenade,
+    Rallied,
+});
+save_enum!(WeaponType {
+    Rifle,
+    LMG,
+    MMG,
+    HMG,
+    Mortar,
+    Demolition,
+    Flamethrower,
+    APMine,
+    ATMine,
+    ATRifle,
+    ATGun,
+    TankGun,
+    Artillery,
+});
+save_enum!(WeaponFamily {
+    Rifle,
+    MachineGun,
+    Mortar,
+    Demolition,
+    Flamethrower,
+    Mine,
+    ATRifle,
+    Gun,
+    Artillery,
+});
+save_enum!(VehicleType { Tank, AssaultGun, Halftrack, ArmouredCar, Truck });
+save_enum!(Terrain {
+    OpenGround,
+    Shellhole,
+    Wheatfield,
+    OnRoad,
+    OntoRoad,
+    Woods,
+    EnterBuilding,
+    WithinBuilding,
+    OverWall,
+    Bunker,
+    Rubble,
+    Blaze,
+    Water,
+});
+save_enum!(StructureKind { Building, Bunker, Bridge });
+save_enum!(Edge { North, East, South, West });
+save_enum!(EndReason { TurnLimit, SuddenDeath });
+save_enum!(FireResult { NoEffect, MoraleCheck(drm), Kill });
+save_enum!(ArmorResult { Miss, NoEffect, Shocked, Immobilized, Destroyed });
+save_enum!(TargetId { Structure(hex), Vehicle(ars) });
+save_enum!(SpecialRule { Stacking(limit), Note(note) });
+save_enum!(VictoryCondition {
+    Control(hexes),
+    ControlBuildings(buildings),
+    Exit(edge, points),
+    Casualties(points),
+    All(conditions),
+});
+save_enum!(Event {
+    PhaseChanged(phase),
+    Moved { units, path },
+    Routed { ars, path },
+    Advanced { units, to },
+    PickedUp { ars, weapon },
+    Captured { ars, weapon },
+    Embarked { ars, vehicle },
+    Disembarked { ars, vehicle },
+    TurretRotated { ars, facing },
+    Damaged { target, health },
+    Destroyed(target),
+    ChargePlaced { ars, weapon, hex },
+    Detonated { weapon, hex, roll, result },
+    Expended(weapon),
+    FireMission { module, target, contact, access },
+    Blast { hex, roll, result },
+    Shellholed(hex),
+    SmokeGrenade { ars, hex, roll },
+    SmokePlaced(hex),
+    SmokeDispersed(hex),
+    SmokeCleared(hex),
+    MineAttack { hex, units, roll },
+    MinefieldRevealed(hex),
+    Ablaze(hex),
+    BurnedOut(hex),
+    AntiTankFire { ars, weapon, target, roll, result },
+    Abandoned { ars, weapon, hex },
+    Transferred { weapon, from, to },
+    Fired { members, weapons, target, roll },
+    Broken(ars),
+    Eliminated(ars),
+    Malfunctioned(weapon),
+    Rally { ars, roll, rallied },
+    Exited { units, edge },
+    ControlChanged { hex, side },
+    BuildingControlled { building, side },
+    Reinforcements { units, roll, arrived },
+    Entered { units, hex },
+    GameTurn(turn),
+    GameOver(game_over),
+});
+
+impl Save for ScenarioManager {
+    fn save(&self, out: &mut SaveWriter) {
+        out.part("scenario", &self.name);
+        out.part("boards", &self.boards);
+        out.part("turns", &(self.turns, self.half_turn));
+        out.part("turn", &(self.phases, self.turn));
+        out.part("game-over", &self.game_over);
+        out.part("sides", &(self.setup, self.first));
+        out.part("rules", &self.rules);
+        out.part("victory", &self.victory);
+        out.part("reinforcements", &self.reinforcements);
+    }
+
+    fn load(input: &mut SaveReader) -> Result<ScenarioManager, SaveError> {
+        let name = input.part("scenario")?;
+        let boards = input.part("boards")?;
+        let (turns, half_turn) = input.part("turns")?;
+        let (phases, turn) = input.part("turn")?;
+        let game_over = input.part("game-over")?;
+        let (setup, first) = input.part("sides")?;
+        Ok(ScenarioManager {
+            name,
+            boards,
+            turns,
+            half_turn,
+            phases,
+            turn,
+            game_over,
+            setup,
+            first,
+            rules: input.part("rules")?,
+            victory: input.part("victory")?,
+            reinforcements: input.part("reinforcements")?,
+        })
+    }
+}
+
+impl Save for BattleManager {
+    fn save(&self, out: &mut SaveWriter) {
+        out.part("map", &self.map);
+        out.part("dice", &self.dice);
+        out.part("phase", &(self.phase, self.attacker));
+        out.part("squads", &self.squads);
+        out.part("leaders", &self.leaders);
+        out.part("vehicles", &self.vehicles);
+        out.part("structures", &self.structures);
+        out.part("minefields", &self.minefields);
+        out.part("artillery", &self.artillery);
+        out.part("exited", &self.exited);
+        out.part("control", &self.control);
+        out.part("buildings", &self.buildings);
+        out.part("offboard", &self.offboard);
+        out.part("unit-sides", &self.sides);
+        out.part("locations", &self.locations);
+        out.part("conditions", &self.conditions);
+        out.part("markers", &self.markers);
+        out.part("weapons", &self.weapons);
+        out.part("carriers", &self.carriers);
+        out.part("abandoned", &self.abandoned);
+        out.part("owners", &self.owners);
+        out.part("mounted", &self.mounted);
+        out.part("malfunctioned", &self.malfunctioned);
+        out.part("charges", &self.charges);
+        out.part("weapon-types-fired", &self.weapon_types_fired);
+        out.part("mf-spent", &self.mf_spent);
+        out.part("moved-portage", &self.moved_portage);
+        out.part("escorts", &self.escorts);
+        out.part("facings", &self.facings);
+        out.part("turrets", &self.turrets);
+        out.part("passengers", &self.passengers);
+        out.part("stacking-limit", &self.stacking_limit);
+        out.part("events", &self.events);
+    }
+
+    fn load(input: &mut SaveReader) -> Result<BattleManager, SaveError> {
+        let map = input.part("map")?;
+        let dice = input.part("dice")?;
+        let (phase, attacker) = input.part("phase")?;
+        Ok(BattleManager {
+            map,
+            dice,
+            phase,
+            attacker,
+            squads: input.part("squads")?,
+            leaders: input.part("leaders")?,
+            vehicles: input.part("vehicles")?,
+            structures: input.part("structures")?,
+            minefields: input.part("minefields")?,
+            artillery: input.part("artillery")?,
+            exited: input.part("exited")?,
+            control: input.part("control")?,
+            buildings: input.part("buildings")?,
+            offboard: input.part("offboard")?,
+            sides: input.part("unit-sides")?,
+            locations: input.part("locations")?,
+            conditions: input.part("conditions")?,
+            markers: input.part("markers")?,
+            weapons: input.part("weapons")?,
+            carriers: input.part("carriers")?,
+            abandoned: input.part("abandoned")?,
+            owners: input.part("owners")?,
+            mounted: input.part("mounted")?,
+            malfunctioned: input.part("malfunctioned")?,
+            charges: input.part("charges")?,
+            weapon_types_fired: input.part("weapon-types-fired")?,
+            mf_spent: input.part("mf-spent")?,
+            moved_portage: input.part("moved-portage")?,
+            escorts: input.part("escorts")?,
+            facings: input.part("facings")?,
+            turrets: input.part("turrets")?,
+            passengers: input.part("passengers")?,
+            stacking_limit: input.part("stacking-limit")?,
+            events: input.part("events")?,
+            history: None,
+        })
+    }
+}
+
+/// Saves the battle, along with the scenario it is fought in.
+fn save_game(scenario: &ScenarioManager, bm: &BattleManager) -> String {
+    let mut out = SaveWriter { text: String::new() };
+    out.word(SAVE_FORMAT);
+    out.word(SAVE_VERSION);
+    scenario.save(&mut out);
+    bm.save(&mut out);
+    out.text.push('\n');
+    out.text
+}
+
+/// Loads a saved game, ready to be played on from where it was saved.
+fn load_game(
+    text: &str,
+) -> Result<(ScenarioManager, BattleManager), SaveError> {
+    let mut words = text.split_whitespace();
+    if words.next() != Some(SAVE_FORMAT) {
+        return Err(SaveError::Format);
+    }
+    let mut input = SaveReader { words, part: "version" };
+    let version = input.parse()?;
+    if version != SAVE_VERSION {
+        return Err(SaveError::Version(version));
+    }
+    let scenario: ScenarioManager = Save::load(&mut input)?;
+    let bm: BattleManager = Save::load(&mut input)?;
+    if input.words.next().is_some() {
+        return Err(SaveError::Corrupt("end"));
+    }
+    bm.check_loaded()?;
+    let mut reinforcements =
+        scenario.reinforcements.iter().flat_map(|group| &group.units);
+    if scenario.boards != bm.map.boards
+        || reinforcements.any(|ars| !bm.sides.contains_key(ars))
+    {
+        return Err(SaveError::Corrupt("scenario"));
+    }
+    Ok((scenario, bm))
+}
+
+impl BattleManager {
+    /// Checks that the parts of a loaded battle agree with each other, that
+    /// is that every unit, weapon and hex any part refers to exists.
+    fn check_loaded(&self) -> Result<(), SaveError> {
+        let unit = |ars: &Ipv4Addr| self.sides.contains_key(ars);
+        let vehicle = |ars: &Ipv4Addr| self.vehicles.contains_key(ars);
+        let weapon = |id: &u32| self.weapons.contains_key(id);
+        let hex = |hex: &HexId| self.map.hexes.contains_key(hex);
+        let check = |part, valid: bool| {
+            if valid {
+                Ok(())
+            } else {
+                Err(SaveError::Corrupt(part))
+            }
+        };
+        check(
+            "unit-sides",
+            self.sides.keys().all(|ars| {
+                self.squads.contains_key(ars)
+                    || self.leaders.contains_key(ars)
+                    || vehicle(ars)
+            }),
+        )?;
+        check("squads", self.squads.keys().all(unit))?;
+        check("leaders", self.leaders.keys().all(unit))?;
+        check("vehicles", self.vehicles.keys().all(unit))?;
+        check("structures", self.structures.keys().all(hex))?;
+        check("minefields", self.minefields.keys().all(hex))?;
+        check(
+            "artillery",
+            self.artillery.values().all(|module| {
+                self.leaders.contains_key(&module.radio)
+                    && module.pre_registered.iter().all(hex)
+            }),
+        )?;
+        check("exited", self.exited.keys().all(unit))?;
+        check("control", self.control.keys().all(hex))?;
+        check("buildings", self.buildings.values().flatten().all(hex))?;
+        check(
+            "offboard",
+            self.offboard
+                .iter()
+                .all(|(ars, hexes)| unit(ars) && hexes.iter().all(hex)),
+        )?;
+        check(
+            "locations",
+            self.locations.iter().all(|(ars, at)| unit(ars) && hex(at)),
+        )?;
+        check("conditions", self.conditions.keys().all(unit))?;
+        check("markers", self.markers.keys().all(unit))?;
+        check(
+            "carriers",
+            self.carriers.iter().all(|(id, ars)| weapon(id) && unit(ars)),
+        )?;
+        check(
+            "abandoned",
+            self.abandoned.iter().all(|(id, at)| weapon(id) && hex(at)),
+        )?;
+        check("owners", self.owners.keys().all(weapon))?;
+        check("mounted", self.mounted.iter().all(weapon))?;
+        check("malfunctioned", self.malfunctioned.iter().all(weapon))?;
+        check(
+            "charges",
+            self.charges
+                .iter()
+                .all(|(id, (ars, at))| weapon(id) && unit(ars) && hex(at)),
+        )?;
+        check("weapon-types-fired", self.weapon_types_fired.keys().all(unit))?;
+        check("mf-spent", self.mf_spent.keys().all(unit))?;
+        check("moved-portage", self.moved_portage.keys().all(unit))?;
+        check(
+            "escorts",
+            self.escorts.iter().all(|(ars, leader)| unit(ars) && unit(leader)),
+        )?;
+        check("facings", self.facings.keys().all(vehicle))?;
+        check("turrets", self.turrets.keys().all(vehicle))?;
+        check(
+            "passengers",
+            self.passengers
+                .iter()
+                .all(|(ars, carrier)| unit(ars) && vehicle(carrier)),
+        )
+    }
+}
+
+// Game records
+//
+// A battle can be written down as it is played, order by order, much like a
+// game of chess is, and the record replayed later on. A record starts out
+// naming the scenario and the seed of the dice, followed by a line for every
+// phase played, and a line for every order given.
+//
+// A phase line holds the game turn, the side of Player::Attacker and the
+// phase. An order line starts with the phase it was given in, followed by
+// the units carrying it out, any support weapons they fire, and the hex they
+// are in (`@off` for units off board). Then follows the order itself, where
+// `->` is the path of a move, rout or advance, or the target of a fire
+// attack. Every line ends with the dice rolled, white die first, if any.
+// For example:
+//
+// ```text
+// scenario The Guards Counterattack
+// seed 11
+// 1 Axis RP
+// 1 Axis PF
+// PF 1.1.1.1 +1 @1C3 -> 1D5 : 4+2 3+3
+// PF 1.1.9.1 +2 @1A1 -> 2.2.9.1 : 5+1 2+6
+// 1 Axis MV
+// MV 1.1.1.1 1.1.1.0 @1C3 -> 1C4 1C5
+// MV 1.1.1.1 @1C5 smoke 1D5 : 2+1
+// MV 2.2.9.1 @off enter 1GG5
+// ...
+// 5 Allies CC
+// game-over 5 TurnLimit Axis
+// ```
+//
+// The other orders are `carry <weapon>`, `abandon <weapon>`,
+// `transfer <weapon> <unit>`, `embark <vehicle>`, `disembark`,
+// `turret <hexside>`, `charge <weapon> <hex>`, `smoke <hex>`, `exit <edge>`,
+// `enter <hex>`, `mission <module> <hex>` and `rally`. A fire attack may
+// name its spotter with `spotter <unit>`, and mortars and artillery fire
+// smoke rounds when followed by `smoke`.
+
+/// An order given to the units on the battlefield.
+#[derive(Debug, Clone)]
+enum Order {
+    // A stack of units, or a vehicle, moving along the path.
+    Move { units: Vec<Ipv4Addr>, path: Vec<HexId> },
+    Rout { ars: Ipv4Addr, path: Vec<HexId> },
+    Advance { units: Vec<Ipv4Addr>, to: HexId },
+    Fire { group: FireGroup, target: HexId },
+    FireAtVehicle { ars: Ipv4Addr, weapon: u32, target: Ipv4Addr },
+    Carry { ars: Ipv4Addr, weapon: u32 },
+    Abandon { ars: Ipv4Addr, weapon: u32 },
+    Transfer { weapon: u32, from: Ipv4Addr, to: Ipv4Addr },
+    Embark { ars: Ipv4Addr, vehicle: Ipv4Addr },
+    Disembark(Ipv4Addr),
+    RotateTurret { ars: Ipv4Addr, facing: Facing },
+    PlaceCharge { ars: Ipv4Addr, weapon: u32, hex: HexId },
+    ThrowSmoke { ars: Ipv4Addr, hex: HexId },
+    ExitMap { units: Vec<Ipv4Addr>, edge: Edge },
+    EnterMap { units: Vec<Ipv4Addr>, hex: HexId },
+    FireMission { module: u32, target: HexId, ammunition: Ammunition },
+    Rally(Ipv4Addr),
+}
+
+impl BattleManager {
+    /// Carries out the order. Only orders given this way can be taken back,
+    /// see `BattleManager::undo`.
+    fn order(&mut self, order: &Order) -> Result<(), RuleBreak> {
+        let before = self.snapshot(Step::Order);
+        self.carry_out(order)?;
+        self.remember(before);
+        Ok(())
+    }
+
+    fn carry_out(&mut self, order: &Order) -> Result<(), RuleBreak> {
+        match order {
+            Order::Move { units, path } => match units.as_slice() {
+                [ars] if self.vehicles.contains_key(ars) => {
+                    self.move_vehicle(*ars, path).map(|_| ())
+                }
+                _ => self.move_stack(units, path).map(|_| ()),
+            },
+            Order::Rout { ars, path } => self.rout(*ars, path),
+            Order::Advance { units, to } => self.advance(units, *to),
+            Order::Fire { group, target } => {
+                self.fire(group, *target).map(|_| ())
+            }
+            Order::FireAtVehicle { ars, weapon, target } => {
+                self.fire_at_vehicle(*ars, *weapon, *target).map(|_| ())
+            }
+            Order::Carry { ars, weapon } => self.carry(*ars, *weapon),
+            Order::Abandon { ars, weapon } => self.abandon(*ars, *weapon),
+            Order::Transfer { weapon, from, to } => {
+                self.transfer(*weapon, *from, *to)
+            }
+            Order::Embark { ars, vehicle } => self.embark(*ars, *vehicle),
+            Order::Disembark(ars) => self.disembark(*ars),
+            Order::RotateTurret { ars, facing } => {
+                self.rotate_turret(*ars, *facing)
+            }
+            Order::PlaceCharge { ars, weapon, hex } => {
+                self.place_charge(*ars, *weapon, *hex)
+            }
+            Order::ThrowSmoke { ars, hex } => {
+                self.throw_smoke(*ars, *hex).map(|_| ())
+            }
+            Order::ExitMap { units, edge } => self.exit_map(units, *edge),
+            Order::EnterMap { units, hex } => {
+                self.enter_map(units, *hex).map(|_| ())
+            }
+            Order::FireMission { module, target, ammunition } => self
+                .call_fire_mission(*module, *target, *ammunition)
+                .map(|_| ()),
+            Order::Rally(ars) => self.rally(*ars).map(|_| ()),
+        }
+    }
+}
+
+impl Order {
+    /// Splits the order into the units carrying it out, the weapons they
+    /// fire, and the notation of the order itself.
+    fn parts(&self, bm: &BattleManager) -> (Vec<Ipv4Addr>, Vec<u32>, String) {
+        match self {
+            Order::Move { units, path } => {
+                (units.clone(), Vec::new(), format!("-> {}", words(path)))
+            }
+            Order::Rout { ars, path } => {
+                (vec![*ars], Vec::new(), format!("-> {}", words(path)))
+            }
+            Order::Advance { units, to } => {
+                (units.clone(), Vec::new(), format!("-> {to}"))
+            }
+            Order::Fire { group, target } => {
+                let mut action = format!("-> {target}");
+                if let Some(spotter) = group.spotter {
+                    action.push_str(&format!(" spotter {spotter}"));
+                }
+                if group.ammunition == Ammunition::Smoke {
+                    action.push_str(" smoke");
+                }
+                (group.members.clone(), group.weapons.clone(), action)
+            }
+            Order::FireAtVehicle { ars, weapon, target } => {
+                (vec![*ars], vec![*weapon], format!("-> {target}"))
+            }
+            Order::Carry { ars, weapon } => {
+                (vec![*ars], Vec::new(), format!("carry {weapon}"))
+            }
+            Order::Abandon { ars, weapon } => {
+                (vec![*ars], Vec::new(), format!("abandon {weapon}"))
+            }
+            Order::Transfer { weapon, from, to } => {
+                (vec![*from], Vec::new(), format!("transfer {weapon} {to}"))
+            }
+            Order::Embark { ars, vehicle } => {
+                (vec![*ars], Vec::new(), format!("embark {vehicle}"))
+            }
+            Order::Disembark(ars) => {
+                (vec![*ars], Vec::new(), "disembark".to_string())
+            }
+            Order::RotateTurret { ars, facing } => {
+                (vec![*ars], Vec::new(), format!("turret {}", facing.0))
+            }
+            Order::PlaceCharge { ars, weapon, hex } => {
+                (vec![*ars], Vec::new(), format!("charge {weapon} {hex}"))
+            }
+            Order::ThrowSmoke { ars, hex } => {
+                (vec![*ars], Vec::new(), format!("smoke {hex}"))
+            }
+            Order::ExitMap { units, edge } => {
+                (units.clone(), Vec::new(), format!("exit {edge:?}"))
+            }
+            Order::EnterMap { units, hex } => {
+                (units.clone(), Vec::new(), format!("enter {hex}"))
+            }
+            Order::FireMission { module, target, ammunition } => {
+                let radio = bm.artillery.get(module).map(|m| m.radio);
+                let mut action = format!("mission {module} {target}");
+                if *ammunition == Ammunition::Smoke {
+                    action.push_str(" smoke");
+                }
+                (radio.into_iter().collect(), Vec::new(), action)
+            }
+            Order::Rally(ars) => (vec![*ars], Vec::new(), "rally".to_string()),
+        }
+    }
+
+    /// Writes the order down in the notation of a game record, as given in
+    /// the current phase of the battle.
+    fn notation(&self, bm: &BattleManager) -> String {
+        let (units, weapons, action) = self.parts(bm);
+        let mut notation = format!("{} {}", bm.phase.code(), words(&units));
+        for weapon in weapons {
+            notation.push_str(&format!(" +{weapon}"));
+        }
+        match units.first().and_then(|ars| bm.locations.get(ars)) {
+            Some(hex) => notation.push_str(&format!(" @{hex} {action}")),
+            None => notation.push_str(&format!(" @off {action}")),
+        }
+        notation
+    }
+}
+
+/// Joins the items into words separated by spaces.
+fn words<T: fmt::Display>(items: &[T]) -> String {
+    let words: Vec<String> = items.iter().map(T::to_string).collect();
+    words.join(" ")
+}
+
+impl Phase {
+    /// The short code of the phase used in game records.
+    fn code(&self) -> &'static str {
+        match self {
+            Phase::Rally => "RP",
+            Phase::PrepFire => "PF",
+            Phase::Movement => "MV",
+            Phase::DefensiveFire => "DF",
+            Phase::AdvancingFire => "AF",
+            Phase::Rout => "RT",
+            Phase::Advance => "AD",
+            Phase::CloseCombat => "CC",
+        }
+    }
+}
+
+impl FromStr for Phase {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Phase, ()> {
+        let mut phase = Phase::Rally;
+        for _ in 0..PHASES_PER_PLAYER_TURN {
+            if phase.code() == s {
+                return Ok(phase);
+            }
+            phase = phase.next();
+        }
+        Err(())
+    }
+}
+
+impl fmt::Display for DiceRoll {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{}", self.white, self.coloured)
+    }
+}
+
+impl FromStr for DiceRoll {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<DiceRoll, ()> {
+        let (white, coloured) = s.split_once('+').ok_or(())?;
+        let die = |die: &str| match die.parse() {
+            Ok(pips @ 1..=6) => Ok(pips),
+            _ => Err(()),
+        };
+        Ok(DiceRoll { white: die(white)?, coloured: die(coloured)? })
+    }
+}
+
+impl Dice {
+    /// Returns every roll made since the dice were in the earlier state.
+    /// The rolls are counted off from the earlier state, rather than found
+    /// by rolling the earlier dice until they catch up.
+    fn rolls_since(&self, earlier: &Dice) -> Vec<DiceRoll> {
+        let count = self.rolls.saturating_sub(earlier.rolls);
+        let mut dice = earlier.clone();
+        (0..count).map(|_| dice.roll()).collect()
+    }
+}
+
+// The lines naming the scenario and the seed, and the phase the battle
+// starts in, which are never taken back.
+const RECORD_HEADER: usize = 3;
+
+/// A record of a battle, written as it is played.
+#[derive(Debug, Clone)]
+struct GameRecord {
+    lines: Vec<String>,
+    // The dice as they were when the last line was written.
+    dice: Dice,
+    // The lines of the steps that were taken back, to be written again if
+    // the steps are redone.
+    undone: Vec<String>,
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+impl GameRecord {
+    /// Starts the record of a battle, just loaded from the scenario with the
+    /// given seed.
+    fn new(
+        scenario: &ScenarioManager,
+        bm: &BattleManager,
+        seed: u64,
+    ) -> GameRecord {
+        let mut record = GameRecord {
+            lines: vec![
+                format!("scenario {}", scenario.name),
+                format!("seed {seed}"),
+            ],
+            dice: Dice::new(seed),
+            undone: Vec::new(),
+        };
+        record.write(phase_line(scenario, bm), bm);
+        record
+    }
+
+    /// Writes the line, followed by the dice rolled since the last line.
+    fn write(&mut self, mut line: String, bm: &BattleManager) {
+        let rolls = bm.dice.rolls_since(&self.dice);
+        if !rolls.is_empty() {
+            line.push_str(&format!(" : {}", words(&rolls)));
+        }
+        self.lines.push(line);
+        self.undone.clear();
+        self.dice = bm.dice.clone();
+    }
+
+    /// Carries out the order, and records it if it was carried out.
+    fn order(
+        &mut self,
+        bm: &mut BattleManager,
+        order: &Order,
+    ) -> Result<(), RuleBreak> {
+        let notation = order.notation(bm);
+        bm.order(order)?;
+        self.write(notation, bm);
+        Ok(())
+    }
+
+    /// Ends the current phase, and records the phase that follows, or the
+    /// end of the battle.
+    fn end_phase(
+        &mut self,
+        scenario: &mut ScenarioManager,
+        bm: &mut BattleManager,
+    ) -> Option<GameOver> {
+        bm.end_phase();
+        let game_over = scenario.update_scenario_turn(bm);
+        let line = match &game_over {
+            Some(game_over) => game_over_line(game_over),
+            None => phase_line(scenario, bm),
+        };
+        self.write(line, bm);
+        game_over
+    }
+
+    /// Takes back the last order, or end of phase, and its line.
+    fn undo(
+        &mut self,
+        scenario: &mut ScenarioManager,
+        bm: &mut BattleManager,
+    ) -> Result<(), UndoError> {
+        self.check_undo(bm)?;
+        bm.undo(Some(scenario))?;
+        self.take_back(1, bm);
+        Ok(())
+    }
+
+    /// Redoes the last step taken back, and writes its line once more.
+    fn redo(
+        &mut self,
+        scenario: &mut ScenarioManager,
+        bm: &mut BattleManager,
+    ) -> Result<(), UndoError> {
+        self.check_undo(bm)?;
+        bm.redo(Some(scenario))?;
+        self.lines.extend(self.undone.pop());
+        self.dice = bm.dice.clone();
+        Ok(())
+    }
+
+    /// Rewinds the battle to the start of an earlier phase or game turn,
+    /// taking back the lines of every step since.
+    fn rewind(
+        &mut self,
+        to: Rewind,
+        scenario: &mut ScenarioManager,
+        bm: &mut BattleManager,
+    ) -> Result<(), UndoError> {
+        self.check_undo(bm)?;
+        let steps = |bm: &BattleManager| {
+            bm.history.as_ref().map_or(0, |history| history.undo.len())
+        };
+        let before = steps(bm);
+        bm.rewind(to, Some(scenario))?;
+        self.take_back(before - steps(bm), bm);
+        Ok(())
+    }
+
+    /// Checks that the steps of the battle can be taken back without the
+    /// record losing track of the dice. Orders given again must roll the
+    /// very dice they rolled before, for the record to replay.
+    fn check_undo(&self, bm: &BattleManager) -> Result<(), UndoError> {
+        match bm.history.as_ref().map(|history| history.dice) {
+            None => Err(UndoError::Forbidden),
+            Some(DicePolicy::Reroll) => Err(UndoError::Recorded),
+            Some(DicePolicy::Keep) => Ok(()),
+        }
+    }
+
+    fn take_back(&mut self, steps: usize, bm: &BattleManager) {
+        for _ in 0..steps {
+            if self.lines.len() > RECORD_HEADER {
+                self.undone.extend(self.lines.pop());
+            }
+        }
+        self.dice = bm.dice.clone();
+    }
+}
+
+fn phase_line(scenario: &ScenarioManager, bm: &BattleManager) -> String {
+    format!("{} {:?} {}", scenario.turn, bm.attacker, bm.phase.code())
+}
+
+fn game_over_line(game_over: &GameOver) -> String {
+    let winner = match game_over.winner {
+        Some(side) => format!("{side:?}"),
+        None => "draw".to_string(),
+    };
+    format!("game-over {} {:?} {winner}", game_over.turn, game_over.reason)
+}
+
+/// Why a game record could not be replayed, along with the line of the
+/// record where it happened.
+#[derive(Debug, Clone, PartialEq)]
+enum ReplayError {
+    // The scenario of the record could not be loaded.
+    Scenario(ScenarioError),
+    // The line could not be read.
+    Syntax(usize, String),
+    // The order on the line breaks the rules.
+    Order(usize, RuleBreak),
+    // The battle played out differently than recorded.
+    Mismatch(usize, String),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Scenario(error) => write!(f, "scenario: {error}"),
+            ReplayError::Syntax(line, reason) => {
+                write!(f, "line {line}: {reason}")
+            }
+            ReplayError::Order(line, rule_break) => {
+                write!(f, "line {line}: {rule_break}")
+            }
+            ReplayError::Mismatch(line, reason) => {
+                write!(f, "line {line}: {reason}")
+            }
+        }
+    }
+}
+
+impl From<ScenarioError> for ReplayError {
+    fn from(error: ScenarioError) -> ReplayError {
+        match error {
+            ScenarioError::Syntax(line, reason) => {
+                ReplayError::Syntax(line, reason)
+            }
+            error => ReplayError::Scenario(error),
+        }
+    }
+}
+
+impl Entry<'_> {
+    /// Reads the dice rolled at the end of a line of a game record.
+    fn dice(&mut self) -> Result<Vec<DiceRoll>, ScenarioError> {
+        let rolls = match self.words.clone().next() {
+            Some(":") => {
+                self.words.next();
+                self.all("a dice roll")?
+            }
+            _ => Vec::new(),
+        };
+        self.end()?;
+        Ok(rolls)
+    }
+
+    fn syntax(&self, reason: &str) -> ScenarioError {
+        ScenarioError::Syntax(self.line, reason.to_string())
+    }
+
+    /// Reads the `smoke` ending an order firing smoke rounds.
+    fn ammunition(&mut self) -> Ammunition {
+        match self.words.clone().next() {
+            Some("smoke") => {
+                self.words.next();
+                Ammunition::Smoke
+            }
+            _ => Ammunition::HighExplosive,
+        }
+    }
+
+    /// Reads the rest of an order given in the phase, after the phase code.
+    /// Returns the hex of the units given the order, or None if they are
+    /// off board, along with the order.
+    fn order(
+        &mut self,
+        phase: Phase,
+    ) -> Result<(Option<HexId>, Order), ScenarioError> {
+        let mut units: Vec<Ipv4Addr> = Vec::new();
+        let mut weapons: Vec<u32> = Vec::new();
+        let at = loop {
+            let word = self.words.next().ok_or(self.syntax("missing `@`"))?;
+            if let Some(at) = word.strip_prefix('@') {
+                break at;
+            }
+            match word.strip_prefix('+') {
+                Some(weapon) => weapons.push(
+                    weapon.parse().map_err(|_| self.syntax("bad weapon"))?,
+                ),
+                None => units
+                    .push(word.parse().map_err(|_| self.syntax("bad unit"))?),
+            }
+        };
+        let origin = match at {
+            "off" => None,
+            hex => Some(hex.parse().map_err(|_| self.syntax("bad hex"))?),
+        };
+        let ars = *units.first().ok_or(self.syntax("missing unit"))?;
+        let fire = matches!(
+            phase,
+            Phase::PrepFire | Phase::DefensiveFire | Phase::AdvancingFire
+        );
+        let action: String = self.next("an order")?;
+        let order = match action.as_str() {
+            "->" if phase == Phase::Movement => {
+                Order::Move { units, path: self.all("a hex")? }
+            }
+            "->" if phase == Phase::Rout => {
+                Order::Rout { ars, path: self.all("a hex")? }
+            }
+            "->" if phase == Phase::Advance => {
+                Order::Advance { units, to: self.next("a hex")? }
+            }
+            "->" if fire => {
+                let target: String = self.next("a target")?;
+                if let Ok(target) = target.parse() {
+                    let weapon = weapons.first();
+                    let weapon =
+                        *weapon.ok_or(self.syntax("missing weapon"))?;
+                    Order::FireAtVehicle { ars, weapon, target }
+                } else {
+                    let target = target
+                        .parse()
+                        .map_err(|_| self.syntax("bad target"))?;
+                    let mut group = FireGroup::new(&units);
+                    group.weapons = weapons;
+                    if self.words.clone().next() == Some("spotter") {
+                        self.words.next();
+                        group.spotter = Some(self.next("a spotter")?);
+                    }
+                    group.ammunition = self.ammunition();
+                    Order::Fire { group, target }
+                }
+            }
+            "carry" => Order::Carry { ars, weapon: self.next("a weapon")? },
+            "abandon" => {
+                Order::Abandon { ars, weapon: self.next("a weapon")? }
+            }
+            "transfer" => Order::Transfer {
+                weapon: self.next("a weapon")?,
+                from: ars,
+                to: self.next("a unit")?,
+            },
+            "embark" => {
+                Order::Embark { ars, vehicle: self.next("a vehicle")? }
+            }
+            "disembark" => Order::Disembark(ars),
+            "rally" => Order::Rally(ars),
+            "turret" => Order::RotateTurret {
+                ars,
+                facing: Facing(self.next("a facing")?),
+            },
+            "charge" => Order::PlaceCharge {
+                ars,
+                weapon: self.next("a weapon")?,
+                hex: self.next("a hex")?,
+            },
+            "smoke" => Order::ThrowSmoke { ars, hex: self.next("a hex")? },
+            "exit" => Order::ExitMap { units, edge: self.next("an edge")? },
+            "enter" => Order::EnterMap { units, hex: self.next("a hex")? },
+            "mission" => Order::FireMission {
+                module: self.next("a module")?,
+                target: self.next("a hex")?,
+                ammunition: self.ammunition(),
+            },
+            _ => {
+                let reason = format!("unknown order `{action}` in {phase:?}");
+                return Err(ScenarioError::Syntax(self.line, reason));
+            }
+        };
+        Ok((origin, order))
+    }
+}
+
+/// Replays the game record of a battle fought in the scenario, checking that
+/// every phase, order and dice roll plays out just as recorded. Returns the
+/// battle as it stands at the end of the record.
+fn replay(
+    scenario: &str,
+    record: &str,
+) -> Result<(ScenarioManager, BattleManager), ReplayError> {
+    let mut lines = entries(record);
+    let mut header = |keyword| match lines.next() {
+        Some((word, entry)) if word == keyword => Ok(entry),
+        Some((_, entry)) => {
+            Err(ReplayError::Syntax(entry.line, format!("expected {keyword}")))
+        }
+        None => Err(ReplayError::Syntax(0, format!("missing {keyword}"))),
+    };
+    let mut entry = header("scenario")?;
+    let (name, name_line) = (entry.rest(), entry.line);
+    let mut entry = header("seed")?;
+    let seed = entry.next("a seed")?;
+    entry.end()?;
+    let (mut sm, mut bm) = ScenarioManager::load(scenario, seed)
+        .map_err(ReplayError::Scenario)?;
+    if sm.name != name {
+        let reason = format!("the record is of `{name}`");
+        return Err(ReplayError::Mismatch(name_line, reason));
+    }
+    let mut dice = Dice::new(seed);
+    let mut started = false;
+    for (keyword, mut entry) in lines {
+        let line = entry.line;
+        let mismatch =
+            |reason: String| Err(ReplayError::Mismatch(line, reason));
+        if let Ok(turn) = keyword.parse::<u8>() {
+            let side: Side = entry.next("a side")?;
+            let phase: Phase = entry.next("a phase")?;
+            if started {
+                bm.end_phase();
+                if let Some(game_over) = sm.update_scenario_turn(&mut bm) {
+                    let reached = game_over_line(&game_over);
+                    return mismatch(format!("reached {reached}"));
+                }
+            }
+            started = true;
+            if (sm.turn, bm.attacker, bm.phase) != (turn, side, phase) {
+                return mismatch(format!("reached {}", phase_line(&sm, &bm)));
+            }
+        } else if keyword == "game-over" {
+            let turn: u8 = entry.next("a game turn")?;
+            let reason: String = entry.next("a reason")?;
+            let winner: String = entry.next("a winner")?;
+            let recorded = format!("game-over {turn} {reason} {winner}");
+            bm.end_phase();
+            match sm.update_scenario_turn(&mut bm) {
+                Some(game_over) if game_over_line(&game_over) == recorded => {}
+                Some(game_over) => {
+                    return mismatch(format!(
+                        "reached {}",
+                        game_over_line(&game_over)
+                    ))
+                }
+                None => {
+                    return mismatch(format!(
+                        "reached {}",
+                        phase_line(&sm, &bm)
+                    ))
+                }
+            }
+        } else {
+            let phase: Phase = keyword.parse().map_err(|_| {
+                entry.syntax(&format!("unknown entry `{keyword}`"))
+            })?;
+            let (origin, order) = entry.order(phase)?;
+            if phase != bm.phase {
+                return mismatch(format!("the battle is in {:?}", bm.phase));
+            }
+            let (units, _, _) = order.parts(&bm);
+            let located = units.first().and_then(|ars| bm.locations.get(ars));
+            if located != origin.as_ref() {
+                let at =
+                    origin.map_or("off".to_string(), |hex| hex.to_string());
+                return mismatch(format!("the units are not @{at}"));
+            }
+            bm.order(&order).map_err(|e| ReplayError::Order(line, e))?;
+        }
+        let recorded = entry.dice()?;
+        let rolled = bm.dice.rolls_since(&dice);
+        if rolled != recorded {
+            return mismatch(format!("rolled {}", words(&rolled)));
+        }
+        dice = bm.dice.clone();
+    }
+    Ok((sm, bm))
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Undo
+//
+// During playtests, orders given by mistake may be taken back, and the battle
+// rewound to the start of any earlier phase or game turn. Undo is turned off
+// unless the players allow it, and competitive play should keep it that way.
+//
+// The battle is taken back with snapshots: a copy of the battle is kept from
+// just before every order given through `BattleManager::order`, and before
+// every end of phase. When the battle is fought in a scenario, the
+// ScenarioManager is kept along with the snapshots taken before the end of a
+// phase, since that is when the turn record chart moves on. The event log
+// only ever grows between snapshots, so a snapshot keeps the length of the
+// log rather than a copy of it.
+//
+// A battle that is being written down in a GameRecord must be taken back
+// through the record, which takes back its lines along with the steps.
+//
+// The players decide up front what becomes of the dice rolled in the
+// orders that are taken back. Either the dice are taken back along with the
+// orders, so that giving the same orders again rolls the very same dice, or
+// the dice keep rolling on from where they were, and every order given again
+// is rolled for anew.
+
+/// What becomes of the dice rolled by orders that are taken back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DicePolicy {
+    // The dice are taken back along with the orders.
+    Keep,
+    // The dice roll on, and orders given again are rolled for anew.
+    Reroll,
+}
+
+/// The kind of step in the battle that a snapshot was taken before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Order,
+    EndPhase,
+}
+
+#[derive(Debug, Clone)]
+struct Snapshot {
+    step: Step,
+    // The battle just before the step, without its history or events.
+    battle: Box<BattleManager>,
+    // The scenario just before the step, if the step ended a phase of a
+    // battle fought in a scenario.
+    scenario: Option<Box<ScenarioManager>>,
+    // The length of the event log just before the step, and the events
+    // logged by the step, if it was taken back and may be redone.
+    logged: usize,
+    events: Vec<Event>,
+}
+
+#[derive(Debug, Clone)]
+struct History {
+    dice: DicePolicy,
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+}
+
+/// Why the battle could not be taken back, or taken forward again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UndoError {
+    // Undo is turned off.
+    Forbidden,
+    // There is nothing to undo, or redo.
+    Empty,
+    // The phase, or game turn, is not in the history of the battle.
+    Unreached,
+    // The step ended a phase of the scenario, which was not passed along.
+    Scenario,
+    // The dice roll on past orders that are taken back, which a game record
+    // has no way of writing down.
+    Recorded,
+}
+
+impl fmt::Display for UndoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            UndoError::Forbidden => "undo is turned off",
+            UndoError::Empty => "there is nothing to take back",
+            UndoError::Unreached => "the history does not go back that far",
+            UndoError::Scenario => "the scenario must be taken back as well",
+            UndoError::Recorded => {
+                "the dice taken back cannot be written in the record"
+            }
+        };
+        write!(f, "{reason}")
+    }
+}
+
+/// A point in the history of the battle to rewind to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rewind {
+    // The start of the most recent phase of this kind.
+    Phase(Phase),
+    // The start of the game turn. Game turns are only known to a battle
+    // fought in a scenario.
+    GameTurn(u8),
+}
+
+impl BattleManager {
+    /// Allows orders to be taken back, with the dice rolled by them dealt
+    /// with according to the policy.
+    fn allow_undo(&mut self, dice: DicePolicy) {
+        let (undo, redo) = (Vec::new(), Vec::new());
+        self.history = Some(History { dice, undo, redo });
+    }
+
+    /// Turns undo off, forgetting the history of the battle.
+    fn forbid_undo(&mut self) {
+        self.history = None;
+    }
+
+    /// Takes a snapshot of the battle before the step, unless undo is
+    /// turned off.
+    fn snapshot(&mut self, step: Step) -> Option<Snapshot> {
+        let history = self.history.take()?;
+        let events = std::mem::take(&mut self.events);
+        let battle = Box::new(self.clone());
+        let logged = events.len();
+        self.events = events;
+        self.history = Some(history);
+        let (scenario, events) = (None, Vec::new());
+        Some(Snapshot { step, battle, scenario, logged, events })
+    }
+
+    /// Remembers the snapshot taken before a step that has now been taken.
+    /// Taking a new step forgets every step that was undone.
+    fn remember(&mut self, snapshot: Option<Snapshot>) {
+        if let (Some(history), Some(snapshot)) = (&mut self.history, snapshot)
+        {
+            history.undo.push(snapshot);
+            history.redo.clear();
+        }
+    }
+
+    /// Keeps the scenario, as it was before the end of the phase that was
+    /// just remembered.
+    fn remember_scenario(&mut self, scenario: &ScenarioManager) {
+        let history = self.history.as_mut();
+        let last = history.and_then(|history| history.undo.last_mut());
+        if let Some(snapshot) = last {
+            if snapshot.step == Step::EndPhase && snapshot.scenario.is_none() {
+                snapshot.scenario = Some(Box::new(scenario.clone()));
+            }
+        }
+    }
+
+    /// Puts the battle, and the scenario, back the way they were in the
+    /// snapshot. Returns the snapshot of the battle as it was.
+    fn restore(
+        &mut self,
+        snapshot: Snapshot,
+        scenario: Option<&mut ScenarioManager>,
+    ) -> Snapshot {
+        let history = self.history.take();
+        let mut events = std::mem::take(&mut self.events);
+        let taken = events.split_off(snapshot.logged);
+        events.extend(snapshot.events);
+        let battle = std::mem::replace(self, *snapshot.battle);
+        self.events = events;
+        let mut current = Snapshot {
+            step: snapshot.step,
+            battle: Box::new(battle),
+            scenario: None,
+            logged: snapshot.logged,
+            events: taken,
+        };
+        if let (Some(scenario), Some(saved)) = (scenario, snapshot.scenario) {
+            current.scenario = Some(Box::new(scenario.clone()));
+            *scenario = *saved;
+        }
+        self.history = history;
+        current
+    }
+
+    /// Returns the history, after checking that the next snapshot to be
+    /// restored from it can be.
+    fn check_restore(
+        &mut self,
+        redo: bool,
+        scenario: bool,
+    ) -> Result<&mut History, UndoError> {
+        let history = self.history.as_mut().ok_or(UndoError::Forbidden)?;
+        let snapshots = if redo { &history.redo } else { &history.undo };
+        let snapshot = snapshots.last().ok_or(UndoError::Empty)?;
+        if snapshot.scenario.is_some() && !scenario {
+            return Err(UndoError::Scenario);
+        }
+        Ok(history)
+    }
+
+    /// Takes back the last order, or end of phase. Pass the scenario the
+    /// battle is fought in, if any, for it to be taken back as well.
+    fn undo(
+        &mut self,
+        scenario: Option<&mut ScenarioManager>,
+    ) -> Result<(), UndoError> {
+        let history = self.check_restore(false, scenario.is_some())?;
+        let snapshot = history.undo.pop().ok_or(UndoError::Empty)?;
+        let policy = history.dice;
+        let dice = self.dice.clone();
+        let current = self.restore(snapshot, scenario);
+        if policy == DicePolicy::Reroll {
+            self.dice = dice;
+        }
+        if let Some(history) = &mut self.history {
+            history.redo.push(current);
+        }
+        Ok(())
+    }
+
+    /// Takes the last step that was taken back once more, just as it
+    /// played out before.
+    fn redo(
+        &mut self,
+        scenario: Option<&mut ScenarioManager>,
+    ) -> Result<(), UndoError> {
+        let history = self.check_restore(true, scenario.is_some())?;
+        let snapshot = history.redo.pop().ok_or(UndoError::Empty)?;
+        let current = self.restore(snapshot, scenario);
+        if let Some(history) = &mut self.history {
+            history.undo.push(current);
+        }
+        Ok(())
+    }
+
+    /// Rewinds the battle to the start of an earlier phase or game turn,
+    /// by undoing every step taken since. The steps may be redone one by
+    /// one. Rewinding to a game turn takes the scenario that the battle is
+    /// fought in.
+    fn rewind(
+        &mut self,
+        to: Rewind,
+        mut scenario: Option<&mut ScenarioManager>,
+    ) -> Result<(), UndoError> {
+        let history = self.history.as_ref().ok_or(UndoError::Forbidden)?;
+        let undo = &history.undo;
+        // Go back through the battle as it was before each step, along with
+        // the scenario. The scenario does not change within a phase, and is
+        // kept with the snapshot taken before the end of the phase.
+        let mut at = scenario.as_deref();
+        let mut undone = None;
+        for i in (0..=undo.len()).rev() {
+            let battle = match undo.get(i) {
+                Some(snapshot) => {
+                    at = snapshot.scenario.as_deref().or(at);
+                    &*snapshot.battle
+                }
+                None => &*self,
+            };
+            // A phase starts where the previous phase ended.
+            let start = i == 0 || undo[i - 1].step == Step::EndPhase;
+            let reached = match to {
+                Rewind::Phase(phase) => battle.phase == phase,
+                Rewind::GameTurn(turn) => at.is_some_and(|at| {
+                    at.turn == turn
+                        && battle.phase == Phase::Rally
+                        && battle.attacker == at.first
+                }),
+            };
+            if start && reached {
+                undone = Some(undo.len() - i);
+                break;
+            }
+        }
+        let undone = undone.ok_or(UndoError::Unreached)?;
+        let mut passed = undo[undo.len() - undone..].iter();
+        if scenario.is_none() && passed.any(|s| s.scenario.is_some()) {
+            return Err(UndoError::Scenario);
+        }
+        for _ in 0..undone {
+            self.undo(scenario.as_deref_mut())?;
+        }
+        Ok(())
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Legal orders
+//
+// Much like a chess engine generates every legal move in a position, the
+// BattleManager can list the orders that may be given at any point of the
+// battle, for the units of the player, or players, acting in the current
+// phase. Every order listed has been tried out on a copy of the battle, and
+// passes every rule check, see `BattleManager::order`. The copies leave out
+// the event log and the history of the battle, which would otherwise be
+// copied once for every order tried.
+//
+// Some orders come in too many variations to list in full. A move is listed
+// once for every hex a unit or stack can reach, along the path costing the
+// fewest MF. Fire groups are formed by a single unit, with or without each
+// of its support weapons, or by every unit in a hex, firing every weapon
+// they carry. Fire with a spotter, smoke rounds and fire groups spanning
+// several hexes are left out, as are close combats, which the battle has no
+// orders for yet.
+
+/// Lists every legal order in the current phase of the battle.
+fn legal_actions(bm: &BattleManager) -> Vec<Order> {
+    let trial = bm.trial();
+    let mut candidates = Vec::new();
+    match bm.phase {
+        Phase::PrepFire | Phase::DefensiveFire | Phase::AdvancingFire => {
+            bm.fire_candidates(&mut candidates);
+        }
+        Phase::Movement => {
+            bm.move_candidates(&trial, &mut candidates);
+            bm.movement_candidates(&mut candidates);
+        }
+        Phase::Rout => bm.rout_candidates(&trial, &mut candidates),
+        Phase::Advance => bm.advance_candidates(&mut candidates),
+        Phase::Rally => {
+            let broken = bm.locations.keys().filter(|ars| bm.broken(ars));
+            candidates.extend(broken.map(|ars| Order::Rally(*ars)));
+        }
+        // There are no orders for close combat yet.
+        Phase::CloseCombat => {}
+    }
+    bm.weapon_candidates(&mut candidates);
+    candidates
+        .into_iter()
+        .filter(|order| {
+            let (units, _, _) = order.parts(bm);
+            units
+                .iter()
+                .all(|ars| bm.sides.get(ars).is_some_and(|s| bm.acting(*s)))
+        })
+        .filter(|order| trial.clone().carry_out(order).is_ok())
+        .collect()
+}
+
+impl BattleManager {
+    /// Returns a copy of the battle to try orders out on. The copy leaves
+    /// out the event log and the history, which orders that are only tried
+    /// out have no use for.
+    fn trial(&self) -> BattleManager {
+        BattleManager {
+            map: self.map.clone(),
+            dice: self.dice.clone(),
+            phase: self.phase,
+            attacker: self.attacker,
+            squads: self.squads.clone(),
+            leaders: self.leaders.clone(),
+            vehicles: self.vehicles.clone(),
+            structures: self.structures.clone(),
+            minefields: self.minefields.clone(),
+            artillery: self.artillery.clone(),
+            exited: self.exited.clone(),
+            control: self.control.clone(),
+            buildings: self.buildings.clone(),
+            offboard: self.offboard.clone(),
+            sides: self.sides.clone(),
+            locations: self.locations.clone(),
+            conditions: self.conditions.clone(),
+            markers: self.markers.clone(),
+            weapons: self.weapons.clone(),
+            carriers: self.carriers.clone(),
+            abandoned: self.abandoned.clone(),
+            owners: self.owners.clone(),
+            mounted: self.mounted.clone(),
+            malfunctioned: self.malfunctioned.clone(),
+            charges: self.charges.clone(),
+            weapon_types_fired: self.weapon_types_fired.clone(),
+            mf_spent: self.mf_spent.clone(),
+            moved_portage: self.moved_portage.clone(),
+            escorts: self.escorts.clone(),
+            facings: self.facings.clone(),
+            turrets: self.turrets.clone(),
+            passengers: self.passengers.clone(),
+            stacking_limit: self.stacking_limit,
+            events: Vec::new(),
+            history: None,
+        }
+    }
+
+    /// Returns the units on the map, grouped by the hex they are in.
+    fn stacks(&self) -> BTreeMap<HexId, Vec<Ipv4Addr>> {
+        let mut stacks: BTreeMap<HexId, Vec<Ipv4Addr>> = BTreeMap::new();
+        for (ars, hex) in &self.locations {
+            stacks.entry(*hex).or_default().push(*ars);
+        }
+        stacks
+    }
+
+    /// Returns the weapons carried by the unit.
+    fn carried(&self, ars: &Ipv4Addr) -> Vec<u32> {
+        let carried = self.carriers.iter().filter(|(_, c)| *c == ars);
+        carried.map(|(weapon, _)| *weapon).collect()
+    }
+
+    fn fire_candidates(&self, candidates: &mut Vec<Order>) {
+        let targets: Vec<HexId> = self.stacks().into_keys().collect();
+        for (hex, units) in self.stacks() {
+            let mut groups = Vec::new();
+            for ars in &units {
+                let weapons = self.carried(ars);
+                groups.push(FireGroup::new(&[*ars]));
+                for weapon in &weapons {
+                    groups.push(FireGroup::new(&[*ars]).with_weapon(*weapon));
+                }
+                if weapons.len() > 1 {
+                    let mut group = FireGroup::new(&[*ars]);
+                    group.weapons = weapons.clone();
+                    groups.push(group);
+                }
+                for weapon in weapons {
+                    if self.weapons[&weapon].armor_penetration == 0 {
+                        continue;
+                    }
+                    for target in self.vehicles.keys() {
+                        candidates.push(Order::FireAtVehicle {
+                            ars: *ars,
+                            weapon,
+                            target: *target,
+                        });
+                    }
+                }
+            }
+            if units.len() > 1 {
+                let mut group = FireGroup::new(&units);
+                group.weapons =
+                    units.iter().flat_map(|u| self.carried(u)).collect();
+                groups.push(group);
+            }
+            for group in groups {
+                for target in &targets {
+                    let group = group.clone();
+                    candidates.push(Order::Fire { group, target: *target });
+                }
+            }
+        }
+        for (module, artillery) in &self.artillery {
+            let mut hexes = targets.clone();
+            hexes.extend(artillery.pre_registered.iter().copied());
+            for target in hexes {
+                candidates.push(Order::FireMission {
+                    module: *module,
+                    target,
+                    ammunition: Ammunition::HighExplosive,
+                });
+            }
+        }
+    }
+
+    /// Lists a move to every hex that each unit, and each stack, can reach.
+    fn move_candidates(
+        &self,
+        trial: &BattleManager,
+        candidates: &mut Vec<Order>,
+    ) {
+        for (hex, units) in self.stacks() {
+            let movers: Vec<Ipv4Addr> = units
+                .into_iter()
+                .filter(|ars| self.unit_can_move(ars).is_ok())
+                .filter(|ars| !self.passengers.contains_key(ars))
+                .collect();
+            let mut stacks: Vec<Vec<Ipv4Addr>> =
+                movers.iter().map(|ars| vec![*ars]).collect();
+            let on_foot: Vec<Ipv4Addr> = movers
+                .iter()
+                .filter(|ars| !self.vehicles.contains_key(ars))
+                .copied()
+                .collect();
+            if on_foot.len() > 1 {
+                stacks.push(on_foot);
+            }
+            for units in stacks {
+                let mut order = |path: &[HexId]| Order::Move {
+                    units: units.clone(),
+                    path: path.to_vec(),
+                };
+                for path in trial.reachable(hex, &mut order) {
+                    candidates.push(order(&path));
+                }
+            }
+        }
+    }
+
+    /// Lists a rout to every hex that each broken unit can reach.
+    fn rout_candidates(
+        &self,
+        trial: &BattleManager,
+        candidates: &mut Vec<Order>,
+    ) {
+        for (ars, hex) in &self.locations {
+            if !self.broken(ars) {
+                continue;
+            }
+            let mut order = |path: &[HexId]| Order::Rout {
+                ars: *ars,
+                path: path.to_vec(),
+            };
+            for path in trial.reachable(*hex, &mut order) {
+                candidates.push(order(&path));
+            }
+        }
+    }
+
+    /// Searches out the cheapest path to every hex that the order can be
+    /// carried out along, trying the order out along each path. Paths are
+    /// compared by the MF that the units have left at the end of them. The
+    /// battle is a trial, see `BattleManager::trial`.
+    fn reachable(
+        &self,
+        start: HexId,
+        order: &mut impl FnMut(&[HexId]) -> Order,
+    ) -> Vec<Vec<HexId>> {
+        let mf_left = |bm: &BattleManager, order: &Order| -> u16 {
+            let units = match order {
+                Order::Move { units, .. } => units.clone(),
+                Order::Rout { ars, .. } => vec![*ars],
+                _ => Vec::new(),
+            };
+            units
+                .iter()
+                .map(|ars| {
+                    let spent = bm.mf_spent.get(ars).copied().unwrap_or(0);
+                    u16::from(bm.mf_allowance(ars).saturating_sub(spent))
+                })
+                .sum()
+        };
+        let mut best: BTreeMap<HexId, (u16, Vec<HexId>)> = BTreeMap::new();
+        let mut frontier = vec![Vec::new()];
+        while let Some(path) = frontier.pop() {
+            let from = path.last().copied().unwrap_or(start);
+            for next in self.map.neighbours(&from) {
+                if next == start || path.contains(&next) {
+                    continue;
+                }
+                let mut longer = path.clone();
+                longer.push(next);
+                let order = order(&longer);
+                let mut trial = self.clone();
+                if trial.carry_out(&order).is_err() {
+                    continue;
+                }
+                let left = mf_left(&trial, &order);
+                if best.get(&next).map_or(true, |(most, _)| left > *most) {
+                    best.insert(next, (left, longer.clone()));
+                    frontier.push(longer);
+                }
+            }
+        }
+        best.into_values().map(|(_, path)| path).collect()
+    }
+
+    /// Lists the orders of Phase::Movement, other than moves.
+    fn movement_candidates(&self, candidates: &mut Vec<Order>) {
+        let edges = [Edge::North, Edge::East, Edge::South, Edge::West];
+        for (hex, units) in self.stacks() {
+            for ars in &units {
+                for edge in edges {
+                    if self.map.on_edge(&hex, edge) {
+                        let units = vec![*ars];
+                        candidates.push(Order::ExitMap { units, edge });
+                    }
+                }
+                for vehicle in
+                    units.iter().filter(|v| self.vehicles.contains_key(v))
+                {
+                    candidates
+                        .push(Order::Embark { ars: *ars, vehicle: *vehicle });
+                }
+                if self.passengers.contains_key(ars) {
+                    candidates.push(Order::Disembark(*ars));
+                }
+                let mut hexes = self.map.neighbours(&hex);
+                hexes.push(hex);
+                for hex in hexes {
+                    candidates.push(Order::ThrowSmoke { ars: *ars, hex });
+                }
+            }
+        }
+        for (ars, hexes) in &self.offboard {
+            for hex in hexes {
+                let units = vec![*ars];
+                candidates.push(Order::EnterMap { units, hex: *hex });
+            }
+        }
+    }
+
+    /// Lists an advance into every hex adjacent to each unit.
+    fn advance_candidates(&self, candidates: &mut Vec<Order>) {
+        for (hex, units) in self.stacks() {
+            for to in self.map.neighbours(&hex) {
+                for ars in &units {
+                    let units = vec![*ars];
+                    candidates.push(Order::Advance { units, to });
+                }
+                if units.len() > 1 {
+                    let units = units.clone();
+                    candidates.push(Order::Advance { units, to });
+                }
+            }
+        }
+    }
+
+    /// Lists the orders handling support weapons and turrets, which may be
+    /// given in more than one phase.
+    fn weapon_candidates(&self, candidates: &mut Vec<Order>) {
+        for (weapon, hex) in &self.abandoned {
+            for ars in self.units_in(hex) {
+                candidates.push(Order::Carry { ars, weapon: *weapon });
+            }
+        }
+        for (weapon, ars) in &self.carriers {
+            let weapon = *weapon;
+            candidates.push(Order::Abandon { ars: *ars, weapon });
+            let Some(hex) = self.locations.get(ars) else {
+                continue;
+            };
+            for to in self.units_in(hex) {
+                if to != *ars {
+                    candidates.push(Order::Transfer {
+                        weapon,
+                        from: *ars,
+                        to,
+                    });
+                }
+            }
+            for hex in self.map.neighbours(hex) {
+                candidates.push(Order::PlaceCharge { ars: *ars, weapon, hex });
+            }
+        }
+        for (ars, turret) in &self.turrets {
+            for hexside in 0..6 {
+                if hexside != turret.0 {
+                    let facing = Facing(hexside);
+                    candidates.push(Order::RotateTurret { ars: *ars, facing });
+                }
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Commanders
+//
+// A side may be led by a commander instead of a player, so that a battle can
+// be played solo against the engine, or played out by commanders alone. A
+// commander gives its orders through `BattleManager::order`, just like a
+// player does, and only ever gives orders that are legal, see
+// `legal_actions`.
+//
+// The heuristic commander goes by a few rules of thumb. It fires whenever
+// the odds are good, though only at the very best odds during
+// Phase::PrepFire, since units that prep fire may not move. It moves its
+// units toward the objectives of its victory conditions, preferring paths
+// out of enemy LOS, and hexes with cover. Broken units are rallied, and rout
+// to cover away from the enemy. Units advance into hexes held by a weaker
+// enemy, or closer to their objective.
+//
+// A unit may be given a standing order, which the commander carries out over
+// as many player turns as it takes. A unit ordered to move to a hex keeps
+// moving toward it every Phase::Movement, until it is there, and a unit
+// ordered to hold never leaves its hex.
+
+/// A commander leading one side of the battle.
+trait Commander {
+    /// The side the commander leads.
+    fn side(&self) -> Side;
+
+    /// Gives the orders of the side for the current phase of the battle.
+    fn command(&mut self, bm: &mut BattleManager);
+}
+
+/// An order that a unit follows over several player turns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StandingOrder {
+    MoveTo(HexId),
+    Hold,
+}
+
+// The odds an attack must have, in expected units broken or eliminated, for
+// the heuristic commander to fire it. Prep fire is reserved for the best
+// odds, as the units that prep fire may not move.
+const PREP_FIRE_ODDS: f64 = 0.5;
+const FIRE_ODDS: f64 = 0.1;
+
+/// A commander going by rules of thumb.
+#[derive(Debug, Clone)]
+struct HeuristicCommander {
+    side: Side,
+    // The hexes the commander tries to take.
+    objectives: Vec<HexId>,
+    // The edges the commander tries to exit units by.
+    exits: Vec<Edge>,
+    standing_orders: BTreeMap<Ipv4Addr, StandingOrder>,
+}
+
+impl VictoryCondition {
+    /// Returns the hexes that must be controlled to meet the condition.
+    fn objectives(&self, bm: &BattleManager) -> Vec<HexId> {
+        match self {
+            VictoryCondition::Control(hexes) => hexes.clone(),
+            VictoryCondition::ControlBuildings(buildings) => buildings
+                .iter()
+                .filter_map(|id| bm.buildings.get(id))
+                .flatten()
+                .copied()
+                .collect(),
+            VictoryCondition::All(conditions) => {
+                conditions.iter().flat_map(|c| c.objectives(bm)).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the edges that units must exit by to meet the condition.
+    fn exits(&self) -> Vec<Edge> {
+        match self {
+            VictoryCondition::Exit(edge, _) => vec![*edge],
+            VictoryCondition::All(conditions) => {
+                conditions.iter().flat_map(|c| c.exits()).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl HeuristicCommander {
+    fn new(side: Side) -> HeuristicCommander {
+        HeuristicCommander {
+            side,
+            objectives: Vec::new(),
+            exits: Vec::new(),
+            standing_orders: BTreeMap::new(),
+        }
+    }
+
+    /// Creates a commander going after the victory conditions of its side in
+    /// the scenario.
+    fn for_scenario(
+        side: Side,
+        scenario: &ScenarioManager,
+        bm: &BattleManager,
+    ) -> HeuristicCommander {
+        let mut commander = HeuristicCommander::new(side);
+        for victory in scenario.victory.iter().filter(|v| v.side == side) {
+            commander.objectives.extend(victory.condition.objectives(bm));
+            commander.exits.extend(victory.condition.exits());
+        }
+        commander
+    }
+
+    fn with_objective(mut self, hex: HexId) -> HeuristicCommander {
+        self.objectives.push(hex);
+        self
+    }
+
+    /// Gives the unit a standing order, replacing any earlier one.
+    fn standing_order(&mut self, ars: Ipv4Addr, order: StandingOrder) {
+        self.standing_orders.insert(ars, order);
+    }
+
+    /// Returns the hex the unit is heading for from the hex, if any. Units
+    /// without a standing order head for the nearest objective that the
+    /// side does not control yet, or else the nearest enemy.
+    fn destination(
+        &self,
+        bm: &BattleManager,
+        ars: &Ipv4Addr,
+        from: &HexId,
+    ) -> Option<HexId> {
+        match self.standing_orders.get(ars) {
+            Some(StandingOrder::MoveTo(hex)) => return Some(*hex),
+            Some(StandingOrder::Hold) => return None,
+            None => {}
+        }
+        let distance = |hex: &&HexId| bm.map.distance(from, hex);
+        let objectives = self
+            .objectives
+            .iter()
+            .filter(|hex| bm.controller(hex) != Some(self.side));
+        let enemies = bm
+            .locations
+            .iter()
+            .filter(|(enemy, _)| bm.sides[*enemy] != self.side)
+            .map(|(_, hex)| hex);
+        match objectives.min_by_key(distance) {
+            Some(objective) => Some(*objective),
+            None if self.objectives.is_empty() => {
+                enemies.min_by_key(distance).copied()
+            }
+            None => None,
+        }
+    }
+
+    /// Returns how many hexes closer the move brings the unit to where it is
+    /// heading.
+    fn progress(
+        &self,
+        bm: &BattleManager,
+        ars: &Ipv4Addr,
+        from: &HexId,
+        to: &HexId,
+    ) -> Option<f64> {
+        let destination = self.destination(bm, ars, from)?;
+        let before = bm.map.distance(from, &destination)?;
+        let after = bm.map.distance(to, &destination)?;
+        Some(f64::from(before) - f64::from(after))
+    }
+
+    /// Scores the order, or returns None if the commander would rather not
+    /// give it. Orders are given from the highest score down.
+    fn score(&self, bm: &BattleManager, order: &Order) -> Option<f64> {
+        let holds = |ars: &Ipv4Addr| {
+            self.standing_orders.get(ars) == Some(&StandingOrder::Hold)
+        };
+        let cover = |hex: &HexId| {
+            bm.map
+                .hex(hex)
+                .map_or(0.0, |h| f64::from(terrain_effect_combat(h)))
+        };
+        match order {
+            Order::Fire { group, target } => {
+                let odds = bm.fire_odds(group, target);
+                let least = match bm.phase {
+                    Phase::PrepFire => PREP_FIRE_ODDS,
+                    _ => FIRE_ODDS,
+                };
+                (odds >= least).then_some(odds)
+            }
+            Order::FireAtVehicle { .. } => Some(PREP_FIRE_ODDS),
+            Order::Rally(_) => Some(f64::MAX),
+            Order::Move { units, path } => {
+                let from = bm.locations.get(units.first()?)?;
+                let to = path.last()?;
+                let mut score = 0.0;
+                for ars in units {
+                    if holds(ars) {
+                        return None;
+                    }
+                    let progress = self.progress(bm, ars, from, to)?;
+                    if progress <= 0.0 {
+                        return None;
+                    }
+                    score += progress * 10.0;
+                }
+                // Steer clear of the enemy's LOS while on the move.
+                let exposure = path[..path.len() - 1]
+                    .iter()
+                    .filter(|hex| bm.exposed(self.side, hex))
+                    .count();
+                Some(score - exposure as f64 * 3.0 + cover(to) * 2.0)
+            }
+            Order::EnterMap { units, hex } => {
+                let ars = units.first()?;
+                let destination = self.destination(bm, ars, hex)?;
+                let distance = bm.map.distance(hex, &destination)?;
+                Some(100.0 - f64::from(distance))
+            }
+            Order::ExitMap { edge, .. } => {
+                self.exits.contains(edge).then_some(100.0)
+            }
+            Order::Rout { path, .. } => {
+                // Rout to cover, as far from the enemy as possible.
+                let to = path.last()?;
+                let enemies = bm
+                    .locations
+                    .iter()
+                    .filter(|(enemy, _)| bm.sides[*enemy] != self.side);
+                let nearest = enemies
+                    .filter_map(|(_, hex)| bm.map.distance(to, hex))
+                    .min()
+                    .unwrap_or(0);
+                Some(cover(to) * 2.0 + f64::from(nearest))
+            }
+            Order::Advance { units, to } => {
+                if units.iter().any(holds) {
+                    return None;
+                }
+                let enemies: Vec<Ipv4Addr> = bm
+                    .units_in(to)
+                    .into_iter()
+                    .filter(|ars| bm.sides[ars] != self.side)
+                    .collect();
+                let broken = enemies.iter().filter(|e| bm.broken(e)).count();
+                let unbroken = enemies.len() - broken;
+                let from = bm.locations.get(units.first()?)?;
+                if !enemies.is_empty() {
+                    // Only advance into hexes held by a weaker enemy.
+                    let weak = unbroken < units.len();
+                    return weak.then_some(10.0 * (broken + 1) as f64);
+                }
+                let progress = self.progress(bm, units.first()?, from, to)?;
+                let score = progress * 5.0 + cover(to) - cover(from);
+                (score > 0.0).then_some(score)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Commander for HeuristicCommander {
+    fn side(&self) -> Side {
+        self.side
+    }
+
+    fn command(&mut self, bm: &mut BattleManager) {
+        if !bm.acting(self.side) {
+            return;
+        }
+        // Units that have reached their hex are done moving.
+        self.standing_orders.retain(|ars, order| match order {
+            StandingOrder::MoveTo(hex) => bm.locations.get(ars) != Some(hex),
+            StandingOrder::Hold => true,
+        });
+        let mut scored: Vec<(f64, Order)> = legal_actions(bm)
+            .into_iter()
+            .filter(|order| {
+                let (units, _, _) = order.parts(bm);
+                units.iter().all(|ars| bm.sides.get(ars) == Some(&self.side))
+            })
+            .filter_map(|order| Some((self.score(bm, &order)?, order)))
+            .collect();
+        scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        // Every unit is given a single order each phase.
+        let mut ordered = BTreeSet::new();
+        for (_, order) in scored {
+            let (units, _, _) = order.parts(bm);
+            if units.iter().any(|ars| ordered.contains(ars)) {
+                continue;
+            }
+            if bm.order(&order).is_ok() {
+                ordered.extend(units);
+            }
+        }
+    }
+}
+
+impl BattleManager {
+    /// Returns the number of enemy units that the attack can be expected to
+    /// break or eliminate, or zero if the group cannot fire on the target.
+    fn fire_odds(&self, group: &FireGroup, target: &HexId) -> f64 {
+        let Ok((contributions, modifiers)) =
+            self.check_fire_group(group, target)
+        else {
+            return 0.0;
+        };
+        let Some(side) = group.members.first().and_then(|m| self.sides.get(m))
+        else {
+            return 0.0;
+        };
+        let firepower = contributions
+            .iter()
+            .fold(0u8, |sum, c| sum.saturating_add(c.firepower));
+        let drm = fire_drm(&modifiers);
+        let defenders: Vec<Ipv4Addr> = self
+            .units_in(target)
+            .into_iter()
+            .filter(|ars| self.sides[ars] != *side)
+            .collect();
+        let mut odds = 0.0;
+        for roll in dice_totals() {
+            let result = infantry_fire_table(firepower, roll as i8 + drm);
+            for ars in &defenders {
+                odds += match result {
+                    FireResult::Kill => 1.0,
+                    FireResult::MoraleCheck(drm) => {
+                        self.morale_check_odds(ars, drm as i8)
+                    }
+                    FireResult::NoEffect => 0.0,
+                } / 36.0;
+            }
+        }
+        odds
+    }
+
+    /// Returns the odds of the unit failing a morale check.
+    fn morale_check_odds(&self, ars: &Ipv4Addr, modifier: i8) -> f64 {
+        let leadership =
+            self.led_by(ars).map_or(0, |l| self.leaders[&l].leadership);
+        let morale = self.morale(ars) as i8;
+        let failed = dice_totals()
+            .filter(|roll| *roll as i8 + modifier + leadership > morale)
+            .count();
+        failed as f64 / 36.0
+    }
+
+    /// Returns true if any unbroken enemy unit has LOS to the hex.
+    fn exposed(&self, side: Side, hex: &HexId) -> bool {
+        self.locations.iter().any(|(ars, at)| {
+            self.sides[ars] != side
+                && !self.broken(ars)
+                && self.map.los(at, hex)
+        })
+    }
+}
+
+/// Returns the totals of all 36 combinations of two dice.
+fn dice_totals() -> impl Iterator<Item = u8> {
+    (1..=6).flat_map(|white| (1..=6).map(move |coloured| white + coloured))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ars(text: &str) -> Ipv4Addr {
+        text.parse().unwrap()
+    }
+
+    fn hex(text: &str) -> HexId {
+        text.parse().unwrap()
+    }
+
+    /// A battle on a single board of open ground, in the given phase.
+    fn battle(phase: Phase) -> BattleManager {
+        let mut bm = BattleManager::new(Map::new(&[1]), Side::Axis, 7);
+        while bm.phase != phase {
+            bm.end_phase();
+        }
+        bm
+    }
+
+    fn squad(bm: &mut BattleManager, side: Side, unit: &str, at: &str) {
+        let squad = Squad::new(ars(unit), 4, 6, 7);
+        bm.deploy_squad(side, squad, hex(at)).unwrap();
+    }
+
+    fn lmg() -> SupportWeapon {
+        SupportWeapon::new(WeaponType::LMG, 2, 3, 6, 12, 1)
+    }
+
+    // Fire groups
+
+    #[test]
+    fn fire_group_combines_firepower() {
+        let mut bm = battle(Phase::PrepFire);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Axis, "1.1.1.2", "1C4");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C7");
+        bm.issue_support_weapon(1, lmg(), ars("1.1.1.1")).unwrap();
+        let group =
+            FireGroup::new(&[ars("1.1.1.1"), ars("1.1.1.2")]).with_weapon(1);
+        let resolution = bm.fire(&group, hex("1C7")).unwrap();
+        assert_eq!(resolution.firepower, 10);
+        assert_eq!(resolution.contributions.len(), 3);
+        assert_eq!(bm.fire(&group, hex("1C7")), Err(RuleBreak::A8200));
+    }
+
+    #[test]
+    fn fire_group_members_are_checked() {
+        let mut bm = battle(Phase::Rally);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Axis, "1.1.1.2", "1C6");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C7");
+        let alone = FireGroup::new(&[ars("1.1.1.1")]);
+        assert_eq!(bm.fire(&alone, hex("1C7")), Err(RuleBreak::E4000));
+        bm.end_phase();
+        let apart = FireGroup::new(&[ars("1.1.1.1"), ars("1.1.1.2")]);
+        assert_eq!(bm.fire(&apart, hex("1C7")), Err(RuleBreak::A8100));
+        let mixed = FireGroup::new(&[ars("1.1.1.2"), ars("2.1.1.1")]);
+        assert_eq!(bm.fire(&mixed, hex("1C7")), Err(RuleBreak::A8600));
+        let twice = FireGroup::new(&[ars("1.1.1.1"), ars("1.1.1.1")]);
+        assert_eq!(bm.fire(&twice, hex("1C7")), Err(RuleBreak::E0004));
+        let carried = FireGroup::new(&[ars("1.1.1.1")]).with_weapon(1);
+        assert_eq!(bm.fire(&carried, hex("1C7")), Err(RuleBreak::E0003));
+    }
+
+    #[test]
+    fn fire_group_needs_line_of_sight() {
+        let mut map = Map::new(&[1]);
+        map.hex_mut(&hex("1C5")).unwrap().terrain = vec![Terrain::Woods];
+        let mut bm = BattleManager::new(map, Side::Axis, 7);
+        bm.end_phase();
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C7");
+        let group = FireGroup::new(&[ars("1.1.1.1")]);
+        assert_eq!(bm.fire(&group, hex("1C7")), Err(RuleBreak::E7100));
+    }
+
+    #[test]
+    fn weapons_are_issued_once() {
+        let mut bm = battle(Phase::PrepFire);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        bm.issue_support_weapon(1, lmg(), ars("1.1.1.1")).unwrap();
+        let again = bm.issue_support_weapon(1, lmg(), ars("1.1.1.1"));
+        assert_eq!(again, Err(RuleBreak::E0004));
+        let left = bm.leave_support_weapon(1, lmg(), Side::Axis, hex("1C4"));
+        assert_eq!(left, Err(RuleBreak::E0004));
+        let facing = Facing(2);
+        bm.deploy_vehicle(Side::Axis, halftrack(), hex("1C5"), facing)
+            .unwrap();
+        let mounted = bm.mount(1, lmg(), ars("1.1.9.1"));
+        assert_eq!(mounted, Err(RuleBreak::E0004));
+        assert!(!bm.abandoned.contains_key(&1));
+        assert!(!bm.mounted.contains(&1));
+        assert_eq!(bm.carriers[&1], ars("1.1.1.1"));
+    }
+
+    // Range
+
+    #[test]
+    fn range_changes_firepower() {
+        let mut bm = battle(Phase::PrepFire);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1A5");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1A6");
+        squad(&mut bm, Side::Allies, "2.1.1.2", "1J5");
+        squad(&mut bm, Side::Allies, "2.1.1.3", "1P5");
+        let group = FireGroup::new(&[ars("1.1.1.1")]);
+        let firepower = |bm: &BattleManager, target: &str| {
+            let (contributions, _) =
+                bm.check_fire_group(&group, &hex(target))?;
+            Ok(contributions[0].firepower)
+        };
+        assert_eq!(bm.map.distance(&hex("1A5"), &hex("1J5")), Some(9));
+        assert_eq!(bm.map.distance(&hex("1A5"), &hex("1P5")), Some(15));
+        assert_eq!(firepower(&bm, "1A6"), Ok(8));
+        assert_eq!(firepower(&bm, "1J5"), Ok(2));
+        assert_eq!(firepower(&bm, "1P5"), Err(RuleBreak::E2300));
+    }
+
+    #[test]
+    fn point_blank_firepower_saturates() {
+        let mut bm = battle(Phase::PrepFire);
+        let squad = Squad::new(ars("1.1.1.1"), 200, 6, 7);
+        bm.deploy_squad(Side::Axis, squad, hex("1C1")).unwrap();
+        let squad = Squad::new(ars("1.1.1.2"), 200, 6, 7);
+        bm.deploy_squad(Side::Axis, squad, hex("1C1")).unwrap();
+        self::squad(&mut bm, Side::Allies, "2.1.1.1", "1C2");
+        let group = FireGroup::new(&[ars("1.1.1.1"), ars("1.1.1.2")]);
+        let resolution = bm.fire(&group, hex("1C2")).unwrap();
+        assert_eq!(resolution.contributions[0].firepower, u8::MAX);
+        assert_eq!(resolution.firepower, u8::MAX);
+    }
+
+    // Leaders
+
+    #[test]
+    fn leadership_modifies_fire_and_morale() {
+        let mut bm = battle(Phase::PrepFire);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        let leader = Leader::new(ars("1.1.1.0"), "Lt. Weber", -2, 8);
+        bm.deploy_leader(Side::Axis, leader, hex("1C3")).unwrap();
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C7");
+        let squad = &bm.squads[&ars("1.1.1.1")];
+        assert_eq!(squad.get_leadership_modifier(&bm), -2);
+        let group = FireGroup::new(&[ars("1.1.1.1"), ars("1.1.1.0")]);
+        let (_, modifiers) = bm.check_fire_group(&group, &hex("1C7")).unwrap();
+        let leadership = FireModifier::Leadership(ars("1.1.1.0"), -2);
+        assert!(modifiers.contains(&leadership));
+        // A broken leader leads no one.
+        bm.conditions.insert(ars("1.1.1.0"), Condition::Broken);
+        let squad = &bm.squads[&ars("1.1.1.1")];
+        assert_eq!(squad.get_leadership_modifier(&bm), 0);
+        assert!(!squad.stacked_with_leader(&bm));
+    }
+
+    #[test]
+    fn leaders_lend_movement() {
+        let mut bm = battle(Phase::Movement);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Axis, "1.1.1.2", "1C3");
+        let leader = Leader::new(ars("1.1.1.0"), "Lt. Weber", -1, 8);
+        bm.deploy_leader(Side::Axis, leader, hex("1C3")).unwrap();
+        let (escorted, alone, leader) =
+            (ars("1.1.1.1"), ars("1.1.1.2"), ars("1.1.1.0"));
+        let path = [hex("1C4"), hex("1C5")];
+        assert_eq!(bm.move_stack(&[escorted, leader], &path), Ok(2));
+        assert_eq!(bm.mf_allowance(&escorted), 6);
+        assert_eq!(bm.mf_allowance(&alone), 4);
+        let path = [hex("1C4"), hex("1C5"), hex("1C6"), hex("1C7")];
+        assert_eq!(bm.move_stack(&[alone], &path[..]), Ok(4));
+        assert_eq!(
+            bm.move_stack(&[alone], &[hex("1C8")]),
+            Err(RuleBreak::E5200)
+        );
+    }
+
+    #[test]
+    fn stacks_move_each_unit_once() {
+        let mut bm = battle(Phase::Movement);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        let unit = ars("1.1.1.1");
+        let twice = bm.move_stack(&[unit, unit], &[hex("1C4")]);
+        assert_eq!(twice, Err(RuleBreak::E0004));
+        assert_eq!(bm.move_stack(&[unit], &[]), Err(RuleBreak::E0005));
+        let back_and_forth: Vec<HexId> =
+            ["1C4", "1C3"].iter().cycle().take(300).map(|h| hex(h)).collect();
+        let long = bm.move_stack(&[unit], &back_and_forth);
+        assert_eq!(long, Err(RuleBreak::E5200));
+        assert!(!bm.has_marker(&unit, Marker::Moved));
+        assert_eq!(bm.move_stack(&[unit], &[hex("1C4")]), Ok(1));
+        assert!(bm.has_marker(&unit, Marker::Moved));
+    }
+
+    // Stacking
+
+    #[test]
+    fn moves_may_not_overstack() {
+        let mut bm = battle(Phase::Movement);
+        for unit in ["1.1.1.1", "1.1.1.2", "1.1.1.3"] {
+            squad(&mut bm, Side::Axis, unit, "1C3");
+        }
+        squad(&mut bm, Side::Axis, "1.1.1.4", "1C4");
+        let unit = ars("1.1.1.4");
+        let overstacked = bm.move_stack(&[unit], &[hex("1C3")]);
+        assert_eq!(overstacked, Err(RuleBreak::E6100));
+        let through = bm.move_stack(&[unit], &[hex("1C3"), hex("1C2")]);
+        assert_eq!(through, Ok(2));
+    }
+
+    #[test]
+    fn advances_and_routs_may_not_overstack() {
+        let mut bm = battle(Phase::Rout);
+        bm.set_stacking_limit(StackingLimit {
+            squads: 1,
+            ..bm.stacking_limit
+        });
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Axis, "1.1.1.2", "1C4");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1E3");
+        squad(&mut bm, Side::Allies, "2.1.1.2", "1E5");
+        bm.conditions.insert(ars("2.1.1.2"), Condition::Broken);
+        let rout = bm.rout(ars("2.1.1.2"), &[hex("1E4"), hex("1E3")]);
+        assert_eq!(rout, Err(RuleBreak::E6100));
+        assert_eq!(bm.rout(ars("2.1.1.2"), &[hex("1E6")]), Ok(()));
+        bm.end_phase();
+        let advance = bm.advance(&[ars("1.1.1.2")], hex("1C3"));
+        assert_eq!(advance, Err(RuleBreak::E6100));
+        assert_eq!(bm.advance(&[ars("1.1.1.2")], hex("1C5")), Ok(()));
+    }
+
+    #[test]
+    fn overstacked_hexes_are_vulnerable() {
+        let mut bm = battle(Phase::PrepFire);
+        bm.set_stacking_limit(StackingLimit {
+            squads: 1,
+            ..bm.stacking_limit
+        });
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C7");
+        squad(&mut bm, Side::Allies, "2.1.1.2", "1C7");
+        squad(&mut bm, Side::Allies, "2.1.1.3", "1C7");
+        let group = FireGroup::new(&[ars("1.1.1.1")]);
+        let (_, modifiers) = bm.check_fire_group(&group, &hex("1C7")).unwrap();
+        assert_eq!(modifiers, vec![FireModifier::Overstacked(-2)]);
+    }
+
+    // Portage
+
+    #[test]
+    fn portage_slows_and_encumbers() {
+        let mut bm = battle(Phase::Movement);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Axis, "1.1.1.2", "1C3");
+        let (unit, other) = (ars("1.1.1.1"), ars("1.1.1.2"));
+        let mmg = SupportWeapon::new(WeaponType::MMG, 4, 3, 8, 12, 4);
+        bm.issue_support_weapon(1, mmg, unit).unwrap();
+        bm.issue_support_weapon(2, lmg(), unit).unwrap();
+        assert_eq!(bm.portage(&unit), 5);
+        assert_eq!(bm.mf_allowance(&unit), 4 - bm.portage_penalty(&unit));
+        assert!(bm.portage_penalty(&unit) > 0);
+        assert_eq!(bm.move_stack(&[unit], &[hex("1C4")]), Ok(1));
+        bm.abandon(unit, 2).unwrap();
+        assert_eq!(bm.abandoned.get(&2), Some(&hex("1C4")));
+        assert_eq!(bm.carry(other, 2), Err(RuleBreak::A5700));
+        bm.end_phase();
+        bm.end_phase();
+        assert_eq!(bm.phase, Phase::AdvancingFire);
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C6");
+        let group = FireGroup::new(&[]).with_weapon(1);
+        assert_eq!(bm.fire(&group, hex("1C6")), Err(RuleBreak::E5740));
+    }
+
+    #[test]
+    fn weapons_change_hands() {
+        let mut bm = battle(Phase::Movement);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Axis, "1.1.1.2", "1C3");
+        let (unit, other) = (ars("1.1.1.1"), ars("1.1.1.2"));
+        bm.issue_support_weapon(1, lmg(), unit).unwrap();
+        bm.transfer(1, unit, other).unwrap();
+        assert_eq!(bm.carriers[&1], other);
+        bm.abandon(other, 1).unwrap();
+        bm.carry(unit, 1).unwrap();
+        assert_eq!(bm.carriers[&1], unit);
+        assert_eq!(bm.mf_spent[&unit], 1);
+    }
+
+    #[test]
+    fn picking_up_weapons_may_not_overstack() {
+        let mut bm = battle(Phase::Movement);
+        let limit = StackingLimit { support_weapons: 1, ..bm.stacking_limit };
+        bm.set_stacking_limit(limit);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        let unit = ars("1.1.1.1");
+        bm.issue_support_weapon(1, lmg(), unit).unwrap();
+        bm.leave_support_weapon(2, lmg(), Side::Axis, hex("1C3")).unwrap();
+        assert_eq!(bm.carry(unit, 2), Err(RuleBreak::E6100));
+        assert_eq!(bm.abandoned.get(&2), Some(&hex("1C3")));
+        assert!(!bm.carriers.contains_key(&2));
+        bm.abandon(unit, 1).unwrap();
+        assert_eq!(bm.carry(unit, 2), Ok(()));
+    }
+
+    // Weapon families
+
+    #[test]
+    fn one_weapon_family_per_phase() {
+        let mut bm = battle(Phase::PrepFire);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C7");
+        squad(&mut bm, Side::Allies, "2.1.1.2", "1D7");
+        let unit = ars("1.1.1.1");
+        let mmg = SupportWeapon::new(WeaponType::MMG, 4, 3, 8, 13, 2);
+        bm.issue_support_weapon(1, lmg(), unit).unwrap();
+        bm.issue_support_weapon(2, mmg, unit).unwrap();
+        let both = FireGroup::new(&[]).with_weapon(1).with_weapon(2);
+        let check = bm.check_fire_group(&both, &hex("1C7"));
+        assert_eq!(check.unwrap_err(), RuleBreak::E5750);
+        bm.fire(&FireGroup::new(&[]).with_weapon(1), hex("1C7")).unwrap();
+        let again = FireGroup::new(&[]).with_weapon(2);
+        let check = bm.check_fire_group(&again, &hex("1D7"));
+        assert_eq!(check.unwrap_err(), RuleBreak::E5750);
+        bm.end_phase();
+        assert!(bm.weapon_types_fired.is_empty());
+    }
+
+    // Captured weapons
+
+    #[test]
+    fn enemy_weapons_are_captured() {
+        let mut bm = battle(Phase::Movement);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C4");
+        let (unit, enemy) = (ars("1.1.1.1"), ars("2.1.1.1"));
+        bm.issue_support_weapon(1, lmg(), enemy).unwrap();
+        let gun = SupportWeapon::new(WeaponType::TankGun, 8, 1, 12, 12, 0);
+        bm.leave_support_weapon(2, gun, Side::Allies, hex("1C3")).unwrap();
+        // Eliminated units leave their weapons behind.
+        bm.eliminate(enemy);
+        assert_eq!(bm.abandoned.get(&1), Some(&hex("1C4")));
+        bm.move_stack(&[unit], &[hex("1C4")]).unwrap();
+        bm.carry(unit, 1).unwrap();
+        assert!(bm.captured(&1));
+        assert_eq!(bm.breakdown(&1), 10);
+        let captured = Event::Captured { ars: unit, weapon: 1 };
+        assert!(bm.events.contains(&captured));
+        assert_eq!(bm.carry(unit, 2), Err(RuleBreak::A5700));
+        assert_eq!(bm.check_capture(Side::Axis, &2), Err(RuleBreak::E1810));
+        assert_eq!(bm.check_capture(Side::Allies, &2), Ok(()));
+    }
+
+    // Vehicles
+
+    fn halftrack() -> Vehicle {
+        let armor = Armor { front: 4, side: 2, rear: 1 };
+        let kind = VehicleType::Halftrack;
+        Vehicle::new(ars("1.1.9.1"), "SdKfz 251", kind, 12, armor, 7)
+    }
+
+    #[test]
+    fn vehicles_carry_passengers() {
+        let mut map = Map::new(&[1]);
+        map.hex_mut(&hex("1E5")).unwrap().terrain = vec![Terrain::Woods];
+        let mut bm = BattleManager::new(map, Side::Axis, 7);
+        bm.end_phase();
+        bm.end_phase();
+        let (vehicle, truck) = (ars("1.1.9.1"), ars("1.1.9.2"));
+        bm.deploy_vehicle(Side::Axis, halftrack(), hex("1C5"), Facing(2))
+            .unwrap();
+        let lorry = Vehicle::new(
+            truck,
+            "Opel Blitz",
+            VehicleType::Truck,
+            14,
+            Armor::default(),
+            7,
+        );
+        bm.deploy_vehicle(Side::Axis, lorry, hex("1C6"), Facing(0)).unwrap();
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C5");
+        squad(&mut bm, Side::Axis, "1.1.1.2", "1C5");
+        let (rider, other) = (ars("1.1.1.1"), ars("1.1.1.2"));
+        bm.embark(rider, vehicle).unwrap();
+        assert_eq!(bm.embark(other, vehicle), Err(RuleBreak::A1840));
+        let walk = bm.move_stack(&[rider], &[hex("1D5")]);
+        assert_eq!(walk, Err(RuleBreak::A1841));
+        let drive = bm.move_stack(&[vehicle], &[hex("1D5")]);
+        assert_eq!(drive, Err(RuleBreak::A1820));
+        assert_eq!(bm.move_vehicle(truck, &[]), Err(RuleBreak::E0005));
+        let back_and_forth: Vec<HexId> =
+            ["1C7", "1C6"].iter().cycle().take(300).map(|h| hex(h)).collect();
+        let long = bm.move_vehicle(truck, &back_and_forth);
+        assert_eq!(long, Err(RuleBreak::E5200));
+        let woods = bm.move_vehicle(truck, &[hex("1D5"), hex("1E5")]);
+        assert_eq!(woods, Err(RuleBreak::A1821));
+        bm.move_vehicle(vehicle, &[hex("1D5"), hex("1E5")]).unwrap();
+        assert_eq!(bm.locations[&rider], hex("1E5"));
+        bm.disembark(rider).unwrap();
+        assert!(bm.riders(&vehicle).is_empty());
+    }
+
+    #[test]
+    fn vehicles_fire_within_their_covered_arc() {
+        let mut bm = battle(Phase::PrepFire);
+        let vehicle = ars("1.1.9.1");
+        bm.deploy_vehicle(Side::Axis, halftrack(), hex("1E5"), Facing(2))
+            .unwrap();
+        bm.mount(1, lmg(), vehicle).unwrap();
+        assert_eq!(bm.abandon(vehicle, 1), Err(RuleBreak::A1810));
+        assert_eq!(bm.check_covered_arc(&vehicle, &hex("1H5")), Ok(()));
+        let behind = bm.check_covered_arc(&vehicle, &hex("1B5"));
+        assert_eq!(behind, Err(RuleBreak::A1831));
+        let turret = bm.rotate_turret(vehicle, Facing(0));
+        assert_eq!(turret, Err(RuleBreak::A1830));
+    }
+
+    // Anti-tank fire
+
+    #[test]
+    fn to_hit_falls_with_range() {
+        assert_eq!(to_hit_number(1), 10);
+        assert_eq!(to_hit_number(6), 8);
+        assert_eq!(to_hit_number(17), 5);
+        assert_eq!(damage_table(4), ArmorResult::Destroyed);
+        assert_eq!(damage_table(6), ArmorResult::Immobilized);
+        assert_eq!(damage_table(8), ArmorResult::Shocked);
+        assert_eq!(damage_table(9), ArmorResult::NoEffect);
+    }
+
+    #[test]
+    fn guns_fire_at_vehicles() {
+        for seed in 0..20 {
+            let mut bm = BattleManager::new(Map::new(&[1]), Side::Axis, seed);
+            bm.end_phase();
+            let (tank, target) = (ars("1.1.9.1"), ars("2.1.9.1"));
+            let armor = Armor { front: 6, side: 3, rear: 2 };
+            let panzer =
+                Vehicle::new(tank, "PzIV", VehicleType::Tank, 12, armor, 8);
+            bm.deploy_vehicle(Side::Axis, panzer, hex("1C5"), Facing(2))
+                .unwrap();
+            let gun = SupportWeapon::new(WeaponType::TankGun, 8, 1, 12, 12, 0);
+            bm.mount(1, gun.with_armor_penetration(7), tank).unwrap();
+            bm.mount(2, lmg(), tank).unwrap();
+            let armor = Armor { front: 2, side: 1, rear: 1 };
+            let kind = VehicleType::Halftrack;
+            let m3 = Vehicle::new(target, "M3", kind, 12, armor, 7);
+            bm.deploy_vehicle(Side::Allies, m3, hex("1F6"), Facing(0))
+                .unwrap();
+            let lmg_fire = bm.fire_at_vehicle(tank, 2, target);
+            assert_eq!(lmg_fire.unwrap_err(), RuleBreak::A8700);
+            let resolution = bm.fire_at_vehicle(tank, 1, target).unwrap();
+            assert_eq!(
+                resolution.facing.is_some(),
+                resolution.result != ArmorResult::Miss
+            );
+            let destroyed = resolution.result == ArmorResult::Destroyed;
+            assert_eq!(bm.locations.contains_key(&target), !destroyed);
+        }
+    }
+
+    // Targets
+
+    #[test]
+    fn structures_collapse() {
+        let mut map = Map::new(&[1]);
+        map.hex_mut(&hex("1E5")).unwrap().terrain =
+            vec![Terrain::EnterBuilding];
+        let mut bm = BattleManager::new(map, Side::Axis, 5);
+        let building =
+            Structure::new(StructureKind::Building, hex("1E5"), 4, 6);
+        bm.raise_structure(building).unwrap();
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1E5");
+        let target = TargetId::Structure(hex("1E5"));
+        assert_eq!(bm.damage(target, 3), Ok(Damage::Resisted));
+        assert_eq!(bm.damage(target, 8), Ok(Damage::Damaged(2)));
+        assert_eq!(bm.damage(target, 8), Ok(Damage::Destroyed));
+        assert_eq!(bm.map.hexes[&hex("1E5")].terrain, vec![Terrain::Rubble]);
+        assert_eq!(bm.damage(target, 8), Err(RuleBreak::A1100));
+        assert!(bm.events.contains(&Event::Destroyed(target)));
+    }
+
+    #[test]
+    fn destroyed_bridges_cannot_be_crossed() {
+        let mut map = Map::new(&[1]);
+        map.hex_mut(&hex("1C4")).unwrap().terrain = vec![Terrain::OnRoad];
+        let mut bm = BattleManager::new(map, Side::Axis, 5);
+        let bridge = Structure::new(StructureKind::Bridge, hex("1C4"), 6, 2);
+        bm.raise_structure(bridge).unwrap();
+        bm.end_phase();
+        bm.end_phase();
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        let unit = ars("1.1.1.1");
+        let target = TargetId::Structure(hex("1C4"));
+        assert_eq!(bm.damage(target, 9), Ok(Damage::Destroyed));
+        assert_eq!(bm.map.hexes[&hex("1C4")].terrain, vec![Terrain::Water]);
+        let across = bm.move_stack(&[unit], &[hex("1C4"), hex("1C5")]);
+        assert_eq!(across, Err(RuleBreak::A1200));
+    }
+
+    #[test]
+    fn vehicles_are_targets() {
+        let mut bm = battle(Phase::Rally);
+        let truck = ars("1.1.9.1");
+        let kind = VehicleType::Truck;
+        let opel = Vehicle::new(truck, "Opel", kind, 12, Armor::default(), 7);
+        bm.deploy_vehicle(Side::Axis, opel, hex("1C5"), Facing(0)).unwrap();
+        let target = TargetId::Vehicle(truck);
+        assert_eq!(bm.damage(target, 1), Ok(Damage::Destroyed));
+        assert!(!bm.locations.contains_key(&truck));
+        assert_eq!(bm.damage(target, 1), Err(RuleBreak::A1100));
+    }
+
+    // Special attacks
+
+    #[test]
+    fn flamethrowers_fire_alone() {
+        let mut map = Map::new(&[1]);
+        map.hex_mut(&hex("1C6")).unwrap().terrain = vec![Terrain::Woods];
+        let mut bm = BattleManager::new(map, Side::Axis, 11);
+        bm.end_phase();
+        squad(&mut bm, Side::Axis, "1.1.1.2", "1C5");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C6");
+        let flamethrower =
+            SupportWeapon::new(WeaponType::Flamethrower, 24, 1, 1, 10, 2);
+        bm.issue_support_weapon(1, flamethrower, ars("1.1.1.2")).unwrap();
+        let group = FireGroup::new(&[ars("1.1.1.2")]).with_weapon(1);
+        assert_eq!(bm.fire(&group, hex("1C6")), Err(RuleBreak::A8900));
+        let alone = FireGroup::new(&[]).with_weapon(1);
+        let resolution = bm.fire(&alone, hex("1C6")).unwrap();
+        // Flames ignore the cover of the woods.
+        assert!(resolution.modifiers.is_empty());
+        assert_eq!(resolution.firepower, 24);
+    }
+
+    #[test]
+    fn blazes_burn_out() {
+        let mut map = Map::new(&[1]);
+        map.hex_mut(&hex("1C6")).unwrap().terrain = vec![Terrain::Woods];
+        map.hex_mut(&hex("1E6")).unwrap().terrain =
+            vec![Terrain::EnterBuilding];
+        let mut bm = BattleManager::new(map, Side::Axis, 11);
+        let building =
+            Structure::new(StructureKind::Building, hex("1E6"), 10, 5);
+        bm.raise_structure(building).unwrap();
+        bm.ignite(hex("1C6"));
+        bm.ignite(hex("1E6"));
+        let terrain =
+            |bm: &BattleManager| bm.map.hexes[&hex("1C6")].terrain.clone();
+        assert_eq!(terrain(&bm), vec![Terrain::Woods, Terrain::Blaze]);
+        assert!(!bm.map.los(&hex("1C5"), &hex("1C7")));
+        let end_player_turn = |bm: &mut BattleManager| {
+            bm.end_phase();
+            while bm.phase != Phase::Rally {
+                bm.end_phase();
+            }
+        };
+        end_player_turn(&mut bm);
+        assert_eq!(terrain(&bm), vec![Terrain::OpenGround, Terrain::Blaze]);
+        let rubble = &bm.map.hexes[&hex("1E6")].terrain;
+        assert_eq!(rubble, &vec![Terrain::Rubble, Terrain::Blaze]);
+        assert!(!bm.structures.contains_key(&hex("1E6")));
+        let target = TargetId::Structure(hex("1E6"));
+        assert!(bm.events.contains(&Event::Destroyed(target)));
+        end_player_turn(&mut bm);
+        assert_eq!(terrain(&bm), vec![Terrain::OpenGround]);
+        assert!(bm.map.los(&hex("1C5"), &hex("1C7")));
+        assert!(bm.events.contains(&Event::BurnedOut(hex("1C6"))));
+    }
+
+    #[test]
+    fn demolition_charges_are_placed() {
+        let mut map = Map::new(&[1]);
+        map.hex_mut(&hex("1C6")).unwrap().terrain =
+            vec![Terrain::EnterBuilding];
+        let mut bm = BattleManager::new(map, Side::Axis, 11);
+        let building =
+            Structure::new(StructureKind::Building, hex("1C6"), 10, 5);
+        bm.raise_structure(building).unwrap();
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C5");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C6");
+        let unit = ars("1.1.1.1");
+        let charge =
+            SupportWeapon::new(WeaponType::Demolition, 30, 1, 1, 12, 2);
+        bm.issue_support_weapon(2, charge, unit).unwrap();
+        while bm.phase != Phase::PrepFire {
+            bm.end_phase();
+        }
+        let fired = bm.fire(&FireGroup::new(&[]).with_weapon(2), hex("1C6"));
+        assert_eq!(fired, Err(RuleBreak::A4700));
+        while bm.phase != Phase::Advance {
+            bm.end_phase();
+        }
+        let far = bm.place_charge(unit, 2, hex("1C8"));
+        assert_eq!(far, Err(RuleBreak::A4710));
+        bm.place_charge(unit, 2, hex("1C6")).unwrap();
+        assert!(!bm.carriers.contains_key(&2));
+        bm.end_phase();
+        assert!(bm.charges.is_empty());
+        assert!(bm
+            .events
+            .iter()
+            .any(|event| matches!(event, Event::Detonated { weapon: 2, .. })));
+    }
+
+    // Minefields
+
+    /// Plays out a move through a minefield with each seed until the mines
+    /// break the moving squad, and returns the battle.
+    fn broken_by_mines(setup: impl Fn(&mut BattleManager)) -> BattleManager {
+        (0..100)
+            .find_map(|seed| {
+                let mut bm =
+                    BattleManager::new(Map::new(&[1]), Side::Axis, seed);
+                bm.end_phase();
+                bm.end_phase();
+                squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+                let mines =
+                    Minefield::new(WeaponType::APMine, 12, Side::Allies);
+                bm.lay_minefield(hex("1C5"), mines).unwrap();
+                setup(&mut bm);
+                let path = [hex("1C4"), hex("1C5"), hex("1C6")];
+                bm.move_stack(&[ars("1.1.1.1")], &path).unwrap();
+                let broken = bm.broken(&ars("1.1.1.1"))
+                    && bm.locations.contains_key(&ars("1.1.1.1"));
+                broken.then_some(bm)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn mines_stop_broken_units() {
+        let bm = broken_by_mines(|_| {});
+        assert_eq!(bm.locations[&ars("1.1.1.1")], hex("1C5"));
+        let moved = Event::Moved {
+            units: vec![ars("1.1.1.1")],
+            path: vec![hex("1C4"), hex("1C5")],
+        };
+        assert!(bm.events.contains(&moved));
+        assert!(bm.minefields[&hex("1C5")].revealed);
+    }
+
+    #[test]
+    fn mines_stop_units_short_of_overstacking() {
+        let bm = broken_by_mines(|bm| {
+            let limit = StackingLimit { squads: 1, ..bm.stacking_limit };
+            bm.set_stacking_limit(limit);
+            squad(bm, Side::Axis, "1.1.1.2", "1C5");
+        });
+        assert_eq!(bm.locations[&ars("1.1.1.1")], hex("1C4"));
+        assert_eq!(bm.overstacked(Side::Axis, &hex("1C5")), 0);
+    }
+
+    #[test]
+    fn anti_tank_mines_ignore_infantry() {
+        let mut bm = battle(Phase::Movement);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        let mines = Minefield::new(WeaponType::ATMine, 8, Side::Allies);
+        bm.lay_minefield(hex("1C4"), mines).unwrap();
+        bm.move_stack(&[ars("1.1.1.1")], &[hex("1C4")]).unwrap();
+        assert!(!bm.minefields[&hex("1C4")].revealed);
+        let rifles = Minefield::new(WeaponType::Rifle, 8, Side::Allies);
+        assert_eq!(
+            bm.lay_minefield(hex("1C5"), rifles),
+            Err(RuleBreak::A5800)
+        );
+    }
+
+    #[test]
+    fn views_hide_what_the_side_cannot_see() {
+        let mut map = Map::new(&[1]);
+        map.hex_mut(&hex("1C5")).unwrap().terrain = vec![Terrain::Woods];
+        let mut bm = BattleManager::new(map, Side::Axis, 7);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C4");
+        squad(&mut bm, Side::Allies, "2.1.1.2", "1C7");
+        let mines = Minefield::new(WeaponType::APMine, 8, Side::Allies);
+        bm.lay_minefield(hex("1C8"), mines).unwrap();
+        let axis = bm.view(Side::Axis);
+        let seen: Vec<Ipv4Addr> = axis.units.keys().copied().collect();
+        assert_eq!(seen, vec![ars("1.1.1.1"), ars("2.1.1.1")]);
+        assert!(axis.minefields.is_empty());
+        let allies = bm.view(Side::Allies);
+        assert_eq!(allies.units.len(), 3);
+        assert_eq!(allies.minefields.len(), 1);
+    }
+
+    // Mortars
+
+    fn mortar() -> SupportWeapon {
+        SupportWeapon::new(WeaponType::Mortar, 8, 1, 12, 12, 1)
+    }
+
+    #[test]
+    fn mortars_fire_with_a_spotter() {
+        let mut map = Map::new(&[1]);
+        map.hex_mut(&hex("1C5")).unwrap().terrain = vec![Terrain::Woods];
+        let mut bm = BattleManager::new(map, Side::Axis, 3);
+        bm.end_phase();
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C2");
+        let leader = Leader::new(ars("1.1.1.0"), "Lt. Weber", -1, 8);
+        bm.deploy_leader(Side::Axis, leader, hex("1E7")).unwrap();
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C8");
+        bm.issue_support_weapon(1, mortar(), ars("1.1.1.1")).unwrap();
+        let group = FireGroup::new(&[]).with_weapon(1);
+        let unseen = bm.check_fire_group(&group, &hex("1C8"));
+        assert_eq!(unseen.unwrap_err(), RuleBreak::E7100);
+        let with_squad = FireGroup::new(&[ars("1.1.1.1")]).with_weapon(1);
+        let grouped = bm.check_fire_group(&with_squad, &hex("1C8"));
+        assert_eq!(grouped.unwrap_err(), RuleBreak::A7500);
+        let enemy = group.clone().with_spotter(ars("2.1.1.1"));
+        let spotted = bm.check_fire_group(&enemy, &hex("1C8"));
+        assert_eq!(spotted.unwrap_err(), RuleBreak::A7520);
+        let spotted = group.with_spotter(ars("1.1.1.0"));
+        let (_, modifiers) =
+            bm.check_fire_group(&spotted, &hex("1C8")).unwrap();
+        assert!(modifiers.contains(&FireModifier::Spotted(ars("1.1.1.0"))));
+        bm.fire(&spotted, hex("1C8")).unwrap();
+    }
+
+    #[test]
+    fn mortars_have_a_minimum_range() {
+        let mut bm = battle(Phase::PrepFire);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C2");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C3");
+        bm.issue_support_weapon(1, mortar(), ars("1.1.1.1")).unwrap();
+        let group = FireGroup::new(&[]).with_weapon(1);
+        let close = bm.check_fire_group(&group, &hex("1C3"));
+        assert_eq!(close.unwrap_err(), RuleBreak::A7510);
+    }
+
+    // Artillery
+
+    #[test]
+    fn only_leaders_carry_radios() {
+        let mut bm = battle(Phase::PrepFire);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C2");
+        let module =
+            ArtilleryModule::new(Side::Axis, 20, ars("1.1.1.1"), 7, 2);
+        assert_eq!(bm.add_artillery(1, module), Err(RuleBreak::A7600));
+        let leader = Leader::new(ars("1.1.1.0"), "Lt. Weber", -1, 8);
+        bm.deploy_leader(Side::Axis, leader, hex("1C2")).unwrap();
+        let module =
+            ArtilleryModule::new(Side::Axis, 20, ars("1.1.1.0"), 7, 2)
+                .with_pre_registered(hex("9Z9"));
+        assert_eq!(bm.add_artillery(1, module), Err(RuleBreak::E0002));
+    }
+
+    #[test]
+    fn fire_missions_are_called_once_per_phase() {
+        for seed in 0..10 {
+            let mut bm = BattleManager::new(Map::new(&[1]), Side::Axis, seed);
+            let leader = Leader::new(ars("1.1.1.0"), "Lt. Weber", -1, 8);
+            bm.deploy_leader(Side::Axis, leader, hex("1C2")).unwrap();
+            squad(&mut bm, Side::Allies, "2.1.1.1", "1C8");
+            let module =
+                ArtilleryModule::new(Side::Axis, 20, ars("1.1.1.0"), 7, 2)
+                    .with_pre_registered(hex("1H8"));
+            bm.add_artillery(1, module).unwrap();
+            let he = Ammunition::HighExplosive;
+            let early = bm.call_fire_mission(1, hex("1C8"), he);
+            assert_eq!(early.unwrap_err(), RuleBreak::E4000);
+            bm.end_phase();
+            let resolution = bm.call_fire_mission(1, hex("1C8"), he).unwrap();
+            let missions = bm.artillery[&1].missions;
+            match resolution.accuracy {
+                Some(_) => assert_eq!(missions, 1),
+                None => assert_eq!(missions, 2),
+            }
+            if resolution.impact.is_some() {
+                assert!(resolution.blasts.len() >= 4);
+            }
+            let again = bm.call_fire_mission(1, hex("1C8"), he);
+            assert_eq!(again.unwrap_err(), RuleBreak::A8200);
+        }
+    }
+
+    // Smoke
+
+    #[test]
+    fn smoke_is_thrown_into_adjacent_hexes_once() {
+        let mut bm = battle(Phase::Movement);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C2");
+        squad(&mut bm, Side::Axis, "1.1.1.2", "1C2");
+        let far = bm.throw_smoke(ars("1.1.1.1"), hex("1C5"));
+        assert_eq!(far.unwrap_err(), RuleBreak::A7710);
+        let mut placed = false;
+        for unit in ["1.1.1.1", "1.1.1.2"] {
+            placed |= bm.throw_smoke(ars(unit), hex("1C3")).unwrap();
+            assert_eq!(placed, bm.map.hexes[&hex("1C3")].smoke.is_some());
+            let again = bm.throw_smoke(ars(unit), hex("1C3"));
+            assert_eq!(again.unwrap_err(), RuleBreak::A7720);
+        }
+    }
+
+    #[test]
+    fn smoke_blocks_sight_then_disperses() {
+        let mut bm = battle(Phase::DefensiveFire);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C2");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C6");
+        assert!(bm.map.los(&hex("1C2"), &hex("1C6")));
+        let smoke = Smoke { side: Side::Axis, dispersed: false };
+        bm.map.hex_mut(&hex("1C4")).unwrap().smoke = Some(smoke);
+        assert!(!bm.map.los(&hex("1C2"), &hex("1C6")));
+        let group = FireGroup::new(&[ars("2.1.1.1")]);
+        assert_eq!(bm.fire(&group, hex("1C2")), Err(RuleBreak::E7100));
+        let smoke_shot = group.with_ammunition(Ammunition::Smoke);
+        assert_eq!(bm.fire(&smoke_shot, hex("1C2")), Err(RuleBreak::A7730));
+
+        while bm.attacker == Side::Axis {
+            bm.end_phase();
+        }
+        assert_eq!(bm.map.hexes[&hex("1C4")].smoke, Some(smoke));
+        while bm.attacker == Side::Allies {
+            bm.end_phase();
+        }
+        let dispersed = Smoke { side: Side::Axis, dispersed: true };
+        assert_eq!(bm.map.hexes[&hex("1C4")].smoke, Some(dispersed));
+        assert!(bm.map.los(&hex("1C2"), &hex("1C6")));
+        assert_eq!(bm.map.hindrance(&hex("1C2"), &hex("1C6")), 1);
+
+        bm.end_phase();
+        let group = FireGroup::new(&[ars("1.1.1.1")]);
+        let (_, modifiers) = bm.check_fire_group(&group, &hex("1C6")).unwrap();
+        assert!(modifiers.contains(&FireModifier::Hindrance(1)));
+        bm.map.hex_mut(&hex("1C6")).unwrap().smoke = Some(dispersed);
+        let (contributions, _) =
+            bm.check_fire_group(&group, &hex("1C6")).unwrap();
+        assert_eq!(contributions[0].firepower, 2);
+
+        while bm.attacker == Side::Axis {
+            bm.end_phase();
+        }
+        while bm.attacker == Side::Allies {
+            bm.end_phase();
+        }
+        assert_eq!(bm.map.hexes[&hex("1C4")].smoke, None);
+    }
+
+    // Scenarios
+
+    const SCENARIO: &str = "\
+scenario The Guards Counterattack
+boards 1
+turns 5
+setup Allies
+first Axis
+rule stacking 3 4 4 2
+rule The Allied player may not use smoke.
+terrain 1D5 EnterBuilding WithinBuilding
+elevation 1E5 1
+building 1D5 10 5
+building-hexes 1 1D5 1D6
+bridge 1H3 4 3
+
+side Axis
+squad 1.1.1.1 4 6 7 1C3
+leader 1.1.1.0 -1 8 1C3 Lt. Weber
+weapon 1 LMG 2 3 6 12 1 1.1.1.1
+vehicle 1.1.9.1 Tank 8 6/4/3 7 1A1 2 PzKpfw IV
+weapon 2 TankGun 8 1 12 12 0 1.1.9.1
+artillery 1 20 1.1.1.0 7 2 1H8
+area 1E5
+
+side Allies
+area 1D5 1D6 1E5
+squad 2.1.1.1 4 6 7 1D5
+minefield 1C4 APMine 8
+reinforcements 2 West roll 8
+squad 2.2.1.1 4 6 7
+leader 2.2.1.0 -1 8 Sgt. Baker
+weapon 5 LMG 2 3 6 12 1 2.2.1.1
+reinforcements 1 1GG5 1GG6
+vehicle 2.2.9.1 Truck 10 0/0/0 7 Lorry
+
+victory Axis control 1D5 and exit East 4
+victory Axis casualties 6
+sudden-death Allies casualties 8
+";
+
+    fn scenario() -> (ScenarioManager, BattleManager) {
+        ScenarioManager::load(SCENARIO, 11).unwrap()
+    }
+
+    #[test]
+    fn scenarios_set_up_the_battle() {
+        let (sm, bm) = scenario();
+        assert_eq!(sm.name, "The Guards Counterattack");
+        assert_eq!((sm.turns, sm.half_turn), (5, false));
+        assert_eq!((sm.setup, sm.first), (Side::Allies, Side::Axis));
+        assert_eq!(sm.rules.len(), 2);
+        assert_eq!(sm.victory.len(), 3);
+        assert_eq!(bm.attacker, Side::Axis);
+        assert_eq!(bm.leaders[&ars("1.1.1.0")].identity, "Lt. Weber");
+        assert_eq!(bm.carriers[&1], ars("1.1.1.1"));
+        assert!(bm.mounted.contains(&2));
+        assert_eq!(bm.map.hexes[&hex("1E5")].elevation, 1);
+        assert!(bm.structures.contains_key(&hex("1D5")));
+        assert_eq!(bm.structures[&hex("1H3")].kind, StructureKind::Bridge);
+        assert_eq!(bm.minefields[&hex("1C4")].owner, Side::Allies);
+        assert_eq!(bm.artillery[&1].pre_registered, vec![hex("1H8")]);
+        assert_eq!(sm.reinforcements.len(), 2);
+        assert!(!bm.locations.contains_key(&ars("2.2.1.1")));
+    }
+
+    #[test]
+    fn the_side_that_sets_up_first_is_set_up_first() {
+        // The Axis claim on 1E5 is written first, but the Allies set up
+        // first, so the Axis claim is the one that stands.
+        let (_, bm) = scenario();
+        assert_eq!(bm.controller(&hex("1E5")), Some(Side::Axis));
+        assert_eq!(bm.controller(&hex("1D6")), Some(Side::Allies));
+    }
+
+    #[test]
+    fn scenario_errors_name_the_line() {
+        let load = |text: &str| ScenarioManager::load(text, 11).unwrap_err();
+        let bad =
+            SCENARIO.replace("squad 2.1.1.1 4 6 7", "squad 2.1.1.1 4 six 7");
+        let reason = "expected a range, found `six`".to_string();
+        assert_eq!(load(&bad), ScenarioError::Syntax(25, reason));
+        let bad = SCENARIO.replace("minefield 1C4", "minefield 9C4");
+        assert_eq!(load(&bad), ScenarioError::Setup(26, RuleBreak::E0002));
+        let bad = SCENARIO.replace("turns 5\n", "");
+        assert_eq!(load(&bad), ScenarioError::Missing("turns"));
+        let bad = SCENARIO.replace("side Axis\n", "");
+        assert!(matches!(load(&bad), ScenarioError::Syntax(14, _)));
+    }
+
+    #[test]
+    fn scenarios_may_not_set_up_the_same_thing_twice() {
+        let load = |text: &str| ScenarioManager::load(text, 11).unwrap_err();
+        let twice = |line: usize, item: &str| {
+            ScenarioError::Duplicate(line, item.to_string())
+        };
+        let bad = SCENARIO.replace("squad 2.1.1.1", "squad 1.1.1.1");
+        assert_eq!(load(&bad), twice(15, "unit 1.1.1.1"));
+        let bad = SCENARIO.replace("squad 2.2.1.1", "squad 2.1.1.1");
+        assert_eq!(load(&bad), twice(28, "unit 2.1.1.1"));
+        let bad = SCENARIO.replace("weapon 5", "weapon 1");
+        assert_eq!(load(&bad), twice(17, "weapon 1"));
+        let extra = "artillery 1 20 1.1.1.0 7 2\n\nside Allies";
+        let bad = SCENARIO.replacen("\nside Allies", extra, 1);
+        assert_eq!(load(&bad), twice(22, "artillery module 1"));
+    }
+
+    // Turn record
+
+    #[test]
+    fn battles_end_at_the_turn_limit() {
+        let (mut sm, mut bm) = scenario();
+        let mut phases = 0;
+        let over = game_loop(&mut sm, &mut bm, |_| phases += 1);
+        assert_eq!(phases, 80);
+        assert_eq!((over.turn, over.reason), (5, EndReason::TurnLimit));
+        assert_eq!(over.winner, Some(Side::Allies));
+        let turns =
+            bm.events.iter().filter(|e| matches!(e, Event::GameTurn(_)));
+        assert_eq!(turns.count(), 4);
+        assert_eq!(sm.update_scenario_turn(&mut bm), Some(over));
+    }
+
+    #[test]
+    fn half_turns_are_played_by_the_first_side() {
+        let text = SCENARIO.replace("turns 5", "turns 2.5");
+        let (mut sm, mut bm) = ScenarioManager::load(&text, 11).unwrap();
+        let mut attackers = Vec::new();
+        let over =
+            game_loop(&mut sm, &mut bm, |bm| attackers.push(bm.attacker));
+        assert_eq!(attackers.len(), 40);
+        assert_eq!(attackers.last(), Some(&Side::Axis));
+        assert_eq!(over.turn, 3);
+        let text = SCENARIO.replace("turns 5", "turns 2.3");
+        let error = ScenarioManager::load(&text, 11).unwrap_err();
+        assert!(matches!(error, ScenarioError::Syntax(3, _)));
+    }
+
+    // Victory
+
+    #[test]
+    fn victories_need_every_condition() {
+        let (sm, mut bm) = scenario();
+        assert_eq!(sm.winner(&bm), Some(Side::Allies));
+        bm.eliminate(ars("2.1.1.1"));
+        assert_eq!(bm.casualty_points(Side::Axis), 2);
+        bm.locations.insert(ars("1.1.1.1"), hex("1D5"));
+        bm.update_control();
+        assert_eq!(bm.controller(&hex("1D5")), Some(Side::Axis));
+        assert_eq!(sm.winner(&bm), Some(Side::Allies));
+        bm.end_phase();
+        bm.end_phase();
+        bm.locations.insert(ars("1.1.1.0"), hex("1GG3"));
+        let west = bm.exit_map(&[ars("1.1.1.0")], Edge::West);
+        assert_eq!(west, Err(RuleBreak::A5900));
+        bm.exit_map(&[ars("1.1.1.0")], Edge::East).unwrap();
+        assert_eq!(bm.exit_points(Side::Axis, Edge::East), 1);
+        assert_eq!(sm.winner(&bm), Some(Side::Allies));
+        let report = sm.report(&bm);
+        assert_eq!(report.casualty_points[&Side::Axis], 2);
+        assert_eq!(report.exit_points[&Side::Axis], 1);
+        assert!(report.victories.iter().all(|(_, met)| !met));
+    }
+
+    #[test]
+    fn sudden_death_ends_the_battle() {
+        let (mut sm, mut bm) = scenario();
+        for unit in ["1.1.1.1", "1.1.1.0", "1.1.9.1"] {
+            bm.eliminate(ars(unit));
+        }
+        assert!(bm.casualty_points(Side::Allies) < 8);
+        squad(&mut bm, Side::Axis, "1.1.1.5", "1C3");
+        bm.eliminate(ars("1.1.1.5"));
+        squad(&mut bm, Side::Axis, "1.1.1.6", "1C3");
+        bm.eliminate(ars("1.1.1.6"));
+        assert!(bm.casualty_points(Side::Allies) >= 8);
+        let over = game_loop(&mut sm, &mut bm, |_| {});
+        let expected = (1, EndReason::SuddenDeath, Some(Side::Allies));
+        assert_eq!((over.turn, over.reason, over.winner), expected);
+        assert_eq!(sm.report(&bm).winner, Some(Side::Allies));
+    }
+
+    // Control
+
+    #[test]
+    fn setup_areas_are_controlled() {
+        let (_, bm) = scenario();
+        assert_eq!(bm.building_controller(&1), Some(Side::Allies));
+        assert_eq!(bm.controller(&hex("1C3")), Some(Side::Axis));
+        assert_eq!(bm.controller(&hex("1D6")), Some(Side::Allies));
+        let text = SCENARIO.replace("area 1D5 1D6 1E5", "area 1");
+        let (_, bm) = ScenarioManager::load(&text, 11).unwrap();
+        assert_eq!(bm.controller(&hex("1GG9")), Some(Side::Allies));
+        let text = SCENARIO.replace("area 1D5 1D6 1E5", "area 2");
+        let error = ScenarioManager::load(&text, 11).unwrap_err();
+        assert_eq!(error, ScenarioError::Setup(24, RuleBreak::E0002));
+        let text = SCENARIO.replace("area 1D5 1D6 1E5", "area 1D5 north");
+        let error = ScenarioManager::load(&text, 11).unwrap_err();
+        assert!(matches!(error, ScenarioError::Syntax(24, _)));
+    }
+
+    #[test]
+    fn units_take_control_of_hexes_and_buildings() {
+        let (_, mut bm) = scenario();
+        bm.end_phase();
+        bm.end_phase();
+        bm.minefields.clear();
+        let path = [hex("1D3"), hex("1E4"), hex("1E5")];
+        bm.move_stack(&[ars("1.1.1.1")], &path).unwrap();
+        assert_eq!(bm.controller(&hex("1E4")), Some(Side::Axis));
+        assert_eq!(bm.controller(&hex("1D6")), Some(Side::Allies));
+        let path =
+            [hex("1D3"), hex("1D4"), hex("1C5"), hex("1C6"), hex("1D6")];
+        bm.move_stack(&[ars("1.1.1.0")], &path).unwrap();
+        assert_eq!(bm.controller(&hex("1D6")), Some(Side::Axis));
+        assert_eq!(bm.building_controller(&1), None);
+        bm.eliminate(ars("2.1.1.1"));
+        bm.locations.insert(ars("1.1.1.0"), hex("1D5"));
+        bm.end_phase();
+        assert_eq!(bm.building_controller(&1), Some(Side::Axis));
+        let changed = bm.events.iter().any(|e| {
+            matches!(e, Event::BuildingControlled { building: 1, .. })
+        });
+        assert!(changed);
+    }
+
+    // Reinforcements
+
+    /// Plays the battle on until the Allied Phase::Movement.
+    fn allied_movement(sm: &mut ScenarioManager, bm: &mut BattleManager) {
+        loop {
+            bm.end_phase();
+            sm.update_scenario_turn(bm);
+            if bm.attacker == Side::Allies && bm.phase == Phase::Movement {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn reinforcements_enter_once_released() {
+        let (mut sm, mut bm) = scenario();
+        assert_eq!(sm.reinforcements[0].units.len(), 2);
+        assert_eq!(sm.reinforcements[0].hexes.len(), 10);
+        assert_eq!(bm.carriers[&5], ars("2.2.1.1"));
+        assert_eq!(bm.casualty_points(Side::Axis), 0);
+        assert!(!sm.reinforcements[1].released);
+        allied_movement(&mut sm, &mut bm);
+        assert!(sm.reinforcements[1].released);
+        let truck = ars("2.2.9.1");
+        let wrong = bm.enter_map(&[truck], hex("1GG4"));
+        assert_eq!(wrong, Err(RuleBreak::A2171));
+        let early = bm.enter_map(&[ars("2.2.1.1")], hex("1A4"));
+        assert_eq!(early, Err(RuleBreak::A2170));
+        assert_eq!(bm.enter_map(&[truck], hex("1GG5")), Ok(2));
+        assert_eq!(bm.facings[&truck], Facing(4));
+        assert!(!bm.turrets.contains_key(&truck));
+        assert_eq!(bm.controller(&hex("1GG5")), Some(Side::Allies));
+
+        let mut turns = 0;
+        while !sm.reinforcements[0].released {
+            allied_movement(&mut sm, &mut bm);
+            turns += 1;
+            assert!(turns < 5, "the reinforcements never arrived");
+        }
+        let stack = [ars("2.2.1.1"), ars("2.2.1.0")];
+        let twice = bm.enter_map(&[stack[0], stack[0]], hex("1A4"));
+        assert_eq!(twice, Err(RuleBreak::E0004));
+        bm.map.hex_mut(&hex("1A5")).unwrap().terrain = vec![Terrain::Water];
+        let water = bm.enter_map(&stack, hex("1A5"));
+        assert_eq!(water, Err(RuleBreak::A1200));
+        assert_eq!(bm.enter_map(&stack, hex("1A4")), Ok(1));
+        assert_eq!(bm.locations[&ars("2.2.1.0")], hex("1A4"));
+    }
+
+    #[test]
+    fn units_off_board_may_only_enter() {
+        let (mut sm, mut bm) = scenario();
+        allied_movement(&mut sm, &mut bm);
+        let (squad, truck) = (ars("2.2.1.1"), ars("2.2.9.1"));
+        assert_eq!(bm.embark(squad, truck), Err(RuleBreak::E0001));
+        let path = [hex("1GG5")];
+        assert_eq!(bm.move_vehicle(truck, &path), Err(RuleBreak::E0001));
+        assert_eq!(bm.move_stack(&[squad], &path), Err(RuleBreak::E0001));
+        bm.enter_map(&[truck], hex("1GG5")).unwrap();
+        assert_eq!(bm.embark(squad, truck), Err(RuleBreak::E0001));
+    }
+
+    // Saved games
+
+    /// Plays on for the given number of phases, with the Axis squad firing
+    /// at the building in each of its Phase::PrepFire.
+    fn play(sm: &mut ScenarioManager, bm: &mut BattleManager, phases: usize) {
+        for _ in 0..phases {
+            if bm.phase == Phase::PrepFire && bm.attacker == Side::Axis {
+                let group = FireGroup::new(&[ars("1.1.1.1")]).with_weapon(1);
+                let _ = bm.fire(&group, hex("1D5"));
+            }
+            bm.end_phase();
+            if sm.update_scenario_turn(bm).is_some() {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn saved_games_load_as_they_were_saved() {
+        let (mut sm, mut bm) = scenario();
+        play(&mut sm, &mut bm, 20);
+        let identity = "Lt.  Weber %x".to_string();
+        bm.leaders.get_mut(&ars("1.1.1.0")).unwrap().identity = identity;
+        let saved = save_game(&sm, &bm);
+        let (mut loaded_sm, mut loaded_bm) = load_game(&saved).unwrap();
+        assert_eq!(
+            loaded_bm.leaders[&ars("1.1.1.0")].identity,
+            "Lt.  Weber %x"
+        );
+        assert_eq!(save_game(&loaded_sm, &loaded_bm), saved);
+        play(&mut sm, &mut bm, 40);
+        play(&mut loaded_sm, &mut loaded_bm, 40);
+        assert_eq!(save_game(&sm, &bm), save_game(&loaded_sm, &loaded_bm));
+    }
+
+    #[test]
+    fn broken_saved_games_are_rejected() {
+        let (sm, bm) = scenario();
+        let saved = save_game(&sm, &bm);
+        let error = load_game("squadleader-save 2").unwrap_err();
+        assert_eq!(error, SaveError::Version(2));
+        assert_eq!(load_game("hello").unwrap_err(), SaveError::Format);
+        assert!(load_game(&saved.replace("squads", "squids")).is_err());
+        let mut stray = bm.clone();
+        stray.markers.insert(ars("3.3.3.3"), Vec::new());
+        let error = load_game(&save_game(&sm, &stray)).unwrap_err();
+        assert_eq!(error, SaveError::Corrupt("markers"));
+        let mut stray = bm.clone();
+        stray.locations.insert(ars("1.1.1.1"), hex("2C3"));
+        let error = load_game(&save_game(&sm, &stray)).unwrap_err();
+        assert_eq!(error, SaveError::Corrupt("locations"));
+        let mut stray = bm.clone();
+        stray.carriers.insert(9, ars("1.1.1.1"));
+        let error = load_game(&save_game(&sm, &stray)).unwrap_err();
+        assert_eq!(error, SaveError::Corrupt("carriers"));
+        let mut stray = sm.clone();
+        stray.reinforcements[0].units.push(ars("3.3.3.3"));
+        let error = load_game(&save_game(&stray, &bm)).unwrap_err();
+        assert_eq!(error, SaveError::Corrupt("scenario"));
+    }
+
+    // Game records
+
+    /// Orders the Axis squad to fire at the building, and the Allied truck
+    /// to enter the map, and records the battle for the given number of
+    /// phases.
+    fn record_game(
+        phases: usize,
+    ) -> (ScenarioManager, BattleManager, GameRecord) {
+        let (mut sm, mut bm) = scenario();
+        let mut record = GameRecord::new(&sm, &bm, 11);
+        for _ in 0..phases {
+            let order = match (bm.phase, bm.attacker) {
+                (Phase::PrepFire, Side::Axis) => Some(Order::Fire {
+                    group: FireGroup::new(&[ars("1.1.1.1")]).with_weapon(1),
+                    target: hex("1D5"),
+                }),
+                (Phase::Movement, Side::Allies) => Some(Order::EnterMap {
+                    units: vec![ars("2.2.9.1")],
+                    hex: hex("1GG5"),
+                }),
+                _ => None,
+            };
+            if let Some(order) = order {
+                let _ = record.order(&mut bm, &order);
+            }
+            if record.end_phase(&mut sm, &mut bm).is_some() {
+                break;
+            }
+        }
+        (sm, bm, record)
+    }
+
+    #[test]
+    fn dice_count_their_rolls() {
+        let mut dice = Dice::new(11);
+        let earlier = dice.clone();
+        let rolls = vec![dice.roll(), dice.roll(), dice.roll()];
+        assert_eq!(dice.rolls, 3);
+        assert_eq!(dice.rolls_since(&earlier), rolls);
+        assert_eq!(earlier.rolls_since(&dice), Vec::new());
+    }
+
+    #[test]
+    fn records_replay_the_same_battle() {
+        let (sm, bm, record) = record_game(40);
+        let record = record.to_string();
+        assert!(
+            record.starts_with("scenario The Guards Counterattack\nseed 11\n")
+        );
+        assert!(record.contains("\nPF 1.1.1.1 +1 @1C3 -> 1D5 : "));
+        assert!(record.contains("\nMV 2.2.9.1 @off enter 1GG5\n"));
+        let (replayed_sm, replayed_bm) = replay(SCENARIO, &record).unwrap();
+        assert_eq!(save_game(&sm, &bm), save_game(&replayed_sm, &replayed_bm));
+    }
+
+    #[test]
+    fn replays_stop_where_the_record_differs() {
+        let (_, _, record) = record_game(40);
+        let record = record.to_string();
+        let roll = record.find(" : ").unwrap() + 3;
+        let mut tampered = record.clone();
+        let die = if &record[roll..roll + 1] == "1" { "2" } else { "1" };
+        tampered.replace_range(roll..roll + 1, die);
+        let error = replay(SCENARIO, &tampered).unwrap_err();
+        assert!(matches!(error, ReplayError::Mismatch(5, _)));
+        let moved = record.replace("@1C3", "@1C2");
+        let error = replay(SCENARIO, &moved).unwrap_err();
+        assert!(matches!(error, ReplayError::Mismatch(5, _)));
+        let illegal = "1 Axis MV\nMV 1.1.1.1 @1C3 -> 1E9";
+        let illegal = record.replacen("1 Axis MV", illegal, 1);
+        let error = replay(SCENARIO, &illegal).unwrap_err();
+        assert!(matches!(error, ReplayError::Order(7, _)));
+        let other = format!(
+            "# A comment\n{}",
+            record.replacen("The Guards Counterattack", "Hill 621", 1,)
+        );
+        let error = replay(SCENARIO, &other).unwrap_err();
+        let reason = "the record is of `Hill 621`".to_string();
+        assert_eq!(error, ReplayError::Mismatch(2, reason));
+    }
+
+    // Undo
+
+    fn next_phase(sm: &mut ScenarioManager, bm: &mut BattleManager) {
+        bm.end_phase();
+        sm.update_scenario_turn(bm);
+    }
+
+    fn squad_fires() -> Order {
+        Order::Fire {
+            group: FireGroup::new(&[ars("1.1.1.1")]).with_weapon(1),
+            target: hex("1D5"),
+        }
+    }
+
+    #[test]
+    fn undo_is_turned_off_by_default() {
+        let (mut sm, mut bm) = scenario();
+        assert_eq!(bm.undo(Some(&mut sm)), Err(UndoError::Forbidden));
+        bm.allow_undo(DicePolicy::Keep);
+        assert_eq!(bm.undo(Some(&mut sm)), Err(UndoError::Empty));
+        assert_eq!(bm.redo(Some(&mut sm)), Err(UndoError::Empty));
+        bm.forbid_undo();
+        assert_eq!(bm.undo(None), Err(UndoError::Forbidden));
+    }
+
+    #[test]
+    fn undo_and_redo_keeping_the_dice() {
+        let (mut sm, mut bm) = scenario();
+        bm.allow_undo(DicePolicy::Keep);
+        next_phase(&mut sm, &mut bm);
+        let before = save_game(&sm, &bm);
+        bm.order(&squad_fires()).unwrap();
+        let after = save_game(&sm, &bm);
+        bm.undo(Some(&mut sm)).unwrap();
+        assert_eq!(save_game(&sm, &bm), before);
+        bm.redo(Some(&mut sm)).unwrap();
+        assert_eq!(save_game(&sm, &bm), after);
+        bm.undo(Some(&mut sm)).unwrap();
+        bm.order(&squad_fires()).unwrap();
+        assert_eq!(save_game(&sm, &bm), after);
+        assert_eq!(bm.redo(Some(&mut sm)), Err(UndoError::Empty));
+    }
+
+    #[test]
+    fn undo_and_redo_rerolling_the_dice() {
+        let (mut sm, mut bm) = scenario();
+        bm.allow_undo(DicePolicy::Reroll);
+        next_phase(&mut sm, &mut bm);
+        let before = bm.clone();
+        bm.order(&squad_fires()).unwrap();
+        let after = save_game(&sm, &bm);
+        let rolled = bm.dice.clone();
+        bm.undo(Some(&mut sm)).unwrap();
+        assert_eq!(bm.dice.rolls, rolled.rolls);
+        let mut undone = bm.clone();
+        undone.dice = before.dice.clone();
+        assert_eq!(save_game(&sm, &undone), save_game(&sm, &before));
+        bm.redo(Some(&mut sm)).unwrap();
+        assert_eq!(save_game(&sm, &bm), after);
+        bm.undo(Some(&mut sm)).unwrap();
+        bm.order(&squad_fires()).unwrap();
+        assert_ne!(save_game(&sm, &bm), after);
+    }
+
+    #[test]
+    fn undo_takes_back_the_scenario() {
+        let (mut sm, mut bm) = scenario();
+        bm.allow_undo(DicePolicy::Keep);
+        next_phase(&mut sm, &mut bm);
+        let ended = save_game(&sm, &bm);
+        assert_eq!(bm.undo(None), Err(UndoError::Scenario));
+        assert_eq!(save_game(&sm, &bm), ended);
+        let undone = bm.rewind(Rewind::Phase(Phase::Rally), None);
+        assert_eq!(undone, Err(UndoError::Scenario));
+        bm.undo(Some(&mut sm)).unwrap();
+        assert_eq!((sm.phases, bm.phase), (0, Phase::Rally));
+        assert_eq!(bm.redo(None), Err(UndoError::Scenario));
+        bm.redo(Some(&mut sm)).unwrap();
+        assert_eq!(save_game(&sm, &bm), ended);
+    }
+
+    #[test]
+    fn snapshots_leave_out_the_events() {
+        let (mut sm, mut bm) = scenario();
+        bm.allow_undo(DicePolicy::Keep);
+        for _ in 0..20 {
+            next_phase(&mut sm, &mut bm);
+            let _ = bm.order(&squad_fires());
+        }
+        let history = bm.history.as_ref().unwrap();
+        assert!(history.undo.iter().all(|s| s.battle.events.is_empty()));
+        let events = bm.events.clone();
+        for _ in 0..10 {
+            bm.undo(Some(&mut sm)).unwrap();
+        }
+        assert!(bm.events.len() < events.len());
+        assert_eq!(bm.events[..], events[..bm.events.len()]);
+        for _ in 0..10 {
+            bm.redo(Some(&mut sm)).unwrap();
+        }
+        assert_eq!(bm.events, events);
+    }
+
+    #[test]
+    fn rewinds_go_back_to_the_start_of_a_phase_or_turn() {
+        let (mut sm, mut bm) = scenario();
+        bm.allow_undo(DicePolicy::Keep);
+        next_phase(&mut sm, &mut bm);
+        bm.order(&squad_fires()).unwrap();
+        for _ in 0..20 {
+            next_phase(&mut sm, &mut bm);
+        }
+        assert_eq!(sm.turn, 2);
+        let (phase, now) = (bm.phase, save_game(&sm, &bm));
+        bm.rewind(Rewind::GameTurn(1), Some(&mut sm)).unwrap();
+        assert_eq!((sm.turn, sm.phases, bm.phase), (1, 0, Phase::Rally));
+        for _ in 0..22 {
+            bm.redo(Some(&mut sm)).unwrap();
+        }
+        assert_eq!(bm.phase, phase);
+        assert_eq!(save_game(&sm, &bm), now);
+        bm.rewind(Rewind::Phase(Phase::PrepFire), Some(&mut sm)).unwrap();
+        assert_eq!((sm.turn, bm.phase), (2, Phase::PrepFire));
+        let ahead = bm.rewind(Rewind::GameTurn(3), Some(&mut sm));
+        assert_eq!(ahead, Err(UndoError::Unreached));
+        bm.rewind(Rewind::GameTurn(2), Some(&mut sm)).unwrap();
+        assert_eq!(
+            (sm.turn, bm.phase, bm.attacker),
+            (2, Phase::Rally, Side::Axis)
+        );
+    }
+
+    #[test]
+    fn records_take_back_their_lines() {
+        let (mut sm, mut bm) = scenario();
+        bm.allow_undo(DicePolicy::Keep);
+        let mut record = GameRecord::new(&sm, &bm, 11);
+        record.end_phase(&mut sm, &mut bm);
+        record.order(&mut bm, &squad_fires()).unwrap();
+        let fired = record.to_string();
+        record.end_phase(&mut sm, &mut bm);
+        record.undo(&mut sm, &mut bm).unwrap();
+        record.undo(&mut sm, &mut bm).unwrap();
+        assert!(!record.to_string().contains("\nPF 1.1.1.1"));
+        record.redo(&mut sm, &mut bm).unwrap();
+        assert_eq!(record.to_string(), fired);
+        record.undo(&mut sm, &mut bm).unwrap();
+        record.order(&mut bm, &squad_fires()).unwrap();
+        assert_eq!(record.to_string(), fired);
+        for _ in 0..20 {
+            record.end_phase(&mut sm, &mut bm);
+        }
+        record.rewind(Rewind::GameTurn(2), &mut sm, &mut bm).unwrap();
+        record.end_phase(&mut sm, &mut bm);
+        let (replayed_sm, replayed_bm) =
+            replay(SCENARIO, &record.to_string()).unwrap();
+        assert_eq!(save_game(&sm, &bm), save_game(&replayed_sm, &replayed_bm));
+
+        let (mut sm, mut bm) = scenario();
+        bm.allow_undo(DicePolicy::Reroll);
+        let mut record = GameRecord::new(&sm, &bm, 11);
+        record.end_phase(&mut sm, &mut bm);
+        let undone = record.undo(&mut sm, &mut bm);
+        assert_eq!(undone, Err(UndoError::Recorded));
+    }
+
+    // Rallying
+
+    #[test]
+    fn broken_squads_rally_with_a_leader() {
+        let mut bm = battle(Phase::Rally);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Axis, "1.1.1.2", "1C5");
+        assert_eq!(bm.rally(ars("1.1.1.1")), Err(RuleBreak::E4100));
+        bm.conditions.insert(ars("1.1.1.1"), Condition::Broken);
+        bm.conditions.insert(ars("1.1.1.2"), Condition::Broken);
+        let leader = Leader::new(ars("1.1.1.0"), "Lt. Weber", -1, 8);
+        bm.deploy_leader(Side::Axis, leader, hex("1C3")).unwrap();
+        assert_eq!(bm.rally(ars("1.1.1.2")), Err(RuleBreak::E4110));
+        let rallied = bm.rally(ars("1.1.1.1")).unwrap();
+        assert_eq!(bm.broken(&ars("1.1.1.1")), !rallied);
+        assert!(matches!(bm.events.last(), Some(Event::Rally { .. })));
+        let again = if rallied { RuleBreak::E4100 } else { RuleBreak::E4120 };
+        assert_eq!(bm.rally(ars("1.1.1.1")), Err(again));
+        bm.end_phase();
+        assert!(!bm.has_marker(&ars("1.1.1.1"), Marker::Rallied));
+        assert_eq!(bm.rally(ars("1.1.1.2")), Err(RuleBreak::E4100));
+    }
+
+    // Legal orders
+
+    /// Checks that every legal order can be given, in a copy of the battle.
+    /// Returns the notation of each order.
+    fn check_legal(bm: &BattleManager) -> Vec<String> {
+        let orders = legal_actions(bm);
+        for order in &orders {
+            let mut trial = bm.clone();
+            assert_eq!(trial.order(order), Ok(()), "{}", order.notation(bm));
+            let (units, _, _) = order.parts(bm);
+            let acting = units.iter().all(|ars| bm.acting(bm.sides[ars]));
+            assert!(acting, "{} out of turn", order.notation(bm));
+        }
+        orders.iter().map(|order| order.notation(bm)).collect()
+    }
+
+    #[test]
+    fn legal_orders_in_every_phase() {
+        let (mut sm, mut bm) = scenario();
+        bm.conditions.insert(ars("1.1.1.1"), Condition::Broken);
+        let rally = Order::Rally(ars("1.1.1.1")).notation(&bm);
+        assert!(check_legal(&bm).contains(&rally));
+        bm.conditions.insert(ars("1.1.1.1"), Condition::Composed);
+        next_phase(&mut sm, &mut bm);
+        let fire = squad_fires().notation(&bm);
+        let orders = check_legal(&bm);
+        assert!(orders.contains(&fire));
+        let moving = |order: &Order| matches!(order, Order::Move { .. });
+        assert!(!legal_actions(&bm).iter().any(moving));
+        next_phase(&mut sm, &mut bm);
+        let one_hex = Order::Move {
+            units: vec![ars("1.1.1.1")],
+            path: vec![hex("1C2")],
+        };
+        let orders = check_legal(&bm);
+        assert!(orders.contains(&one_hex.notation(&bm)));
+        assert!(!orders.contains(&squad_fires().notation(&bm)));
+        for _ in 0..5 {
+            next_phase(&mut sm, &mut bm);
+            check_legal(&bm);
+        }
+        assert_eq!(bm.phase, Phase::CloseCombat);
+        let firing = |order: &Order| matches!(order, Order::Fire { .. });
+        let orders = legal_actions(&bm);
+        assert!(!orders.iter().any(|order| firing(order) || moving(order)));
+    }
+
+    #[test]
+    fn legal_orders_leave_the_battle_alone() {
+        let (mut sm, mut bm) = scenario();
+        bm.allow_undo(DicePolicy::Keep);
+        next_phase(&mut sm, &mut bm);
+        next_phase(&mut sm, &mut bm);
+        let before = save_game(&sm, &bm);
+        assert!(!legal_actions(&bm).is_empty());
+        assert_eq!(save_game(&sm, &bm), before);
+        assert_eq!(bm.trial().events, Vec::new());
+        assert!(bm.trial().history.is_none());
+    }
+
+    // Commanders
+
+    #[test]
+    fn commanders_fire_at_good_odds() {
+        let mut bm = battle(Phase::PrepFire);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Axis, "1.1.1.2", "1C3");
+        squad(&mut bm, Side::Allies, "2.1.1.1", "1C4");
+        squad(&mut bm, Side::Allies, "2.1.1.2", "1J9");
+        let near = FireGroup::new(&[ars("1.1.1.1"), ars("1.1.1.2")]);
+        let far = FireGroup::new(&[ars("1.1.1.1")]);
+        assert!(bm.fire_odds(&near, &hex("1C4")) >= PREP_FIRE_ODDS);
+        assert!(bm.fire_odds(&far, &hex("1J9")) < PREP_FIRE_ODDS);
+        let logged = bm.events.len();
+        HeuristicCommander::new(Side::Allies).command(&mut bm);
+        assert_eq!(bm.events.len(), logged);
+        HeuristicCommander::new(Side::Axis).command(&mut bm);
+        assert!(bm.has_marker(&ars("1.1.1.1"), Marker::Fired));
+        let fired =
+            bm.events.iter().filter(|e| matches!(e, Event::Fired { .. }));
+        assert!(fired.count() >= 1);
+    }
+
+    #[test]
+    fn commanders_follow_standing_orders() {
+        let mut bm = battle(Phase::Movement);
+        squad(&mut bm, Side::Axis, "1.1.1.1", "1C3");
+        squad(&mut bm, Side::Axis, "1.1.1.2", "1E3");
+        let mut axis =
+            HeuristicCommander::new(Side::Axis).with_objective(hex("1C9"));
+        axis.standing_order(ars("1.1.1.1"), StandingOrder::Hold);
+        axis.standing_order(ars("1.1.1.2"), StandingOrder::MoveTo(hex("1E4")));
+        axis.command(&mut bm);
+        assert_eq!(bm.locations[&ars("1.1.1.1")], hex("1C3"));
+        assert_eq!(bm.locations[&ars("1.1.1.2")], hex("1E4"));
+        while bm.phase != Phase::Movement || bm.attacker != Side::Axis {
+            bm.end_phase();
+            axis.command(&mut bm);
+        }
+        axis.command(&mut bm);
+        assert_eq!(bm.locations[&ars("1.1.1.1")], hex("1C3"));
+        let left =
+            bm.map.distance(&bm.locations[&ars("1.1.1.2")], &hex("1C9"));
+        assert!(left < Some(5));
+        assert!(!axis.standing_orders.contains_key(&ars("1.1.1.2")));
+    }
+
+    #[test]
+    fn commanders_play_out_a_scenario() {
+        let (mut sm, mut bm) = scenario();
+        let mut axis = HeuristicCommander::for_scenario(Side::Axis, &sm, &bm);
+        let mut allies =
+            HeuristicCommander::for_scenario(Side::Allies, &sm, &bm);
+        assert!(axis.objectives.contains(&hex("1D5")));
+        assert_eq!(axis.exits, vec![Edge::East]);
+        let over = game_loop(&mut sm, &mut bm, |bm| {
+            axis.command(bm);
+            allies.command(bm);
+        });
+        assert_eq!(Some(over), sm.game_over);
+        let moved = bm.events.iter().any(|e| matches!(e, Event::Moved { .. }));
+        assert!(moved);
+    }
+}